@@ -1,7 +1,9 @@
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
-use rand::Rng;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
 
-use radixt::RadixMap;
+use radixt::{RadixMap, SlabAllocator};
 
 const SIZES: &[usize] = &[10000, 100000, 1000000];
 const KEY_LENS: &[usize] = &[8, 32, 128];
@@ -17,6 +19,103 @@ fn generate_kv<R: Rng>(size: usize, rng: &mut R) -> Vec<(Vec<u8>, u64)> {
         .collect()
 }
 
+/// Keys sharing long prefixes, like URLs or file paths, with lengths drawn
+/// from `len_range`.
+fn generate_keys<R: Rng>(
+    size: usize,
+    prefix: &str,
+    segments: &[&str],
+    len_range: std::ops::Range<usize>,
+    rng: &mut R,
+) -> Vec<Vec<u8>> {
+    (0..size)
+        .map(|_| {
+            let len = rng.gen_range(len_range.clone());
+            let mut key = prefix.as_bytes().to_vec();
+            while key.len() < len {
+                key.extend(segments[rng.gen_range(0..segments.len())].as_bytes());
+                key.push(b'/');
+            }
+            key.extend(rng.gen::<u32>().to_string().as_bytes());
+            key
+        })
+        .collect()
+}
+
+fn bench_get_key_distributions(c: &mut Criterion) {
+    // Seeded so that runs with and without the `scalar` feature are comparable.
+    let mut rng = StdRng::seed_from_u64(0);
+    let segments = &["api", "v1", "users", "src", "lib", "images", "2023", "docs"];
+    let distributions = [
+        ("short", generate_keys(100000, "", segments, 0..8, &mut rng)),
+        (
+            "urls",
+            generate_keys(
+                100000,
+                "https://www.example.com/",
+                segments,
+                30..120,
+                &mut rng,
+            ),
+        ),
+        (
+            "paths",
+            generate_keys(100000, "/home/user/projects/", segments, 60..250, &mut rng),
+        ),
+    ];
+
+    for (name, keys) in distributions.iter() {
+        let mut map = RadixMap::new();
+        for (i, key) in keys.iter().enumerate() {
+            map.insert(key, i);
+        }
+
+        c.bench_function(&format!("get_keys={}", name), |bench| {
+            bench.iter(|| {
+                let key = &keys[rng.gen::<usize>() % keys.len()];
+                black_box(map.get(key))
+            })
+        });
+    }
+}
+
+/// All keys of `depth` bytes drawn from `fanout` distinct bytes, so every
+/// internal node has `fanout` children.
+fn generate_fanout_keys(fanout: usize, depth: usize) -> Vec<Vec<u8>> {
+    let bytes: Vec<u8> = (0..fanout).map(|i| (i * 256 / fanout) as u8).collect();
+    let mut keys = vec![vec![]];
+    for _ in 0..depth {
+        keys = keys
+            .iter()
+            .flat_map(|key| {
+                bytes
+                    .iter()
+                    .map(move |&byte| [key.as_slice(), &[byte]].concat())
+            })
+            .collect();
+    }
+    keys
+}
+
+fn bench_get_fanout(c: &mut Criterion) {
+    let mut rng = StdRng::seed_from_u64(0);
+    // Around 65k keys each, with fanouts below and above the dense index
+    // threshold.
+    for (fanout, depth) in [(4, 8), (16, 4), (48, 3), (256, 2)] {
+        let mut keys = generate_fanout_keys(fanout, depth);
+        let mut map = RadixMap::new();
+        for (i, key) in keys.iter().enumerate() {
+            map.insert(key, i);
+        }
+        keys.shuffle(&mut rng);
+
+        let mut keys_it = keys.iter().cycle();
+        c.bench_function(&format!("get_fanout={}", fanout), |bench| {
+            bench.iter(|| black_box(map.get(keys_it.next().unwrap())))
+        });
+    }
+}
+
 fn bench_insert(c: &mut Criterion) {
     let mut rng = rand::thread_rng();
 
@@ -58,6 +157,30 @@ fn bench_get(c: &mut Criterion) {
     }
 }
 
+fn bench_get_slab(c: &mut Criterion) {
+    let mut rng = rand::thread_rng();
+    for &size in SIZES {
+        let kv = generate_kv(size, &mut rng);
+
+        for &key_len in KEY_LENS {
+            let mut map = RadixMap::new_in(SlabAllocator::new());
+            for (k, v) in kv.iter() {
+                map.insert(&k[..key_len], *v);
+            }
+
+            c.bench_function(
+                &format!("get_slab_n={},key_len={}", size, key_len),
+                |bench| {
+                    bench.iter(|| {
+                        let (key, _) = &kv[rng.gen::<usize>() % kv.len()];
+                        black_box(map.get(&key[..key_len]))
+                    })
+                },
+            );
+        }
+    }
+}
+
 fn bench_remove(c: &mut Criterion) {
     let mut rng = rand::thread_rng();
     for &size in SIZES {
@@ -82,6 +205,6 @@ fn bench_remove(c: &mut Criterion) {
 criterion_group!(
     name = benches;
     config = Criterion::default().significance_level(0.01).sample_size(1000);
-    targets = bench_get, bench_insert, bench_remove
+    targets = bench_get, bench_get_slab, bench_get_key_distributions, bench_get_fanout, bench_insert, bench_remove
 );
 criterion_main!(benches);
@@ -0,0 +1,200 @@
+use alloc::alloc::{alloc, dealloc, realloc, Layout};
+use core::cell::Cell;
+use core::ptr::{self, NonNull};
+
+/// An allocator used for node storage.
+///
+/// Maps and sets are generic over the allocator so that nodes can be placed
+/// in an arena or counted per map. The allocator is owned by the map and only
+/// accessed through a shared reference. Implementing the trait for `&A` lets
+/// several maps share a single allocator.
+///
+/// # Safety
+///
+/// Blocks returned by [`Self::allocate()`] and [`Self::reallocate()`] must be
+/// valid for reads and writes of `layout.size()` bytes, aligned to
+/// `layout.align()` and must not overlap with other allocated blocks. They must
+/// stay valid until they are deallocated, even if the allocator is moved.
+pub unsafe trait NodeAllocator {
+    /// Allocates a block of memory described by `layout`. Returns `None` if
+    /// the allocation failed.
+    fn allocate(&self, layout: Layout) -> Option<NonNull<u8>>;
+
+    /// Deallocates a block of memory.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must denote a block currently allocated by this allocator with
+    /// `layout`.
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout);
+
+    /// Grows or shrinks a block of memory. The contents of the block are
+    /// preserved up to the smaller of the old and new sizes. Returns `None`
+    /// and leaves the block untouched if the allocation failed.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must denote a block currently allocated by this allocator with
+    /// `old_layout`.
+    unsafe fn reallocate(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Option<NonNull<u8>> {
+        let new_ptr = self.allocate(new_layout)?;
+        ptr::copy_nonoverlapping(
+            ptr.as_ptr(),
+            new_ptr.as_ptr(),
+            old_layout.size().min(new_layout.size()),
+        );
+        self.deallocate(ptr, old_layout);
+        Some(new_ptr)
+    }
+
+    /// Releases all blocks allocated by this allocator at once. Returns
+    /// `false` if the allocator does not support it, in which case nothing
+    /// is released.
+    fn reset(&mut self) -> bool {
+        false
+    }
+}
+
+unsafe impl<A: NodeAllocator + ?Sized> NodeAllocator for &A {
+    #[inline(always)]
+    fn allocate(&self, layout: Layout) -> Option<NonNull<u8>> {
+        (**self).allocate(layout)
+    }
+
+    #[inline(always)]
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        (**self).deallocate(ptr, layout)
+    }
+
+    #[inline(always)]
+    unsafe fn reallocate(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Option<NonNull<u8>> {
+        (**self).reallocate(ptr, old_layout, new_layout)
+    }
+}
+
+/// The global memory allocator. This is the default allocator of all maps
+/// and sets.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Global;
+
+unsafe impl NodeAllocator for Global {
+    #[inline(always)]
+    fn allocate(&self, layout: Layout) -> Option<NonNull<u8>> {
+        NonNull::new(unsafe { alloc(layout) })
+    }
+
+    #[inline(always)]
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        dealloc(ptr.as_ptr(), layout)
+    }
+
+    #[inline(always)]
+    unsafe fn reallocate(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Option<NonNull<u8>> {
+        if old_layout.align() != new_layout.align() {
+            // `realloc` keeps the alignment of the old layout.
+            let new_ptr = self.allocate(new_layout)?;
+            ptr::copy_nonoverlapping(
+                ptr.as_ptr(),
+                new_ptr.as_ptr(),
+                old_layout.size().min(new_layout.size()),
+            );
+            self.deallocate(ptr, old_layout);
+            return Some(new_ptr);
+        }
+        NonNull::new(realloc(ptr.as_ptr(), old_layout, new_layout.size()))
+    }
+}
+
+/// Counts the bytes a map has allocated and fails allocations that would
+/// exceed its memory limit. The count is written back to the map when the
+/// budget is dropped.
+pub(crate) struct Budget<'a, A: NodeAllocator> {
+    alloc: &'a A,
+    used: Cell<usize>,
+    limit: usize,
+    exceeded: Cell<bool>,
+    total: &'a mut usize,
+}
+
+impl<'a, A: NodeAllocator> Budget<'a, A> {
+    /// Creates a budget starting at `used` bytes. No limit is enforced if
+    /// `limit` is `None`.
+    pub(crate) fn new(alloc: &'a A, used: &'a mut usize, limit: Option<usize>) -> Self {
+        Budget {
+            alloc,
+            used: Cell::new(*used),
+            limit: limit.unwrap_or(usize::MAX),
+            exceeded: Cell::new(false),
+            total: used,
+        }
+    }
+
+    /// Returns `true` if an allocation failed because of the limit.
+    pub(crate) fn exceeded(&self) -> bool {
+        self.exceeded.get()
+    }
+
+    fn reserve(&self, size: usize) -> bool {
+        if self.used.get().saturating_add(size) > self.limit {
+            self.exceeded.set(true);
+            return false;
+        }
+        true
+    }
+}
+
+unsafe impl<A: NodeAllocator> NodeAllocator for Budget<'_, A> {
+    #[inline(always)]
+    fn allocate(&self, layout: Layout) -> Option<NonNull<u8>> {
+        if !self.reserve(layout.size()) {
+            return None;
+        }
+        let ptr = self.alloc.allocate(layout)?;
+        self.used.set(self.used.get() + layout.size());
+        Some(ptr)
+    }
+
+    #[inline(always)]
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        self.alloc.deallocate(ptr, layout);
+        self.used.set(self.used.get() - layout.size());
+    }
+
+    #[inline(always)]
+    unsafe fn reallocate(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Option<NonNull<u8>> {
+        let growth = new_layout.size().saturating_sub(old_layout.size());
+        if !self.reserve(growth) {
+            return None;
+        }
+        let ptr = self.alloc.reallocate(ptr, old_layout, new_layout)?;
+        self.used
+            .set(self.used.get() - old_layout.size() + new_layout.size());
+        Some(ptr)
+    }
+}
+
+impl<A: NodeAllocator> Drop for Budget<'_, A> {
+    fn drop(&mut self) {
+        *self.total = self.used.get();
+    }
+}
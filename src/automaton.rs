@@ -0,0 +1,413 @@
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::node::Node;
+
+/// A deterministic automaton over key bytes that drives a search.
+///
+/// The search feeds the bytes of every key to the automaton starting from
+/// [`Automaton::start()`] and yields the keys whose final state
+/// [`Automaton::is_match()`]. Subtrees are skipped as soon as
+/// [`Automaton::can_match()`] returns `false` for the state of their prefix.
+pub trait Automaton {
+    type State: Clone;
+
+    /// Returns the state before any byte was fed.
+    fn start(&self) -> Self::State;
+
+    /// Returns the state after feeding `byte` in `state`.
+    fn accept(&self, state: &Self::State, byte: u8) -> Self::State;
+
+    /// Returns `true` if the bytes fed so far form a match.
+    fn is_match(&self, state: &Self::State) -> bool;
+
+    /// Returns `false` if no sequence of bytes fed from `state` can lead to a
+    /// match. The default never prunes.
+    fn can_match(&self, _state: &Self::State) -> bool {
+        true
+    }
+
+    /// Matches keys that match both `self` and `other`.
+    fn intersection<B: Automaton>(self, other: B) -> Intersection<Self, B>
+    where
+        Self: Sized,
+    {
+        Intersection(self, other)
+    }
+
+    /// Matches keys that match `self`, `other` or both.
+    fn union<B: Automaton>(self, other: B) -> Union<Self, B>
+    where
+        Self: Sized,
+    {
+        Union(self, other)
+    }
+
+    /// Matches keys that don't match `self`.
+    fn complement(self) -> Complement<Self>
+    where
+        Self: Sized,
+    {
+        Complement(self)
+    }
+}
+
+impl<A: Automaton + ?Sized> Automaton for &A {
+    type State = A::State;
+
+    #[inline]
+    fn start(&self) -> Self::State {
+        (**self).start()
+    }
+
+    #[inline]
+    fn accept(&self, state: &Self::State, byte: u8) -> Self::State {
+        (**self).accept(state, byte)
+    }
+
+    #[inline]
+    fn is_match(&self, state: &Self::State) -> bool {
+        (**self).is_match(state)
+    }
+
+    #[inline]
+    fn can_match(&self, state: &Self::State) -> bool {
+        (**self).can_match(state)
+    }
+}
+
+/// Matches every key that starts with the given bytes.
+#[derive(Debug, Clone, Copy)]
+pub struct Prefix<'a>(&'a [u8]);
+
+impl<'a> Prefix<'a> {
+    pub fn new<K: AsRef<[u8]> + ?Sized>(prefix: &'a K) -> Self {
+        Prefix(prefix.as_ref())
+    }
+}
+
+impl Automaton for Prefix<'_> {
+    /// Number of bytes of the prefix matched so far, `None` after a mismatch.
+    type State = Option<usize>;
+
+    #[inline]
+    fn start(&self) -> Self::State {
+        Some(0)
+    }
+
+    #[inline]
+    fn accept(&self, state: &Self::State, byte: u8) -> Self::State {
+        match *state {
+            Some(len) if len == self.0.len() => Some(len),
+            Some(len) if self.0[len] == byte => Some(len + 1),
+            _ => None,
+        }
+    }
+
+    #[inline]
+    fn is_match(&self, state: &Self::State) -> bool {
+        *state == Some(self.0.len())
+    }
+
+    #[inline]
+    fn can_match(&self, state: &Self::State) -> bool {
+        state.is_some()
+    }
+}
+
+/// Matches exactly the given bytes.
+#[derive(Debug, Clone, Copy)]
+pub struct Str<'a>(&'a [u8]);
+
+impl<'a> Str<'a> {
+    pub fn new<K: AsRef<[u8]> + ?Sized>(key: &'a K) -> Self {
+        Str(key.as_ref())
+    }
+}
+
+impl Automaton for Str<'_> {
+    /// Number of bytes of the key matched so far, `None` after a mismatch.
+    type State = Option<usize>;
+
+    #[inline]
+    fn start(&self) -> Self::State {
+        Some(0)
+    }
+
+    #[inline]
+    fn accept(&self, state: &Self::State, byte: u8) -> Self::State {
+        match *state {
+            Some(len) if self.0.get(len) == Some(&byte) => Some(len + 1),
+            _ => None,
+        }
+    }
+
+    #[inline]
+    fn is_match(&self, state: &Self::State) -> bool {
+        *state == Some(self.0.len())
+    }
+
+    #[inline]
+    fn can_match(&self, state: &Self::State) -> bool {
+        state.is_some()
+    }
+}
+
+/// Matches keys that match either automaton. Built by [`Automaton::union()`].
+#[derive(Debug, Clone, Copy)]
+pub struct Union<A, B>(A, B);
+
+impl<A: Automaton, B: Automaton> Automaton for Union<A, B> {
+    type State = (A::State, B::State);
+
+    #[inline]
+    fn start(&self) -> Self::State {
+        (self.0.start(), self.1.start())
+    }
+
+    #[inline]
+    fn accept(&self, state: &Self::State, byte: u8) -> Self::State {
+        (self.0.accept(&state.0, byte), self.1.accept(&state.1, byte))
+    }
+
+    #[inline]
+    fn is_match(&self, state: &Self::State) -> bool {
+        self.0.is_match(&state.0) || self.1.is_match(&state.1)
+    }
+
+    #[inline]
+    fn can_match(&self, state: &Self::State) -> bool {
+        self.0.can_match(&state.0) || self.1.can_match(&state.1)
+    }
+}
+
+/// Matches keys that match both automata. Built by
+/// [`Automaton::intersection()`].
+#[derive(Debug, Clone, Copy)]
+pub struct Intersection<A, B>(A, B);
+
+impl<A: Automaton, B: Automaton> Automaton for Intersection<A, B> {
+    type State = (A::State, B::State);
+
+    #[inline]
+    fn start(&self) -> Self::State {
+        (self.0.start(), self.1.start())
+    }
+
+    #[inline]
+    fn accept(&self, state: &Self::State, byte: u8) -> Self::State {
+        (self.0.accept(&state.0, byte), self.1.accept(&state.1, byte))
+    }
+
+    #[inline]
+    fn is_match(&self, state: &Self::State) -> bool {
+        self.0.is_match(&state.0) && self.1.is_match(&state.1)
+    }
+
+    #[inline]
+    fn can_match(&self, state: &Self::State) -> bool {
+        self.0.can_match(&state.0) && self.1.can_match(&state.1)
+    }
+}
+
+/// Matches keys that don't match the automaton. Built by
+/// [`Automaton::complement()`].
+///
+/// The inner automaton can't tell whether every key of a subtree matches, so
+/// the search never prunes.
+#[derive(Debug, Clone, Copy)]
+pub struct Complement<A>(A);
+
+impl<A: Automaton> Automaton for Complement<A> {
+    type State = A::State;
+
+    #[inline]
+    fn start(&self) -> Self::State {
+        self.0.start()
+    }
+
+    #[inline]
+    fn accept(&self, state: &Self::State, byte: u8) -> Self::State {
+        self.0.accept(state, byte)
+    }
+
+    #[inline]
+    fn is_match(&self, state: &Self::State) -> bool {
+        !self.0.is_match(state)
+    }
+}
+
+/// An iterator over the entries whose key matches an automaton, sorted by
+/// key.
+pub struct Search<'a, T, A: Automaton, U = ()> {
+    aut: A,
+    /// Nodes to visit with the length of the prefix before them and the
+    /// state after feeding it.
+    stack: Vec<(usize, A::State, &'a Node<T, U>)>,
+    prefix: Vec<u8>,
+}
+
+impl<'a, T, A: Automaton, U> Search<'a, T, A, U> {
+    pub(crate) fn new(root: &'a Node<T, U>, aut: A) -> Self {
+        let start = aut.start();
+        Search {
+            stack: vec![(0, start, root)],
+            aut,
+            prefix: vec![],
+        }
+    }
+}
+
+impl<'a, T, A: Automaton, U> Iterator for Search<'a, T, A, U> {
+    type Item = (Box<[u8]>, &'a T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((prefix_len, mut state, node)) = self.stack.pop() {
+            if !self.aut.can_match(&state) {
+                continue;
+            }
+            self.prefix.truncate(prefix_len);
+            self.prefix.extend_from_slice(node.key());
+            let mut bytes = node.key().iter();
+            if !bytes.all(|&byte| {
+                state = self.aut.accept(&state, byte);
+                self.aut.can_match(&state)
+            }) {
+                continue;
+            }
+
+            // Push node's children to stack
+            for child in node.children().iter().rev() {
+                self.stack.push((self.prefix.len(), state.clone(), child));
+            }
+
+            if let Some(value) = node.value() {
+                if self.aut.is_match(&state) {
+                    return Some((self.prefix.as_slice().into(), value));
+                }
+            }
+        }
+        None
+    }
+}
+
+/// An iterator over the keys of a set that match an automaton, in ascending
+/// order.
+pub struct SearchKeys<'a, A: Automaton>(Search<'a, (), A>);
+
+impl<'a, A: Automaton> SearchKeys<'a, A> {
+    pub(crate) fn new(search: Search<'a, (), A>) -> Self {
+        SearchKeys(search)
+    }
+}
+
+impl<A: Automaton> Iterator for SearchKeys<'_, A> {
+    type Item = Box<[u8]>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(|(key, _)| key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::map::RadixMap;
+
+    /// Matches keys made only of ASCII digits and counts the bytes fed.
+    struct Digits<'a>(&'a core::cell::Cell<usize>);
+
+    impl Automaton for Digits<'_> {
+        type State = bool;
+
+        fn start(&self) -> bool {
+            true
+        }
+
+        fn accept(&self, state: &bool, byte: u8) -> bool {
+            self.0.set(self.0.get() + 1);
+            *state && byte.is_ascii_digit()
+        }
+
+        fn is_match(&self, state: &bool) -> bool {
+            *state
+        }
+
+        fn can_match(&self, state: &bool) -> bool {
+            *state
+        }
+    }
+
+    fn populated_map() -> RadixMap<usize> {
+        [
+            "", "1", "12", "123", "12a", "1a", "2", "a", "ab", "abc", "b",
+        ]
+        .into_iter()
+        .enumerate()
+        .map(|(i, key)| (key, i))
+        .collect()
+    }
+
+    fn search<A: Automaton>(map: &RadixMap<usize>, aut: A) -> Vec<(Box<[u8]>, usize)> {
+        map.search(aut).map(|(key, value)| (key, *value)).collect()
+    }
+
+    fn expected<F: Fn(&[u8]) -> bool>(map: &RadixMap<usize>, f: F) -> Vec<(Box<[u8]>, usize)> {
+        map.iter()
+            .filter(|(key, _)| f(key))
+            .map(|(key, value)| (key, *value))
+            .collect()
+    }
+
+    #[test]
+    fn test_search() {
+        let map = populated_map();
+        let fed = core::cell::Cell::new(0);
+
+        assert_eq!(
+            search(&map, Digits(&fed)),
+            expected(&map, |key| key.iter().all(u8::is_ascii_digit))
+        );
+        // "12a" and "1a" are rejected on their last byte, "a" and "b" on
+        // their only one, so the children of "a" are never visited.
+        assert_eq!(fed.get(), 8);
+
+        assert_eq!(search(&map, Str::new("12")), [(b"12"[..].into(), 2)]);
+        assert_eq!(search(&map, Str::new("")), [(b""[..].into(), 0)]);
+        assert_eq!(search(&map, Str::new("13")), []);
+        assert_eq!(
+            search(&map, Prefix::new("12")),
+            expected(&map, |key| key.starts_with(b"12"))
+        );
+        assert_eq!(search(&map, Prefix::new("")), expected(&map, |_| true));
+    }
+
+    #[test]
+    fn test_search_combinators() {
+        let map = populated_map();
+        let fed = core::cell::Cell::new(0);
+
+        assert_eq!(
+            search(&map, Prefix::new("1").intersection(Digits(&fed))),
+            expected(&map, |key| key.starts_with(b"1")
+                && key.iter().all(u8::is_ascii_digit))
+        );
+        assert_eq!(
+            search(&map, Prefix::new("a").union(Str::new("2"))),
+            expected(&map, |key| key.starts_with(b"a") || key == b"2")
+        );
+        assert_eq!(
+            search(&map, Digits(&fed).complement()),
+            expected(&map, |key| !key.iter().all(u8::is_ascii_digit))
+        );
+        assert_eq!(
+            search(&map, Prefix::new("1").complement().union(Str::new("12"))),
+            expected(&map, |key| !key.starts_with(b"1") || key == b"12")
+        );
+
+        let digits = Digits(&fed);
+        assert_eq!(search(&map, &digits), search(&map, digits));
+    }
+}
@@ -0,0 +1,225 @@
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::allocator::NodeAllocator;
+use crate::bytes::common_prefix_len;
+use crate::error::RadixError;
+use crate::node::Node;
+
+/// Builds a tree bottom-up from keys given in ascending order.
+///
+/// The builder keeps the nodes on the path to the most recently pushed key
+/// open. A node is allocated once all of its children are known, i.e. when
+/// a later key diverges from its path, so no node is ever reallocated.
+pub(crate) struct SortedBuilder<'a, T, A: NodeAllocator, U = ()> {
+    alloc: &'a A,
+    /// Key of the most recently pushed item.
+    prev_key: Vec<u8>,
+    /// Open nodes on the path to `prev_key`, ordered from the root.
+    stack: Vec<OpenNode<T, U>>,
+    size: usize,
+}
+
+struct OpenNode<T, U> {
+    /// Offset in `prev_key` at which the node's key ends.
+    end: usize,
+    value: Option<T>,
+    children: Vec<Node<T, U>>,
+}
+
+impl<'a, T, A: NodeAllocator, U> SortedBuilder<'a, T, A, U> {
+    pub(crate) fn new(alloc: &'a A) -> Self {
+        SortedBuilder {
+            alloc,
+            prev_key: vec![],
+            stack: vec![OpenNode {
+                end: 0,
+                value: None,
+                children: vec![],
+            }],
+            size: 0,
+        }
+    }
+
+    /// Returns `true` if `key` can be pushed without breaking the sort order.
+    #[inline(always)]
+    pub(crate) fn is_ordered(&self, key: &[u8]) -> bool {
+        self.size == 0 || key >= self.prev_key.as_slice()
+    }
+
+    /// Pushes a key-value pair. Keys must be pushed in ascending order. If the
+    /// key is equal to the previous one the previous value is replaced.
+    ///
+    /// Returns an error if a node can't be allocated. The value is dropped
+    /// and the builder must not be used any further.
+    pub(crate) fn push(&mut self, key: &[u8], value: T) -> Result<(), RadixError> {
+        assert!(
+            self.is_ordered(key),
+            "Keys must be pushed in ascending order"
+        );
+
+        if self.size > 0 && key == self.prev_key.as_slice() {
+            // Duplicate key. The last value wins.
+            self.stack.last_mut().unwrap().value = Some(value);
+            return Ok(());
+        }
+
+        let prefix_len = common_prefix_len(key, &self.prev_key);
+        self.close(prefix_len)?;
+
+        if key.len() == prefix_len {
+            // Only an empty first key ends at an already open node.
+            self.stack.last_mut().unwrap().value = Some(value);
+        } else {
+            self.stack.push(OpenNode {
+                end: key.len(),
+                value: Some(value),
+                children: vec![],
+            });
+        }
+
+        self.prev_key.clear();
+        self.prev_key.extend_from_slice(key);
+        self.size += 1;
+        Ok(())
+    }
+
+    /// Finishes the tree and returns its root and the number of keys in it.
+    /// Returns an error if a node can't be allocated, in which case the nodes
+    /// allocated so far are freed.
+    pub(crate) fn build(mut self) -> Result<(Node<T, U>, usize), RadixError> {
+        self.close(0)?;
+        let root = self.stack.pop().unwrap();
+        let root = Node::try_from_parts(self.alloc, &[], root.value, root.children)?;
+        Ok((root, self.size))
+    }
+
+    /// Allocates all open nodes whose key ends past `prefix_len`. If `prefix_len`
+    /// ends in the middle of a node's key, the node is split and its prefix is
+    /// left open.
+    fn close(&mut self, prefix_len: usize) -> Result<(), RadixError> {
+        while self.stack.last().unwrap().end > prefix_len {
+            let open = self.stack.pop().unwrap();
+            let parent_end = self.stack.last().unwrap().end;
+            let node = Node::try_from_parts(
+                self.alloc,
+                &self.prev_key[parent_end.max(prefix_len)..open.end],
+                open.value,
+                open.children,
+            )?;
+
+            if parent_end < prefix_len {
+                self.stack.push(OpenNode {
+                    end: prefix_len,
+                    value: None,
+                    children: vec![node],
+                });
+            } else {
+                self.stack.last_mut().unwrap().children.push(node);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'a, T, A: NodeAllocator, U> Drop for SortedBuilder<'a, T, A, U> {
+    fn drop(&mut self) {
+        // The values of open nodes are dropped with the stack.
+        let children = self
+            .stack
+            .iter_mut()
+            .flat_map(|open| open.children.drain(..))
+            .collect();
+        Node::drop_all_in(children, self.alloc);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::allocator::Global;
+    use crate::growth::GrowthPolicy;
+
+    fn count_nodes<T>(node: &Node<T>) -> usize {
+        1 + node.children().iter().map(count_nodes).sum::<usize>()
+    }
+
+    #[test]
+    fn test_build() {
+        let keys: &[&[u8]] = &[b"", b"ab", b"abb;0", b"abc;0", b"c", b"cad", b"cb"];
+
+        let mut builder = SortedBuilder::new(&Global);
+        let mut inserted = Node::new(&Global, &[]);
+        for (i, key) in keys.iter().enumerate() {
+            builder.push(key, i).unwrap();
+            inserted.insert(&Global, GrowthPolicy::Exact, key, i);
+        }
+        let (root, size) = builder.build().unwrap();
+
+        assert_eq!(size, keys.len());
+        assert_eq!(count_nodes(&root), count_nodes(&inserted));
+        for (i, key) in keys.iter().enumerate() {
+            assert_eq!(root.get(key), Some(&i));
+        }
+        assert_eq!(root.get(b"a"), None);
+        assert_eq!(root.get(b"ca"), None);
+
+        root.drop_in(&Global);
+        inserted.drop_in(&Global);
+    }
+
+    #[test]
+    fn test_build_duplicates() {
+        let mut builder: SortedBuilder<_, _> = SortedBuilder::new(&Global);
+        builder.push(b"a", 1).unwrap();
+        builder.push(b"a", 2).unwrap();
+        builder.push(b"b", 3).unwrap();
+        builder.push(b"b", 4).unwrap();
+        let (root, size) = builder.build().unwrap();
+
+        assert_eq!(size, 2);
+        assert_eq!(root.get(b"a"), Some(&2));
+        assert_eq!(root.get(b"b"), Some(&4));
+
+        root.drop_in(&Global);
+    }
+
+    #[test]
+    fn test_build_long_keys() {
+        let key_a = vec![0; 260];
+        let mut key_b = key_a.clone();
+        key_b.extend(&[1, 2, 3]);
+        let key_c = vec![0; 520];
+        let key_d = vec![1; 510];
+
+        let mut builder = SortedBuilder::new(&Global);
+        builder.push(&key_a, 1).unwrap();
+        builder.push(&key_c, 2).unwrap();
+        builder.push(&key_b, 3).unwrap();
+        builder.push(&key_d, 4).unwrap();
+        let (root, size) = builder.build().unwrap();
+
+        assert_eq!(size, 4);
+        assert_eq!(root.get(&key_a), Some(&1));
+        assert_eq!(root.get(&key_c), Some(&2));
+        assert_eq!(root.get(&key_b), Some(&3));
+        assert_eq!(root.get(&key_d), Some(&4));
+        assert_eq!(root.get(&key_a[..255]), None);
+        // No chain nodes are needed for long keys.
+        assert_eq!(count_nodes(&root), 5);
+
+        root.drop_in(&Global);
+    }
+
+    #[test]
+    fn test_is_ordered() {
+        let mut builder: SortedBuilder<_, _> = SortedBuilder::new(&Global);
+        assert!(builder.is_ordered(b"b"));
+        builder.push(b"b", ()).unwrap();
+        assert!(builder.is_ordered(b"b"));
+        assert!(builder.is_ordered(b"ba"));
+        assert!(!builder.is_ordered(b"a"));
+        assert!(!builder.is_ordered(b""));
+    }
+}
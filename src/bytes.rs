@@ -0,0 +1,301 @@
+//! Byte slice primitives on the lookup path.
+//!
+//! Prefixes are compared a word at a time and, unless the `scalar` feature is
+//! enabled, with SSE2/AVX2 on x86_64 and NEON on aarch64. AVX2 is detected at
+//! runtime.
+
+/// Returns the length of the longest common prefix of `a` and `b`.
+#[inline(always)]
+pub(crate) fn common_prefix_len(a: &[u8], b: &[u8]) -> usize {
+    let len = a.len().min(b.len());
+    let (a, b) = (&a[..len], &b[..len]);
+
+    // Shorter slices are compared faster a word at a time.
+    #[cfg(all(not(feature = "scalar"), target_arch = "x86_64"))]
+    if len >= 32 {
+        return x86::common_prefix_len(a, b);
+    }
+    #[cfg(all(not(feature = "scalar"), target_arch = "aarch64"))]
+    if len >= 32 {
+        return neon::common_prefix_len(a, b);
+    }
+
+    common_prefix_len_words(a, b, 0)
+}
+
+/// Searches sorted `bytes` for `byte`. Returns `Ok(index)` if it was found and
+/// `Err(index)` with the insert position that maintains the sort order
+/// otherwise.
+#[inline(always)]
+pub(crate) fn find_byte(bytes: &[u8], byte: u8) -> Result<usize, usize> {
+    let idx = count_less(bytes, byte);
+    if idx < bytes.len() && bytes[idx] == byte {
+        Ok(idx)
+    } else {
+        Err(idx)
+    }
+}
+
+/// Returns the number of bytes in sorted `bytes` that are less than `byte`.
+#[inline(always)]
+fn count_less(bytes: &[u8], byte: u8) -> usize {
+    #[cfg(all(not(feature = "scalar"), target_arch = "x86_64"))]
+    if bytes.len() >= 16 {
+        return x86::count_less(bytes, byte);
+    }
+    #[cfg(all(not(feature = "scalar"), target_arch = "aarch64"))]
+    if bytes.len() >= 16 {
+        return neon::count_less(bytes, byte);
+    }
+
+    if bytes.len() <= 16 {
+        count_less_linear(bytes, byte, 0)
+    } else {
+        bytes.partition_point(|&b| b < byte)
+    }
+}
+
+/// Portable version of [`common_prefix_len()`] starting at `start`. Both
+/// slices must have the same length.
+#[inline(always)]
+fn common_prefix_len_words(a: &[u8], b: &[u8], start: usize) -> usize {
+    let len = a.len();
+    let mut i = start;
+    while i + 8 <= len {
+        let x = u64::from_le_bytes(a[i..i + 8].try_into().unwrap());
+        let y = u64::from_le_bytes(b[i..i + 8].try_into().unwrap());
+        if x != y {
+            return i + ((x ^ y).trailing_zeros() / 8) as usize;
+        }
+        i += 8;
+    }
+    while i < len && a[i] == b[i] {
+        i += 1;
+    }
+    i
+}
+
+#[inline(always)]
+fn count_less_linear(bytes: &[u8], byte: u8, start: usize) -> usize {
+    bytes[start..]
+        .iter()
+        .position(|&b| b >= byte)
+        .map_or(bytes.len(), |idx| start + idx)
+}
+
+#[cfg(all(not(feature = "scalar"), target_arch = "x86_64"))]
+mod x86 {
+    use core::arch::x86_64::*;
+
+    #[inline(always)]
+    pub(super) fn common_prefix_len(a: &[u8], b: &[u8]) -> usize {
+        if has_avx2() {
+            unsafe { common_prefix_len_avx2(a, b) }
+        } else {
+            unsafe { common_prefix_len_sse2(a, b, 0) }
+        }
+    }
+
+    #[inline(always)]
+    fn has_avx2() -> bool {
+        #[cfg(target_feature = "avx2")]
+        {
+            true
+        }
+        #[cfg(all(not(target_feature = "avx2"), feature = "std"))]
+        {
+            std::is_x86_feature_detected!("avx2")
+        }
+        // Runtime detection needs the standard library
+        #[cfg(all(not(target_feature = "avx2"), not(feature = "std")))]
+        {
+            false
+        }
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub(super) unsafe fn common_prefix_len_avx2(a: &[u8], b: &[u8]) -> usize {
+        let len = a.len();
+        let mut i = 0;
+        while i + 32 <= len {
+            let x = _mm256_loadu_si256(a.as_ptr().add(i) as *const __m256i);
+            let y = _mm256_loadu_si256(b.as_ptr().add(i) as *const __m256i);
+            let mask = _mm256_movemask_epi8(_mm256_cmpeq_epi8(x, y)) as u32;
+            if mask != u32::MAX {
+                return i + (!mask).trailing_zeros() as usize;
+            }
+            i += 32;
+        }
+        common_prefix_len_sse2(a, b, i)
+    }
+
+    #[inline(always)]
+    pub(super) unsafe fn common_prefix_len_sse2(a: &[u8], b: &[u8], start: usize) -> usize {
+        let len = a.len();
+        let mut i = start;
+        while i + 16 <= len {
+            let x = _mm_loadu_si128(a.as_ptr().add(i) as *const __m128i);
+            let y = _mm_loadu_si128(b.as_ptr().add(i) as *const __m128i);
+            let mask = _mm_movemask_epi8(_mm_cmpeq_epi8(x, y)) as u32;
+            if mask != 0xFFFF {
+                return i + (!mask).trailing_zeros() as usize;
+            }
+            i += 16;
+        }
+        super::common_prefix_len_words(a, b, i)
+    }
+
+    #[inline(always)]
+    pub(super) fn count_less(bytes: &[u8], byte: u8) -> usize {
+        let len = bytes.len();
+        let mut i = 0;
+        unsafe {
+            // SSE2 only has a signed comparison. Flipping the sign bit of both
+            // sides makes it an unsigned one.
+            let bias = _mm_set1_epi8(i8::MIN);
+            let needle = _mm_xor_si128(_mm_set1_epi8(byte as i8), bias);
+            while i + 16 <= len {
+                let chunk = _mm_loadu_si128(bytes.as_ptr().add(i) as *const __m128i);
+                let lt = _mm_cmplt_epi8(_mm_xor_si128(chunk, bias), needle);
+                let mask = _mm_movemask_epi8(lt) as u32;
+                if mask != 0xFFFF {
+                    // Bytes are sorted so the smaller ones form a prefix.
+                    return i + mask.count_ones() as usize;
+                }
+                i += 16;
+            }
+        }
+        super::count_less_linear(bytes, byte, i)
+    }
+}
+
+#[cfg(all(not(feature = "scalar"), target_arch = "aarch64"))]
+mod neon {
+    use core::arch::aarch64::*;
+
+    /// Narrows a byte mask to a u64 with 4 bits per byte.
+    #[inline(always)]
+    unsafe fn to_bitmask(mask: uint8x16_t) -> u64 {
+        let narrowed = vshrn_n_u16(vreinterpretq_u16_u8(mask), 4);
+        vget_lane_u64(vreinterpret_u64_u8(narrowed), 0)
+    }
+
+    #[inline(always)]
+    pub(super) fn common_prefix_len(a: &[u8], b: &[u8]) -> usize {
+        let len = a.len();
+        let mut i = 0;
+        unsafe {
+            while i + 16 <= len {
+                let x = vld1q_u8(a.as_ptr().add(i));
+                let y = vld1q_u8(b.as_ptr().add(i));
+                let mask = to_bitmask(vceqq_u8(x, y));
+                if mask != u64::MAX {
+                    return i + ((!mask).trailing_zeros() / 4) as usize;
+                }
+                i += 16;
+            }
+        }
+        super::common_prefix_len_words(a, b, i)
+    }
+
+    #[inline(always)]
+    pub(super) fn count_less(bytes: &[u8], byte: u8) -> usize {
+        let len = bytes.len();
+        let mut i = 0;
+        unsafe {
+            let needle = vdupq_n_u8(byte);
+            while i + 16 <= len {
+                let chunk = vld1q_u8(bytes.as_ptr().add(i));
+                let mask = to_bitmask(vcltq_u8(chunk, needle));
+                if mask != u64::MAX {
+                    // Bytes are sorted so the smaller ones form a prefix.
+                    return i + (mask.count_ones() / 4) as usize;
+                }
+                i += 16;
+            }
+        }
+        super::count_less_linear(bytes, byte, i)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn naive_common_prefix_len(a: &[u8], b: &[u8]) -> usize {
+        a.iter().zip(b.iter()).take_while(|(x, y)| x == y).count()
+    }
+
+    /// Pairs of slices of various lengths with a mismatch at every position.
+    fn prefix_cases() -> Vec<(Vec<u8>, Vec<u8>)> {
+        let mut cases = vec![];
+        for len in 0..100 {
+            let a: Vec<u8> = (0..len).map(|i| (i * 31 % 251) as u8).collect();
+            cases.push((a.clone(), a.clone()));
+            for pos in 0..len {
+                let mut b = a.clone();
+                b[pos] ^= 0x80;
+                cases.push((a.clone(), b.clone()));
+                b.truncate(pos + 1 + (len - pos) / 2);
+                cases.push((a.clone(), b));
+            }
+        }
+        cases
+    }
+
+    #[test]
+    fn test_common_prefix_len() {
+        for (a, b) in prefix_cases() {
+            let expected = naive_common_prefix_len(&a, &b);
+            assert_eq!(common_prefix_len(&a, &b), expected);
+            assert_eq!(common_prefix_len(&b, &a), expected);
+
+            let len = a.len().min(b.len());
+            assert_eq!(common_prefix_len_words(&a[..len], &b[..len], 0), expected);
+        }
+    }
+
+    #[cfg(all(not(feature = "scalar"), target_arch = "x86_64"))]
+    #[test]
+    fn test_common_prefix_len_x86() {
+        for (a, b) in prefix_cases() {
+            let expected = naive_common_prefix_len(&a, &b);
+            let len = a.len().min(b.len());
+            let (a, b) = (&a[..len], &b[..len]);
+
+            assert_eq!(unsafe { x86::common_prefix_len_sse2(a, b, 0) }, expected);
+            if std::is_x86_feature_detected!("avx2") {
+                assert_eq!(unsafe { x86::common_prefix_len_avx2(a, b) }, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_find_byte() {
+        for len in 0..=256usize {
+            // Bytes spread over the whole range, so that both present and
+            // missing bytes are searched for.
+            let bytes: Vec<u8> = (0..len).map(|i| (i * 255 / len.max(1)) as u8).collect();
+            let mut deduped = bytes.clone();
+            deduped.dedup();
+
+            for byte in 0..=255u8 {
+                assert_eq!(
+                    find_byte(&deduped, byte),
+                    deduped.binary_search(&byte),
+                    "len={} byte={}",
+                    len,
+                    byte
+                );
+                assert_eq!(
+                    count_less(&bytes, byte),
+                    bytes.partition_point(|&b| b < byte)
+                );
+                assert_eq!(
+                    count_less_linear(&bytes, byte, 0),
+                    bytes.partition_point(|&b| b < byte)
+                );
+            }
+        }
+    }
+}
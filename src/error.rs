@@ -0,0 +1,53 @@
+use core::fmt;
+
+/// Errors returned by the fallible operations of [`RadixMap`](crate::RadixMap)
+/// and [`RadixSet`](crate::RadixSet).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RadixError {
+    /// Keys passed to a sorted constructor were not in ascending order.
+    UnsortedKeys,
+    /// The allocator failed to allocate memory for a node or a value.
+    AllocError,
+    /// The operation would exceed the memory limit of the map.
+    CapacityExceeded,
+}
+
+impl fmt::Display for RadixError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RadixError::UnsortedKeys => write!(f, "keys are not sorted in ascending order"),
+            RadixError::AllocError => write!(f, "memory allocation failed"),
+            RadixError::CapacityExceeded => write!(f, "memory limit exceeded"),
+        }
+    }
+}
+
+impl core::error::Error for RadixError {}
+
+/// Errors returned when compiling a [`Regex`](crate::regex::Regex).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegexError {
+    /// The pattern ended inside a group, class, escape or repetition.
+    UnexpectedEnd,
+    /// The byte at the given offset of the pattern is not valid there.
+    UnexpectedByte(usize),
+    /// The repetition at the given offset has no operand or invalid bounds.
+    InvalidRepetition(usize),
+    /// The compiled automaton would have too many states.
+    TooManyStates,
+}
+
+impl fmt::Display for RegexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RegexError::UnexpectedEnd => write!(f, "unexpected end of pattern"),
+            RegexError::UnexpectedByte(pos) => write!(f, "unexpected byte at offset {}", pos),
+            RegexError::InvalidRepetition(pos) => {
+                write!(f, "invalid repetition at offset {}", pos)
+            }
+            RegexError::TooManyStates => write!(f, "pattern compiles to too many states"),
+        }
+    }
+}
+
+impl core::error::Error for RegexError {}
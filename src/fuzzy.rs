@@ -0,0 +1,345 @@
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::node::Node;
+
+/// Options of a fuzzy search.
+///
+/// By default a search matches whole keys whose Levenshtein distance to the
+/// query, i.e. the number of single byte insertions, deletions and
+/// substitutions needed to turn one into the other, is at most
+/// `max_distance`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fuzzy {
+    max_distance: usize,
+    transpositions: bool,
+    prefix: bool,
+}
+
+impl Fuzzy {
+    pub const fn new(max_distance: usize) -> Self {
+        Fuzzy {
+            max_distance,
+            transpositions: false,
+            prefix: false,
+        }
+    }
+
+    /// Also counts swapping two adjacent bytes as a single edit, which is the
+    /// optimal string alignment variant of the Damerau-Levenshtein distance.
+    pub const fn with_transpositions(mut self) -> Self {
+        self.transpositions = true;
+        self
+    }
+
+    /// Matches keys that start with a prefix within `max_distance` of the
+    /// query. The distance of a key is the smallest distance of any of its
+    /// prefixes.
+    pub const fn with_prefix(mut self) -> Self {
+        self.prefix = true;
+        self
+    }
+
+    pub const fn max_distance(&self) -> usize {
+        self.max_distance
+    }
+}
+
+/// An iterator over the entries within an edit distance of a query, sorted
+/// by key. Yields the key, its distance and the value.
+///
+/// The tree is walked depth first with a row of the edit distance matrix per
+/// key byte. Subtrees are skipped once every value of the last row exceeds
+/// the maximum distance, since rows of longer keys can't get smaller.
+pub struct FuzzySearch<'a, T, U = ()> {
+    query: Box<[u8]>,
+    fuzzy: Fuzzy,
+    stack: Vec<(usize, &'a Node<T, U>)>,
+    prefix: Vec<u8>,
+    /// Rows of the matrix for the first `best.len() - 1` bytes of the prefix,
+    /// each `query.len() + 1` values wide.
+    rows: Vec<usize>,
+    /// Smallest distance of any prefix up to each row.
+    best: Vec<usize>,
+}
+
+impl<'a, T, U> FuzzySearch<'a, T, U> {
+    pub(crate) fn new(root: &'a Node<T, U>, query: &[u8], fuzzy: Fuzzy) -> Self {
+        let rows: Vec<usize> = (0..=query.len()).collect();
+        FuzzySearch {
+            query: query.into(),
+            fuzzy,
+            stack: vec![(0, root)],
+            prefix: vec![],
+            best: vec![query.len()],
+            rows,
+        }
+    }
+
+    #[inline]
+    fn width(&self) -> usize {
+        self.query.len() + 1
+    }
+
+    /// Returns `true` if every key with the current prefix matches with the
+    /// same distance, which is the case once no row value is below the best
+    /// distance. Rows are not computed past that point.
+    #[inline]
+    fn settled(&self) -> bool {
+        let best = self.best[self.best.len() - 1];
+        self.fuzzy.prefix
+            && best <= self.fuzzy.max_distance
+            && self.rows[self.rows.len() - self.width()..]
+                .iter()
+                .all(|&d| d >= best)
+    }
+
+    fn truncate(&mut self, len: usize) {
+        self.prefix.truncate(len);
+        let rows = self.best.len().min(len + 1);
+        self.best.truncate(rows);
+        self.rows.truncate(rows * self.width());
+    }
+
+    /// Appends a byte to the prefix. Returns `false` if no key with the new
+    /// prefix can match.
+    fn push(&mut self, byte: u8) -> bool {
+        if self.settled() {
+            self.prefix.push(byte);
+            return true;
+        }
+        self.prefix.push(byte);
+
+        let width = self.width();
+        let i = self.prefix.len();
+        let start = self.rows.len();
+        let prev = start - width;
+        self.rows.push(i);
+        let mut min = i;
+        for j in 1..width {
+            let cost = (self.query[j - 1] != byte) as usize;
+            let mut d = (self.rows[prev + j] + 1)
+                .min(self.rows[start + j - 1] + 1)
+                .min(self.rows[prev + j - 1] + cost);
+            if self.fuzzy.transpositions
+                && i > 1
+                && j > 1
+                && self.query[j - 1] == self.prefix[i - 2]
+                && self.query[j - 2] == byte
+            {
+                d = d.min(self.rows[prev - width + j - 2] + 1);
+            }
+            self.rows.push(d);
+            min = min.min(d);
+        }
+        let best = self.best[self.best.len() - 1].min(self.rows[start + width - 1]);
+        self.best.push(best);
+        min <= self.fuzzy.max_distance || (self.fuzzy.prefix && best <= self.fuzzy.max_distance)
+    }
+
+    /// Returns the distance of the current prefix if it matches.
+    #[inline]
+    fn distance(&self) -> Option<usize> {
+        let distance = match self.fuzzy.prefix {
+            true => self.best[self.best.len() - 1],
+            false => self.rows[self.rows.len() - 1],
+        };
+        (distance <= self.fuzzy.max_distance).then_some(distance)
+    }
+}
+
+impl<'a, T, U> Iterator for FuzzySearch<'a, T, U> {
+    type Item = (Box<[u8]>, usize, &'a T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        'nodes: while let Some((prefix_len, node)) = self.stack.pop() {
+            self.truncate(prefix_len);
+            for &byte in node.key() {
+                if !self.push(byte) {
+                    continue 'nodes;
+                }
+            }
+
+            // Push node's children to stack
+            for child in node.children().iter().rev() {
+                self.stack.push((self.prefix.len(), child));
+            }
+
+            if let Some(value) = node.value() {
+                if let Some(distance) = self.distance() {
+                    return Some((self.prefix.as_slice().into(), distance, value));
+                }
+            }
+        }
+        None
+    }
+}
+
+/// An iterator over the keys of a set within an edit distance of a query,
+/// sorted by key. Yields the key and its distance.
+pub struct FuzzyKeys<'a>(FuzzySearch<'a, ()>);
+
+impl<'a> FuzzyKeys<'a> {
+    pub(crate) fn new(search: FuzzySearch<'a, ()>) -> Self {
+        FuzzyKeys(search)
+    }
+}
+
+impl Iterator for FuzzyKeys<'_> {
+    type Item = (Box<[u8]>, usize);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(|(key, distance, _)| (key, distance))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::map::RadixMap;
+
+    fn levenshtein(a: &[u8], b: &[u8], transpositions: bool) -> usize {
+        let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+        for (i, row) in d.iter_mut().enumerate() {
+            row[0] = i;
+        }
+        d[0] = (0..=b.len()).collect();
+        for i in 1..=a.len() {
+            for j in 1..=b.len() {
+                let cost = (a[i - 1] != b[j - 1]) as usize;
+                d[i][j] = (d[i - 1][j] + 1)
+                    .min(d[i][j - 1] + 1)
+                    .min(d[i - 1][j - 1] + cost);
+                if transpositions && i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1]
+                {
+                    d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+                }
+            }
+        }
+        d[a.len()][b.len()]
+    }
+
+    fn expected(keys: &[Vec<u8>], query: &[u8], fuzzy: Fuzzy) -> Vec<(Box<[u8]>, usize)> {
+        let mut expected: Vec<_> = keys
+            .iter()
+            .filter_map(|key| {
+                let distance = match fuzzy.prefix {
+                    true => (0..=key.len())
+                        .map(|len| levenshtein(&key[..len], query, fuzzy.transpositions))
+                        .min()
+                        .unwrap(),
+                    false => levenshtein(key, query, fuzzy.transpositions),
+                };
+                (distance <= fuzzy.max_distance).then(|| (key.as_slice().into(), distance))
+            })
+            .collect();
+        expected.sort();
+        expected.dedup();
+        expected
+    }
+
+    #[test]
+    fn test_fuzzy_search() {
+        let map: RadixMap<usize> = [
+            ("hello", 0),
+            ("help", 1),
+            ("hell", 2),
+            ("yellow", 3),
+            ("ehllo", 4),
+            ("world", 5),
+        ]
+        .into_iter()
+        .collect();
+        let search = |fuzzy: Fuzzy| -> Vec<(Box<[u8]>, usize)> {
+            map.fuzzy_search_with(b"hello", fuzzy)
+                .map(|(key, distance, _)| (key, distance))
+                .collect()
+        };
+
+        assert_eq!(search(Fuzzy::new(0)), [(b"hello"[..].into(), 0)]);
+        assert_eq!(
+            search(Fuzzy::new(1)),
+            [(b"hell"[..].into(), 1), (b"hello"[..].into(), 0)]
+        );
+        assert_eq!(
+            search(Fuzzy::new(2)),
+            [
+                (b"ehllo"[..].into(), 2),
+                (b"hell"[..].into(), 1),
+                (b"hello"[..].into(), 0),
+                (b"help"[..].into(), 2),
+                (b"yellow"[..].into(), 2)
+            ]
+        );
+        assert_eq!(
+            search(Fuzzy::new(1).with_transpositions()),
+            [
+                (b"ehllo"[..].into(), 1),
+                (b"hell"[..].into(), 1),
+                (b"hello"[..].into(), 0)
+            ]
+        );
+        assert_eq!(
+            search(Fuzzy::new(1).with_prefix()),
+            [
+                (b"hell"[..].into(), 1),
+                (b"hello"[..].into(), 0),
+                (b"yellow"[..].into(), 1)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_fuzzy_search_prefix() {
+        let map: RadixMap<usize> = [("hello world", 0), ("helo", 1), ("help me", 2), ("h", 3)]
+            .into_iter()
+            .collect();
+        let found: Vec<_> = map
+            .fuzzy_search_with(b"helo", Fuzzy::new(1).with_prefix())
+            .map(|(key, distance, value)| (key, distance, *value))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (b"hello world"[..].into(), 1, 0),
+                (b"helo"[..].into(), 0, 1),
+                (b"help me"[..].into(), 1, 2),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_fuzzy_search_matches_brute_force() {
+        let mut keys: Vec<Vec<u8>> = (0..500u32)
+            .map(|i| {
+                let len = (i.wrapping_mul(2654435761) % 9) as usize;
+                (0..len)
+                    .map(|j| b"abc"[(i as usize * 7 + j * j * 3 + j) % 3])
+                    .collect()
+            })
+            .collect();
+        keys.push(vec![]);
+        let map: RadixMap<()> = keys.iter().map(|key| (key, ())).collect();
+
+        for query in [&b""[..], b"a", b"abc", b"cab", b"aabbcc", b"bacabca"] {
+            for max_distance in 0..4 {
+                for transpositions in [false, true] {
+                    for prefix in [false, true] {
+                        let fuzzy = Fuzzy {
+                            max_distance,
+                            transpositions,
+                            prefix,
+                        };
+                        let found: Vec<_> = map
+                            .fuzzy_search_with(query, fuzzy)
+                            .map(|(key, distance, _)| (key, distance))
+                            .collect();
+                        assert_eq!(found, expected(&keys, query, fuzzy), "{:?}", fuzzy);
+                    }
+                }
+            }
+        }
+    }
+}
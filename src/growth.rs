@@ -0,0 +1,74 @@
+/// Determines the capacity of node children arrays.
+///
+/// With any policy other than [`GrowthPolicy::Exact`], children arrays keep
+/// spare capacity so that most child insertions and removals do not
+/// reallocate the node. Spare capacity can be released using
+/// [`crate::RadixMap::shrink_to_fit()`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum GrowthPolicy {
+    /// Children arrays have no spare capacity. Uses the least memory.
+    #[default]
+    Exact,
+    /// Capacity grows to the next power of two.
+    Doubling,
+    /// Capacity grows through the classes 4, 16, 48 and 256.
+    SizeClasses,
+}
+
+impl GrowthPolicy {
+    /// Returns the capacity of a children array that has to fit `count`
+    /// children.
+    #[inline]
+    pub(crate) fn capacity(self, count: usize) -> usize {
+        let capacity = match self {
+            GrowthPolicy::Exact => count,
+            GrowthPolicy::Doubling => count.next_power_of_two(),
+            GrowthPolicy::SizeClasses => match count {
+                0..=4 => 4,
+                5..=16 => 16,
+                17..=48 => 48,
+                _ => 256,
+            },
+        };
+        capacity.min(256)
+    }
+
+    /// Returns the new capacity of a children array of `capacity` that holds
+    /// `count` children if it should be shrunk. Arrays are only shrunk once
+    /// they would fit into a smaller capacity even after doubling, so that
+    /// alternating insertions and removals do not reallocate every time.
+    #[inline]
+    pub(crate) fn shrink(self, count: usize, capacity: usize) -> Option<usize> {
+        if self == GrowthPolicy::Exact {
+            return (count < capacity).then_some(count);
+        }
+        (self.capacity(count * 2) < capacity).then(|| self.capacity(count))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capacity() {
+        assert_eq!(GrowthPolicy::Exact.capacity(5), 5);
+        assert_eq!(GrowthPolicy::Doubling.capacity(1), 1);
+        assert_eq!(GrowthPolicy::Doubling.capacity(5), 8);
+        assert_eq!(GrowthPolicy::Doubling.capacity(256), 256);
+        assert_eq!(GrowthPolicy::SizeClasses.capacity(1), 4);
+        assert_eq!(GrowthPolicy::SizeClasses.capacity(17), 48);
+        assert_eq!(GrowthPolicy::SizeClasses.capacity(49), 256);
+    }
+
+    #[test]
+    fn test_shrink() {
+        assert_eq!(GrowthPolicy::Exact.shrink(3, 4), Some(3));
+        assert_eq!(GrowthPolicy::Exact.shrink(4, 4), None);
+        assert_eq!(GrowthPolicy::Doubling.shrink(3, 8), None);
+        assert_eq!(GrowthPolicy::Doubling.shrink(2, 8), Some(2));
+        assert_eq!(GrowthPolicy::SizeClasses.shrink(20, 256), Some(48));
+        assert_eq!(GrowthPolicy::SizeClasses.shrink(30, 256), None);
+        assert_eq!(GrowthPolicy::SizeClasses.shrink(2, 16), Some(4));
+    }
+}
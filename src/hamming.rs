@@ -0,0 +1,254 @@
+use alloc::boxed::Box;
+use alloc::collections::BinaryHeap;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cmp::{Ordering, Reverse};
+
+use crate::node::Node;
+
+/// Adds the bits in which `bytes` differ from the query bytes at `offset` to
+/// `distance`. Returns `None` if `bytes` extend past the end of the query.
+#[inline]
+fn accumulate(query: &[u8], offset: usize, bytes: &[u8], distance: u32) -> Option<u32> {
+    let query = query.get(offset..offset + bytes.len())?;
+    Some(
+        query
+            .iter()
+            .zip(bytes)
+            .fold(distance, |distance, (a, b)| distance + (a ^ b).count_ones()),
+    )
+}
+
+/// An iterator over the entries whose key has the length of a query and
+/// differs from it in at most a given number of bits, sorted by key. Yields
+/// the key, its Hamming distance and the value.
+pub struct HammingSearch<'a, T, U = ()> {
+    query: Box<[u8]>,
+    max_bits: u32,
+    /// Nodes to visit with the length of the prefix before them and its
+    /// distance.
+    stack: Vec<(usize, u32, &'a Node<T, U>)>,
+    prefix: Vec<u8>,
+}
+
+impl<'a, T, U> HammingSearch<'a, T, U> {
+    pub(crate) fn new(root: &'a Node<T, U>, query: &[u8], max_bits: u32) -> Self {
+        HammingSearch {
+            query: query.into(),
+            max_bits,
+            stack: vec![(0, 0, root)],
+            prefix: vec![],
+        }
+    }
+}
+
+impl<'a, T, U> Iterator for HammingSearch<'a, T, U> {
+    type Item = (Box<[u8]>, u32, &'a T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((prefix_len, distance, node)) = self.stack.pop() {
+            let Some(distance) = accumulate(&self.query, prefix_len, node.key(), distance) else {
+                continue;
+            };
+            if distance > self.max_bits {
+                continue;
+            }
+            self.prefix.truncate(prefix_len);
+            self.prefix.extend_from_slice(node.key());
+
+            if self.prefix.len() == self.query.len() {
+                // Keys below this node are longer than the query.
+                if let Some(value) = node.value() {
+                    return Some((self.prefix.as_slice().into(), distance, value));
+                }
+                continue;
+            }
+
+            // Push node's children to stack
+            for child in node.children().iter().rev() {
+                self.stack.push((self.prefix.len(), distance, child));
+            }
+        }
+        None
+    }
+}
+
+/// A subtree waiting in the queue of [`HammingNearest`], ordered by the
+/// distance and then the key of its prefix.
+struct Candidate<'a, T, U> {
+    distance: u32,
+    prefix: Box<[u8]>,
+    node: &'a Node<T, U>,
+}
+
+impl<T, U> PartialEq for Candidate<'_, T, U> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<T, U> Eq for Candidate<'_, T, U> {}
+
+impl<T, U> PartialOrd for Candidate<'_, T, U> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T, U> Ord for Candidate<'_, T, U> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.distance, &self.prefix).cmp(&(other.distance, &other.prefix))
+    }
+}
+
+/// An iterator over the `k` entries whose key has the length of a query and
+/// is closest to it in Hamming distance, ordered by distance and then by key.
+///
+/// Subtrees are expanded best first: the distance of a prefix never exceeds
+/// the distance of the keys below it, so a key is yielded as soon as it is
+/// the closest candidate left and the rest of the tree is never visited.
+pub struct HammingNearest<'a, T, U = ()> {
+    query: Box<[u8]>,
+    remaining: usize,
+    queue: BinaryHeap<Reverse<Candidate<'a, T, U>>>,
+}
+
+impl<'a, T, U> HammingNearest<'a, T, U> {
+    pub(crate) fn new(root: &'a Node<T, U>, query: &[u8], k: usize) -> Self {
+        let mut queue = BinaryHeap::new();
+        if let Some(distance) = accumulate(query, 0, root.key(), 0).filter(|_| k > 0) {
+            queue.push(Reverse(Candidate {
+                distance,
+                prefix: root.key().into(),
+                node: root,
+            }));
+        }
+        HammingNearest {
+            query: query.into(),
+            remaining: k,
+            queue,
+        }
+    }
+}
+
+impl<'a, T, U> Iterator for HammingNearest<'a, T, U> {
+    type Item = (Box<[u8]>, u32, &'a T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        while let Some(Reverse(candidate)) = self.queue.pop() {
+            if candidate.prefix.len() == self.query.len() {
+                if let Some(value) = candidate.node.value() {
+                    self.remaining -= 1;
+                    return Some((candidate.prefix, candidate.distance, value));
+                }
+                continue;
+            }
+
+            for child in candidate.node.children() {
+                let Some(distance) = accumulate(
+                    &self.query,
+                    candidate.prefix.len(),
+                    child.key(),
+                    candidate.distance,
+                ) else {
+                    continue;
+                };
+                let mut prefix = Vec::with_capacity(candidate.prefix.len() + child.key().len());
+                prefix.extend_from_slice(&candidate.prefix);
+                prefix.extend_from_slice(child.key());
+                self.queue.push(Reverse(Candidate {
+                    distance,
+                    prefix: prefix.into(),
+                    node: child,
+                }));
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use crate::map::RadixMap;
+
+    fn hamming(a: &[u8], b: &[u8]) -> u32 {
+        a.iter().zip(b).map(|(a, b)| (a ^ b).count_ones()).sum()
+    }
+
+    fn fingerprints() -> Vec<[u8; 8]> {
+        let mut rng = StdRng::seed_from_u64(7);
+        (0..2000)
+            .map(|i| {
+                // Share leading bytes between fingerprints so the tree has
+                // inner nodes to prune.
+                let mut key: [u8; 8] = rng.gen();
+                key[0] &= 0x03;
+                key[1] = (i % 5) as u8;
+                key
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_hamming_search() {
+        let keys = fingerprints();
+        let mut map: RadixMap<usize> = keys.iter().enumerate().map(|(i, k)| (k, i)).collect();
+        // Keys of other lengths never match.
+        map.insert(&keys[0][..7], usize::MAX);
+        map.insert([keys[0].as_slice(), b"x"].concat(), usize::MAX);
+
+        for query in [keys[0], keys[1000], [0; 8], [0xff; 8]] {
+            for max_bits in [0, 1, 8, 16, 24, 64] {
+                let mut expected: Vec<_> = keys
+                    .iter()
+                    .enumerate()
+                    .map(|(i, key)| (key.as_slice().into(), hamming(key, &query), i))
+                    .filter(|(_, distance, _)| *distance <= max_bits)
+                    .collect();
+                expected.sort();
+                expected.dedup_by(|a, b| a.0 == b.0);
+
+                let found: Vec<(Box<[u8]>, u32, usize)> = map
+                    .hamming_search(query, max_bits)
+                    .map(|(key, distance, value)| (key, distance, *value))
+                    .collect();
+                assert_eq!(found.len(), expected.len());
+                for ((key, distance, value), expected) in found.iter().zip(&expected) {
+                    assert_eq!((key, distance), (&expected.0, &expected.1));
+                    assert_eq!(map.get(key), Some(value));
+                }
+            }
+        }
+        assert_eq!(map.hamming_search([], 64).count(), 0);
+    }
+
+    #[test]
+    fn test_hamming_nearest() {
+        let keys = fingerprints();
+        let map: RadixMap<()> = keys.iter().map(|k| (k, ())).collect();
+
+        for query in [keys[0], keys[1000], [0; 8], [0xff; 8]] {
+            let mut expected: Vec<(u32, Box<[u8]>)> = keys
+                .iter()
+                .map(|key| (hamming(key, &query), key.as_slice().into()))
+                .collect();
+            expected.sort();
+            expected.dedup();
+
+            for k in [0, 1, 10, 100, 5000] {
+                let found: Vec<_> = map
+                    .hamming_nearest(query, k)
+                    .map(|(key, distance, _)| (distance, key))
+                    .collect();
+                assert_eq!(found, expected[..k.min(expected.len())]);
+            }
+        }
+        assert_eq!(map.hamming_nearest([0; 7], 10).count(), 0);
+    }
+}
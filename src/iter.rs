@@ -1,9 +1,14 @@
-use std::{
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::{
     marker::PhantomData,
     ops::{Bound, RangeBounds},
 };
 
+use crate::allocator::{Global, NodeAllocator};
 use crate::node::Node;
+use crate::summary::{part_summary, Contains, Count};
 
 pub trait IterMap<'a, T> {
     type Output;
@@ -17,14 +22,14 @@ pub trait IterMapMut<'a, T> {
     fn map(prefix: &[u8], value: &'a mut T) -> Self::Output;
 }
 
-pub struct Iter<'a, T, M: IterMap<'a, T>> {
-    stack: Vec<(usize, &'a Node<T>)>,
+pub struct Iter<'a, T, M: IterMap<'a, T>, U = ()> {
+    stack: Vec<(usize, &'a Node<T, U>)>,
     prefix: Vec<u8>,
-    _marker: std::marker::PhantomData<M>,
+    _marker: PhantomData<M>,
 }
 
-impl<'a, T, M: IterMap<'a, T>> Iter<'a, T, M> {
-    pub(crate) fn new(root: Option<&'a Node<T>>, prefix: Vec<u8>) -> Self {
+impl<'a, T, M: IterMap<'a, T>, U> Iter<'a, T, M, U> {
+    pub(crate) fn new(root: Option<&'a Node<T, U>>, prefix: Vec<u8>) -> Self {
         let stack = match root {
             Some(root) => vec![(prefix.len(), root)],
             None => vec![],
@@ -33,7 +38,7 @@ impl<'a, T, M: IterMap<'a, T>> Iter<'a, T, M> {
         Iter {
             stack,
             prefix,
-            _marker: PhantomData::default(),
+            _marker: PhantomData,
         }
     }
 
@@ -44,28 +49,72 @@ impl<'a, T, M: IterMap<'a, T>> Iter<'a, T, M> {
     }
 }
 
-impl<'a, T, M: IterMap<'a, T>> Iterator for Iter<'a, T, M> {
-    type Item = M::Output;
+impl<'a, T, M: IterMap<'a, T>, U> Iter<'a, T, M, U> {
+    /// Creates an iterator that starts at the entry with the given index in
+    /// key order, using the entry counts cached in the nodes to skip the
+    /// subtrees before it.
+    pub(crate) fn from_index<S: Contains<T, Count, I, Output = U>, I>(
+        root: &'a Node<T, U>,
+        summary: &S,
+        index: usize,
+    ) -> Self {
+        let mut iter = Iter::new(None, vec![]);
+        if index >= part_summary(root, summary).unwrap_or(0) {
+            return iter;
+        }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        match self.stack.pop() {
-            Some((prefix_len, node)) => {
-                // Update prefix
-                self.prefix.truncate(prefix_len);
-                self.prefix.extend(node.key());
-
-                // Push node's children to stack
-                for child in node.children().iter().rev() {
-                    self.stack.push((self.prefix.len(), child));
+        // Descends to the entry and leaves the subtrees after it on the
+        // stack like a traversal that had just yielded the entry before it.
+        let mut node = root;
+        let mut index = index;
+        'descend: loop {
+            let prefix_len = iter.prefix.len();
+            iter.prefix.extend_from_slice(node.key());
+            if node.value().is_some() {
+                if index == 0 {
+                    iter.stack.push((prefix_len, node));
+                    break;
                 }
+                index -= 1;
+            }
 
-                // Return value
-                match node.value() {
-                    Some(v) => Some(M::map(&self.prefix, v)),
-                    None => self.next(),
+            let children = node.children();
+            for (i, child) in children.iter().enumerate() {
+                let count = part_summary(child, summary).unwrap_or(0);
+                if index < count {
+                    for sibling in children[i + 1..].iter().rev() {
+                        iter.stack.push((iter.prefix.len(), sibling));
+                    }
+                    node = child;
+                    continue 'descend;
                 }
+                index -= count;
+            }
+            unreachable!("entry counts are out of date");
+        }
+        iter
+    }
+}
+
+impl<'a, T, M: IterMap<'a, T>, U> Iterator for Iter<'a, T, M, U> {
+    type Item = M::Output;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (prefix_len, node) = self.stack.pop()?;
+            // Update prefix
+            self.prefix.truncate(prefix_len);
+            self.prefix.extend(node.key());
+
+            // Push node's children to stack
+            for child in node.children().iter().rev() {
+                self.stack.push((self.prefix.len(), child));
+            }
+
+            // Return value
+            if let Some(v) = node.value() {
+                return Some(M::map(&self.prefix, v));
             }
-            None => None,
         }
     }
 }
@@ -73,7 +122,7 @@ impl<'a, T, M: IterMap<'a, T>> Iterator for Iter<'a, T, M> {
 pub struct IterMut<'a, T, M: IterMapMut<'a, T>> {
     stack: Vec<(usize, &'a mut Node<T>)>,
     prefix: Vec<u8>,
-    _marker: std::marker::PhantomData<M>,
+    _marker: PhantomData<M>,
 }
 
 impl<'a, T, M: IterMapMut<'a, T>> IterMut<'a, T, M> {
@@ -86,7 +135,7 @@ impl<'a, T, M: IterMapMut<'a, T>> IterMut<'a, T, M> {
         IterMut {
             stack,
             prefix,
-            _marker: PhantomData::default(),
+            _marker: PhantomData,
         }
     }
 
@@ -101,78 +150,82 @@ impl<'a, T, M: IterMapMut<'a, T>> Iterator for IterMut<'a, T, M> {
     type Item = M::Output;
 
     fn next(&mut self) -> Option<Self::Item> {
-        match self.stack.pop() {
-            Some((prefix_len, node)) => {
-                // Update prefix
-                self.prefix.truncate(prefix_len);
-                self.prefix.extend(node.key());
+        loop {
+            let (prefix_len, node) = self.stack.pop()?;
+            // Update prefix
+            self.prefix.truncate(prefix_len);
+            self.prefix.extend(node.key());
 
-                let value = node.value_mut().map(|v| v as *mut T);
+            let value = node.value_mut().map(|v| v as *mut T);
 
-                // Push node's children to stack
-                for child in node.children_mut().iter_mut().rev() {
-                    self.stack.push((self.prefix.len(), child));
-                }
+            // Push node's children to stack
+            for child in node.children_mut().iter_mut().rev() {
+                self.stack.push((self.prefix.len(), child));
+            }
 
-                // Return value
-                match value {
-                    Some(v) => Some(M::map(&self.prefix, unsafe {
-                        // SAFETY
-                        // We are giving out mutable references to node's value
-                        // while holding a mutable reference to the node itself
-                        // so this is OK.
-                        &mut *v
-                    })),
-                    None => self.next(),
-                }
+            // Return value
+            if let Some(v) = value {
+                return Some(M::map(&self.prefix, unsafe {
+                    // SAFETY
+                    // We are giving out mutable references to node's value
+                    // while holding a mutable reference to the node itself
+                    // so this is OK.
+                    &mut *v
+                }));
             }
-            None => None,
         }
     }
 }
 
-pub struct IntoIter<T> {
-    stack: Vec<(usize, Node<T>)>,
+pub struct IntoIter<T, A: NodeAllocator = Global, U = ()> {
+    stack: Vec<(usize, Node<T, U>)>,
     prefix: Vec<u8>,
+    alloc: A,
 }
 
-impl<T> IntoIter<T> {
-    pub(crate) fn new(root: Node<T>) -> Self {
+impl<T, A: NodeAllocator, U> IntoIter<T, A, U> {
+    pub(crate) fn new(root: Node<T, U>, alloc: A) -> Self {
         IntoIter {
             stack: vec![(0, root)],
             prefix: vec![],
+            alloc,
         }
     }
 }
 
-impl<T> Iterator for IntoIter<T> {
+impl<T, A: NodeAllocator, U> Iterator for IntoIter<T, A, U> {
     type Item = (Box<[u8]>, T);
 
     fn next(&mut self) -> Option<Self::Item> {
-        match self.stack.pop() {
-            Some((prefix_len, mut node)) => {
-                // Update prefix
-                self.prefix.truncate(prefix_len);
-                self.prefix.extend(node.key());
-
-                // Push node's children to stack
-                for child in node.take_children().rev() {
-                    self.stack.push((self.prefix.len(), child));
-                }
+        loop {
+            let (prefix_len, mut node) = self.stack.pop()?;
+            // Update prefix
+            self.prefix.truncate(prefix_len);
+            self.prefix.extend(node.key());
+
+            // Push node's children to stack and deallocate the node
+            let value = node.take_value(&self.alloc);
+            for child in node.into_children(&self.alloc).rev() {
+                self.stack.push((self.prefix.len(), child));
+            }
 
-                // Return value
-                match node.take_value() {
-                    Some(v) => Some((self.prefix.as_slice().into(), v)),
-                    None => self.next(),
-                }
+            // Return value
+            if let Some(v) = value {
+                return Some((self.prefix.as_slice().into(), v));
             }
-            None => None,
         }
     }
 }
 
+impl<T, A: NodeAllocator, U> Drop for IntoIter<T, A, U> {
+    fn drop(&mut self) {
+        let nodes = self.stack.drain(..).map(|(_, node)| node).collect();
+        Node::drop_all_in(nodes, &self.alloc);
+    }
+}
+
 #[inline(always)]
-fn in_range_left<K: AsRef<[u8]>>(bound: Bound<&K>, key: &[u8]) -> bool {
+pub(crate) fn in_range_left<K: AsRef<[u8]>>(bound: Bound<&K>, key: &[u8]) -> bool {
     match bound {
         Bound::Excluded(lb) => lb.as_ref() < key,
         Bound::Included(lb) => lb.as_ref() <= key,
@@ -181,7 +234,7 @@ fn in_range_left<K: AsRef<[u8]>>(bound: Bound<&K>, key: &[u8]) -> bool {
 }
 
 #[inline(always)]
-fn in_range_right<K: AsRef<[u8]>>(bound: Bound<&K>, key: &[u8]) -> bool {
+pub(crate) fn in_range_right<K: AsRef<[u8]>>(bound: Bound<&K>, key: &[u8]) -> bool {
     match bound {
         Bound::Excluded(ub) => ub.as_ref() > key,
         Bound::Included(ub) => ub.as_ref() >= key,
@@ -189,25 +242,25 @@ fn in_range_right<K: AsRef<[u8]>>(bound: Bound<&K>, key: &[u8]) -> bool {
     }
 }
 
-pub struct Range<'a, T, K: AsRef<[u8]>, B: RangeBounds<K>> {
-    iter: Iter<'a, T, MapV<'a, T>>,
+pub struct Range<'a, T, K: AsRef<[u8]>, B: RangeBounds<K>, U = ()> {
+    iter: Iter<'a, T, MapV<'a, T>, U>,
     bounds: B,
     done: bool,
     _marker: PhantomData<K>,
 }
 
-impl<'a, T, K: AsRef<[u8]>, B: RangeBounds<K>> Range<'a, T, K, B> {
-    pub(crate) fn new(iter: Iter<'a, T, MapV<'a, T>>, bounds: B) -> Self {
+impl<'a, T, K: AsRef<[u8]>, B: RangeBounds<K>, U> Range<'a, T, K, B, U> {
+    pub(crate) fn new(iter: Iter<'a, T, MapV<'a, T>, U>, bounds: B) -> Self {
         Range {
             iter,
             bounds,
             done: false,
-            _marker: PhantomData::default(),
+            _marker: PhantomData,
         }
     }
 }
 
-impl<'a, T, K: AsRef<[u8]>, B: RangeBounds<K>> Iterator for Range<'a, T, K, B> {
+impl<'a, T, K: AsRef<[u8]>, B: RangeBounds<K>, U> Iterator for Range<'a, T, K, B, U> {
     type Item = <MapKV<'a, T> as IterMap<'a, T>>::Output;
 
     fn next(&mut self) -> Option<Self::Item> {
@@ -215,20 +268,18 @@ impl<'a, T, K: AsRef<[u8]>, B: RangeBounds<K>> Iterator for Range<'a, T, K, B> {
             return None;
         }
 
-        match self.iter.next() {
-            Some(v) => {
-                let k = self.iter.curr_key();
-                if !in_range_left(self.bounds.start_bound(), k) {
-                    return self.next();
-                }
-                if !in_range_right(self.bounds.end_bound(), k) {
-                    self.done = true;
-                    return None;
-                }
-
-                Some(MapKV::map(k, v))
+        loop {
+            let v = self.iter.next()?;
+            let k = self.iter.curr_key();
+            if !in_range_left(self.bounds.start_bound(), k) {
+                continue;
+            }
+            if !in_range_right(self.bounds.end_bound(), k) {
+                self.done = true;
+                return None;
             }
-            None => None,
+
+            return Some(MapKV::map(k, v));
         }
     }
 }
@@ -246,7 +297,7 @@ impl<'a, T, K: AsRef<[u8]>, B: RangeBounds<K>> RangeMut<'a, T, K, B> {
             iter,
             bounds,
             done: false,
-            _marker: PhantomData::default(),
+            _marker: PhantomData,
         }
     }
 }
@@ -259,20 +310,18 @@ impl<'a, T, K: AsRef<[u8]>, B: RangeBounds<K>> Iterator for RangeMut<'a, T, K, B
             return None;
         }
 
-        match self.iter.next() {
-            Some(v) => {
-                let k = self.iter.curr_key();
-                if !in_range_left(self.bounds.start_bound(), k) {
-                    return self.next();
-                }
-                if !in_range_right(self.bounds.end_bound(), k) {
-                    self.done = true;
-                    return None;
-                }
-
-                Some(MapKVMut::map(k, v))
+        loop {
+            let v = self.iter.next()?;
+            let k = self.iter.curr_key();
+            if !in_range_left(self.bounds.start_bound(), k) {
+                continue;
             }
-            None => None,
+            if !in_range_right(self.bounds.end_bound(), k) {
+                self.done = true;
+                return None;
+            }
+
+            return Some(MapKVMut::map(k, v));
         }
     }
 }
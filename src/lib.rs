@@ -1,34 +1,54 @@
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+extern crate alloc;
+
+pub(crate) mod builder;
+pub(crate) mod bytes;
+pub(crate) mod neighbor;
 pub(crate) mod node;
 
+pub mod allocator;
+pub mod automaton;
+pub mod error;
+pub mod fuzzy;
+pub mod growth;
+pub mod hamming;
 pub mod iter;
+pub mod list;
 pub mod map;
+pub mod regex;
+#[cfg(feature = "rand")]
+pub mod sample;
 pub mod set;
+pub mod slab;
+pub mod stats;
+pub mod summary;
+pub use allocator::{Global, NodeAllocator};
+pub use automaton::Automaton;
+pub use error::{RadixError, RegexError};
+pub use fuzzy::Fuzzy;
+pub use growth::GrowthPolicy;
 pub use map::RadixMap;
+pub use regex::Regex;
 pub use set::RadixSet;
+pub use slab::SlabAllocator;
+pub use stats::TreeStats;
+pub use summary::Summary;
 
 #[inline]
-fn longest_common_prefix<T>(children: &[node::Node<T>], key: &[u8]) -> (usize, usize) {
-    // If an element exists in the array it returns Ok(index)
-    // If an element does not exist in the array it returns Err(index) where index
-    // is the insert index that maintains the sort order.
-    let byte0 = [key[0]];
-    let idx = match children.binary_search_by_key(&byte0.as_slice(), |k| k.key()) {
+fn longest_common_prefix<T, U>(node: &node::Node<T, U>, key: &[u8]) -> (usize, usize) {
+    // If a child starting with the key's first byte exists it returns
+    // Ok(index), otherwise Err(index) where index is the insert index that
+    // maintains the sort order.
+    let idx = match node.child_index(key[0]) {
         Ok(idx) => idx,
-        Err(idx) => idx,
+        Err(idx) => return (0, idx),
     };
 
-    if (idx >= children.len()) || (children[idx].key()[0] != key[0]) {
-        // Not found
-        return (0, idx);
-    }
-
-    let common_prefix_len = key
-        .iter()
-        .zip(children[idx].key().iter())
-        .take_while(|(a, b)| a == b)
-        .count();
-
-    (common_prefix_len, idx)
+    (
+        bytes::common_prefix_len(key, node.children()[idx].key()),
+        idx,
+    )
 }
 
 #[cfg(test)]
@@ -37,44 +57,38 @@ mod tests {
 
     #[test]
     fn test_longest_common_prefix() {
-        let mut node: node::Node<()> = node::Node::new("".as_bytes());
-
-        node.push_child(node::Node::new("abb;0".as_bytes()));
-        node.push_child(node::Node::new("cde;1".as_bytes()));
-        node.push_child(node::Node::new("fgh;2".as_bytes()));
-        node.push_child(node::Node::new("ijk;3".as_bytes()));
+        let mut node: node::Node<()> = node::Node::new(&allocator::Global, "".as_bytes());
 
-        assert_eq!(
-            longest_common_prefix(node.children(), "abb;1".as_bytes()),
-            (4, 0)
+        node.push_child(
+            &allocator::Global,
+            growth::GrowthPolicy::Exact,
+            node::Node::new(&allocator::Global, "abb;0".as_bytes()),
         );
-        assert_eq!(
-            longest_common_prefix(node.children(), "abb;0123".as_bytes()),
-            (5, 0)
+        node.push_child(
+            &allocator::Global,
+            growth::GrowthPolicy::Exact,
+            node::Node::new(&allocator::Global, "cde;1".as_bytes()),
         );
-        assert_eq!(
-            longest_common_prefix(node.children(), "fg".as_bytes()),
-            (2, 2)
+        node.push_child(
+            &allocator::Global,
+            growth::GrowthPolicy::Exact,
+            node::Node::new(&allocator::Global, "fgh;2".as_bytes()),
         );
-        assert_eq!(
-            longest_common_prefix(node.children(), "ijk;2".as_bytes()),
-            (4, 3)
-        );
-        assert_eq!(
-            longest_common_prefix(node.children(), "ijk;3ab".as_bytes()),
-            (5, 3)
-        );
-        assert_eq!(
-            longest_common_prefix(node.children(), "i".as_bytes()),
-            (1, 3)
-        );
-        assert_eq!(
-            longest_common_prefix(node.children(), "lmo".as_bytes()),
-            (0, 4)
-        );
-        assert_eq!(
-            longest_common_prefix(node.children(), "bar".as_bytes()),
-            (0, 1)
+        node.push_child(
+            &allocator::Global,
+            growth::GrowthPolicy::Exact,
+            node::Node::new(&allocator::Global, "ijk;3".as_bytes()),
         );
+
+        assert_eq!(longest_common_prefix(&node, "abb;1".as_bytes()), (4, 0));
+        assert_eq!(longest_common_prefix(&node, "abb;0123".as_bytes()), (5, 0));
+        assert_eq!(longest_common_prefix(&node, "fg".as_bytes()), (2, 2));
+        assert_eq!(longest_common_prefix(&node, "ijk;2".as_bytes()), (4, 3));
+        assert_eq!(longest_common_prefix(&node, "ijk;3ab".as_bytes()), (5, 3));
+        assert_eq!(longest_common_prefix(&node, "i".as_bytes()), (1, 3));
+        assert_eq!(longest_common_prefix(&node, "lmo".as_bytes()), (0, 4));
+        assert_eq!(longest_common_prefix(&node, "bar".as_bytes()), (0, 1));
+
+        node.drop_in(&allocator::Global);
     }
 }
@@ -0,0 +1,229 @@
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::neighbor::ceiling;
+use crate::node::Node;
+
+/// A page of entries returned by [`RadixMap::list()`](crate::RadixMap::list).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Listing<'a, T> {
+    /// Entries whose keys have no delimiter after the prefix, sorted by key.
+    pub entries: Vec<(Box<[u8]>, &'a T)>,
+    /// Distinct keys up to and including the first delimiter after the
+    /// prefix, sorted. Each stands for all entries whose keys start with it.
+    pub common_prefixes: Vec<Box<[u8]>>,
+    /// The last key or common prefix listed if the listing was cut short by
+    /// `max_keys`. Pass it as `start_after` to get the next page.
+    pub continuation: Option<Box<[u8]>>,
+}
+
+/// Returns the position of the first occurrence of `needle` in `haystack`.
+#[inline]
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// Returns the smallest key greater than every key that starts with
+/// `prefix`, or `None` if there is none.
+fn successor(prefix: &[u8]) -> Option<Vec<u8>> {
+    let last = prefix.iter().rposition(|&byte| byte != u8::MAX)?;
+    let mut key = prefix[..=last].to_vec();
+    key[last] += 1;
+    Some(key)
+}
+
+/// Lists the entries and common prefixes below `prefix` that sort after
+/// `start_after`.
+///
+/// Every item is found by a ceiling lookup from the root. After a common
+/// prefix is listed the lookup continues from the first key past all keys
+/// that start with it, so the entries it stands for are never visited.
+pub(crate) fn list<'a, T, U>(
+    root: &'a Node<T, U>,
+    prefix: &[u8],
+    delimiter: &[u8],
+    start_after: Option<&[u8]>,
+    max_keys: usize,
+) -> Listing<'a, T> {
+    let mut listing = Listing {
+        entries: vec![],
+        common_prefixes: vec![],
+        continuation: None,
+    };
+    let mut next = match start_after {
+        Some(start_after) if start_after >= prefix => ceiling(root, start_after, false),
+        _ => ceiling(root, prefix, true),
+    };
+    let mut listed = 0;
+    let mut truncated = false;
+    while let Some((key, value)) = next.take().filter(|(key, _)| key.starts_with(prefix)) {
+        let group_len = match delimiter.is_empty() {
+            true => None,
+            false => find(&key[prefix.len()..], delimiter),
+        };
+        let Some(group_len) = group_len else {
+            if listed == max_keys {
+                truncated = true;
+                break;
+            }
+            next = ceiling(root, &key, false);
+            listing.entries.push((key, value));
+            listed += 1;
+            continue;
+        };
+
+        let common_prefix = &key[..prefix.len() + group_len + delimiter.len()];
+        // A common prefix that `start_after` falls into was listed before.
+        if start_after.is_none_or(|start_after| common_prefix > start_after) {
+            if listed == max_keys {
+                truncated = true;
+                break;
+            }
+            listing.common_prefixes.push(common_prefix.into());
+            listed += 1;
+        }
+        next = successor(common_prefix).and_then(|key| ceiling(root, &key, true));
+    }
+
+    if truncated {
+        // Entries are listed before the common prefixes that follow them, so
+        // the larger of the last ones is the last item listed.
+        let entry = listing.entries.last().map(|(key, _)| key);
+        listing.continuation = entry.max(listing.common_prefixes.last()).cloned();
+    }
+    listing
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::BTreeSet;
+
+    use crate::map::RadixMap;
+
+    fn populated_map() -> RadixMap<usize> {
+        [
+            &b"a.txt"[..],
+            b"photos/",
+            b"photos/2005/a.jpg",
+            b"photos/2005/b.jpg",
+            b"photos/2006/a.jpg",
+            b"photos/2006/jan/b.jpg",
+            b"photos/index.html",
+            b"photos/\xff/x",
+            b"photos/\xff\xff/y",
+            b"z",
+        ]
+        .into_iter()
+        .enumerate()
+        .map(|(i, key)| (key, i))
+        .collect()
+    }
+
+    /// Lists by scanning every entry, for comparison.
+    fn expected<'a>(
+        map: &'a RadixMap<usize>,
+        prefix: &[u8],
+        delimiter: &[u8],
+        start_after: Option<&[u8]>,
+    ) -> Listing<'a, usize> {
+        let mut entries = vec![];
+        let mut common_prefixes = BTreeSet::new();
+        for (key, value) in map.iter().filter(|(key, _)| key.starts_with(prefix)) {
+            let group = match delimiter.is_empty() {
+                true => None,
+                false => find(&key[prefix.len()..], delimiter),
+            };
+            match group {
+                Some(len) => {
+                    let common_prefix = &key[..prefix.len() + len + delimiter.len()];
+                    if start_after.is_none_or(|start_after| common_prefix > start_after) {
+                        common_prefixes.insert(common_prefix.into());
+                    }
+                }
+                None if start_after.is_none_or(|start_after| &key[..] > start_after) => {
+                    entries.push((key, value))
+                }
+                None => {}
+            }
+        }
+        Listing {
+            entries,
+            common_prefixes: common_prefixes.into_iter().collect(),
+            continuation: None,
+        }
+    }
+
+    #[test]
+    fn test_list() {
+        let map = populated_map();
+
+        let listing = map.list("photos/", "/", None, 100);
+        assert_eq!(
+            listing.entries,
+            [
+                (b"photos/"[..].into(), &1),
+                (b"photos/index.html"[..].into(), &6)
+            ]
+        );
+        assert_eq!(
+            listing.common_prefixes,
+            [
+                &b"photos/2005/"[..],
+                b"photos/2006/",
+                b"photos/\xff/",
+                b"photos/\xff\xff/",
+            ]
+            .map(Box::from)
+        );
+        assert_eq!(listing.continuation, None);
+
+        let listing = map.list("", "/", None, 100);
+        assert_eq!(listing.entries.len(), 2);
+        assert_eq!(listing.common_prefixes, [b"photos/"[..].into()]);
+
+        let listing = map.list("photos/2006/", "", None, 100);
+        assert_eq!(listing.entries.len(), 2);
+        assert!(listing.common_prefixes.is_empty());
+
+        let listing = map.list("photos/", "/", Some(b"photos/2005/a.jpg"), 100);
+        assert_eq!(listing.common_prefixes[0], b"photos/2006/"[..].into());
+        assert_eq!(map.list("photos/", "/", None, 0).entries, []);
+        assert_eq!(map.list("q", "/", None, 10).entries, []);
+    }
+
+    #[test]
+    fn test_list_pages() {
+        let map = populated_map();
+        for (prefix, delimiter) in [("", "/"), ("photos/", "/"), ("photos/", "jpg"), ("", "")] {
+            let expected = expected(&map, prefix.as_bytes(), delimiter.as_bytes(), None);
+            for max_keys in 1..5 {
+                let mut listed = Listing {
+                    entries: vec![],
+                    common_prefixes: vec![],
+                    continuation: None,
+                };
+                let mut start_after: Option<Box<[u8]>> = None;
+                loop {
+                    let listing = map.list(prefix, delimiter, start_after.as_deref(), max_keys);
+                    assert!(listing.entries.len() + listing.common_prefixes.len() <= max_keys);
+                    listed.entries.extend(listing.entries);
+                    listed.common_prefixes.extend(listing.common_prefixes);
+                    match listing.continuation {
+                        Some(continuation) => start_after = Some(continuation),
+                        None => break,
+                    }
+                }
+                assert_eq!(listed, expected);
+            }
+        }
+
+        let start_after = b"photos/2005/b.jpg";
+        let listing = map.list("photos/", "/", Some(start_after), 100);
+        assert_eq!(listing, expected(&map, b"photos/", b"/", Some(start_after)));
+    }
+}
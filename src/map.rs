@@ -1,31 +1,246 @@
-use std::ops::RangeBounds;
-
+use alloc::boxed::Box;
+use alloc::vec;
+use core::mem::{self, ManuallyDrop};
+use core::ops::RangeBounds;
+use core::ptr;
+
+use crate::allocator::{Budget, Global, NodeAllocator};
+use crate::automaton::{Automaton, Search};
+use crate::builder::SortedBuilder;
+use crate::error::RadixError;
+use crate::fuzzy::{Fuzzy, FuzzySearch};
+use crate::growth::GrowthPolicy;
+use crate::hamming::{HammingNearest, HammingSearch};
 use crate::iter::{
     IntoIter, Iter, IterMap, IterMapMut, IterMut, MapK, MapKV, MapKVMut, MapV, MapVMut, Range,
     RangeMut,
 };
+use crate::list::{self, Listing};
+use crate::neighbor;
 use crate::node::Node;
+use crate::stats::TreeStats;
+use crate::summary::{range_summary, Contains, Count, MaxScore, Summary, TopK, ValueMut};
 
 #[derive(Debug)]
-pub struct RadixMap<T> {
-    root: Node<T>,
+pub struct RadixMap<T, A: NodeAllocator = Global, S: Summary<T> = ()> {
+    root: Node<T, S::Output>,
     size: usize,
+    alloc: A,
+    policy: GrowthPolicy,
+    memory_used: usize,
+    memory_limit: Option<usize>,
+    summary: S,
 }
 
-impl<T> Default for RadixMap<T> {
+impl<T, A: NodeAllocator + Default, S: Summary<T> + Default> Default for RadixMap<T, A, S> {
     fn default() -> Self {
-        RadixMap::new()
+        RadixMap::with_summary_in(S::default(), A::default())
     }
 }
 
 impl<T> RadixMap<T> {
     pub fn new() -> Self {
+        RadixMap::new_in(Global)
+    }
+
+    /// Creates an empty map whose children arrays grow according to `policy`.
+    pub fn with_growth_policy(policy: GrowthPolicy) -> Self {
+        RadixMap::with_growth_policy_in(policy, Global)
+    }
+
+    /// Creates an empty map that allocates at most `limit` bytes for its
+    /// nodes and values. Inserts that would exceed the limit fail with
+    /// [`RadixError::CapacityExceeded`].
+    pub fn with_memory_limit(limit: usize) -> Self {
+        RadixMap::with_memory_limit_in(limit, Global)
+    }
+
+    /// Creates a map from an iterator of key-value pairs sorted by key in
+    /// ascending order.
+    ///
+    /// Unlike [`Self::insert()`] this allocates every node exactly once. If a key
+    /// occurs more than once the last value wins.
+    ///
+    /// Returns [`RadixError::UnsortedKeys`] if the keys are not sorted.
+    pub fn from_sorted_iter<K: AsRef<[u8]>, I: IntoIterator<Item = (K, T)>>(
+        iter: I,
+    ) -> Result<Self, RadixError> {
+        RadixMap::from_sorted_iter_in(iter, Global)
+    }
+}
+
+impl<T, S: Summary<T>> RadixMap<T, Global, S> {
+    /// Creates an empty map that caches the summary of every subtree.
+    pub fn with_summary(summary: S) -> Self {
+        RadixMap::with_summary_in(summary, Global)
+    }
+}
+
+impl<T, F: Fn(&T) -> K, K: Ord + Copy> RadixMap<T, Global, MaxScore<F>> {
+    /// Creates an empty map that ranks its values by `score`, for use with
+    /// [`Self::top_k()`].
+    pub fn with_score(score: F) -> Self {
+        RadixMap::with_score_in(score, Global)
+    }
+}
+
+impl<T, A: NodeAllocator> RadixMap<T, A> {
+    /// Creates an empty map whose nodes are allocated by `alloc`.
+    pub fn new_in(alloc: A) -> Self {
+        RadixMap::with_summary_in((), alloc)
+    }
+
+    /// Same as [`RadixMap::with_growth_policy()`] but the nodes are allocated
+    /// by `alloc`.
+    pub fn with_growth_policy_in(policy: GrowthPolicy, alloc: A) -> Self {
+        let mut map = RadixMap::new_in(alloc);
+        map.policy = policy;
+        map
+    }
+
+    /// Same as [`RadixMap::with_memory_limit()`] but the nodes are allocated
+    /// by `alloc`.
+    pub fn with_memory_limit_in(limit: usize, alloc: A) -> Self {
+        let mut map = RadixMap::new_in(alloc);
+        map.memory_limit = Some(limit);
+        map
+    }
+
+    /// Same as [`RadixMap::from_sorted_iter()`] but the nodes are allocated
+    /// by `alloc`.
+    pub fn from_sorted_iter_in<K: AsRef<[u8]>, I: IntoIterator<Item = (K, T)>>(
+        iter: I,
+        alloc: A,
+    ) -> Result<Self, RadixError> {
+        let mut map = RadixMap::new_in(alloc);
+        let mut iter = iter.into_iter();
+        if map.extend_sorted(&mut iter)?.is_some() {
+            return Err(RadixError::UnsortedKeys);
+        }
+        Ok(map)
+    }
+
+    // Values can only be mutated in place in maps without summaries, whose
+    // cached summaries would go stale otherwise. Other maps use
+    // `get_mut_guarded()`.
+
+    /// Returns a mutable reference to the value corresponding to the key.
+    #[inline(always)]
+    pub fn get_mut<K: AsRef<[u8]>>(&mut self, key: K) -> Option<&mut T> {
+        self.root.get_mut(key.as_ref())
+    }
+
+    /// Gets a mutable iterator over the entries of the map, sorted by key.
+    /// This iterator allocates a boxed slice for each item. If you only
+    /// need to access values consider using [`Self::values_mut()`] instead.
+    #[inline(always)]
+    pub fn iter_mut(&mut self) -> IterMut<'_, T, MapKVMut<'_, T>> {
+        self.get_iter_mut()
+    }
+
+    /// Gets a mutable iterator over the entries of the map matching a given prefix, sorted by key.
+    ///
+    /// This iterator allocates a boxed slice for each item. If you only
+    /// need to access values consider using [`Self::prefix_values_mut()`] instead.
+    #[inline(always)]
+    pub fn prefix_iter_mut<K: AsRef<[u8]>>(
+        &mut self,
+        prefix: K,
+    ) -> IterMut<'_, T, MapKVMut<'_, T>> {
+        self.get_prefix_iter_mut(prefix)
+    }
+
+    /// Gets a mutable iterator over the values of the map, in order by key.
+    #[inline(always)]
+    pub fn values_mut(&mut self) -> IterMut<'_, T, MapVMut<'_, T>> {
+        self.get_iter_mut()
+    }
+
+    /// Gets a mutable iterator over the values of the map matching a given prefix, in order by key.
+    #[inline(always)]
+    pub fn prefix_values_mut<K: AsRef<[u8]>>(
+        &mut self,
+        prefix: K,
+    ) -> IterMut<'_, T, MapVMut<'_, T>> {
+        self.get_prefix_iter_mut(prefix)
+    }
+
+    /// Constructs a mutable iterator over a sub-range of elements in the map. The simplest
+    /// way is to use the range syntax `min..max`, thus `range(min..max)` will yield elements from min
+    /// (inclusive) to max (exclusive). The range may also be entered as `(Bound<T>, Bound<T>)`.
+    #[inline(always)]
+    pub fn range_mut<K: AsRef<[u8]>, B: RangeBounds<K>>(
+        &mut self,
+        bounds: B,
+    ) -> RangeMut<'_, T, K, B> {
+        RangeMut::new(self.get_iter_mut(), bounds)
+    }
+
+    fn get_iter_mut<'a, M: IterMapMut<'a, T>>(&'a mut self) -> IterMut<'a, T, M> {
+        IterMut::new(Some(&mut self.root), vec![])
+    }
+
+    fn get_prefix_iter_mut<'a, M: IterMapMut<'a, T>, K: AsRef<[u8]>>(
+        &'a mut self,
+        prefix: K,
+    ) -> IterMut<'a, T, M> {
+        match self.root.find_prefix_mut(prefix.as_ref()) {
+            Some((prefix_len, prefix_node)) => {
+                IterMut::new(Some(prefix_node), prefix.as_ref()[..prefix_len].to_vec())
+            }
+            None => IterMut::new(None, vec![]),
+        }
+    }
+}
+
+impl<T, A: NodeAllocator, S: Summary<T>> RadixMap<T, A, S> {
+    /// Same as [`RadixMap::with_summary()`] but the nodes are allocated by
+    /// `alloc`.
+    pub fn with_summary_in(summary: S, alloc: A) -> Self {
+        let mut memory_used = 0;
+        let root = Node::new(&Budget::new(&alloc, &mut memory_used, None), &[]);
         RadixMap {
-            root: Node::new(&[]),
+            root,
             size: 0,
+            alloc,
+            policy: GrowthPolicy::default(),
+            memory_used,
+            memory_limit: None,
+            summary,
         }
     }
 
+    /// Returns the summary the map caches for its subtrees.
+    #[inline(always)]
+    pub fn summary(&self) -> &S {
+        &self.summary
+    }
+
+    /// Returns a reference to the map's allocator.
+    #[inline(always)]
+    pub fn allocator(&self) -> &A {
+        &self.alloc
+    }
+
+    /// Returns the growth policy of the map's children arrays.
+    #[inline(always)]
+    pub fn growth_policy(&self) -> GrowthPolicy {
+        self.policy
+    }
+
+    /// Returns the memory limit of the map, if any.
+    #[inline(always)]
+    pub fn memory_limit(&self) -> Option<usize> {
+        self.memory_limit
+    }
+
+    /// Returns the number of bytes the map has allocated for its nodes and
+    /// values. This is what the memory limit is checked against.
+    #[inline(always)]
+    pub fn memory_usage(&self) -> usize {
+        self.memory_used
+    }
+
     /// Returns the number of elements in the map.
     #[inline(always)]
     pub fn len(&self) -> usize {
@@ -38,26 +253,109 @@ impl<T> RadixMap<T> {
         self.len() == 0
     }
 
+    /// Clears the map, removing all key-value pairs.
+    ///
+    /// If the values have no drop glue and the allocator supports releasing
+    /// all of its blocks at once (e.g. [`crate::SlabAllocator`]), this takes
+    /// constant time.
+    pub fn clear(&mut self) {
+        if !mem::needs_drop::<T>() && self.alloc.reset() {
+            // All nodes were released at once
+            self.memory_used = 0;
+            let alloc = Budget::new(&self.alloc, &mut self.memory_used, None);
+            unsafe { ptr::write(&mut self.root, Node::new(&alloc, &[])) };
+            self.size = 0;
+            return;
+        }
+        // The map is emptied before the values are dropped so that it stays
+        // valid if a destructor panics.
+        let alloc = Budget::new(&self.alloc, &mut self.memory_used, None);
+        let root = mem::replace(&mut self.root, Node::new(&alloc, &[]));
+        self.size = 0;
+        root.drop_in(&alloc);
+    }
+
+    /// Releases the spare capacity of all children arrays.
+    ///
+    /// Only children arrays are over-allocated, nodes are still reallocated
+    /// whenever their keys or values change.
+    pub fn shrink_to_fit(&mut self) {
+        let alloc = Budget::new(&self.alloc, &mut self.memory_used, None);
+        self.root.shrink_to_fit(&alloc);
+    }
+
     /// Inserts a key-value pair into the map.
     ///
     /// If the map did not have this key present, None is returned.
     ///
     /// If the map did have this key present, the value is updated, and the old
     /// value is returned.
+    ///
+    /// # Panics
+    ///
+    /// Panics if memory can't be allocated or the insert would exceed the
+    /// memory limit. See [`Self::try_insert()`] for a fallible version.
     #[inline(always)]
     pub fn insert<K: AsRef<[u8]>>(&mut self, key: K, value: T) -> Option<T> {
-        let old = self.root.insert(key.as_ref(), value);
+        match self.try_insert(key, value) {
+            Ok(old) => old,
+            Err(err) => panic!("{}", err),
+        }
+    }
+
+    /// Inserts a key-value pair into the map like [`Self::insert()`], but
+    /// returns an error instead of panicking if memory can't be allocated.
+    ///
+    /// Returns [`RadixError::CapacityExceeded`] if the insert would exceed the
+    /// memory limit and [`RadixError::AllocError`] if the allocator fails.
+    /// The map is left unchanged and the value is dropped in both cases.
+    #[inline]
+    pub fn try_insert<K: AsRef<[u8]>>(
+        &mut self,
+        key: K,
+        value: T,
+    ) -> Result<Option<T>, RadixError> {
+        let alloc = Budget::new(&self.alloc, &mut self.memory_used, self.memory_limit);
+        let old = self
+            .root
+            .try_insert(&alloc, self.policy, key.as_ref(), value)
+            .map_err(|err| match alloc.exceeded() {
+                true => RadixError::CapacityExceeded,
+                false => err,
+            })?;
         self.size += old.is_none() as usize;
-        old
+        Self::update_summaries(&mut self.root, &self.summary, key.as_ref());
+        Ok(old)
+    }
+
+    /// Inserts the items of an iterator one by one like
+    /// [`Self::try_insert()`]. Stops at the first item that can't be inserted
+    /// and returns its error. The items inserted before it stay in the map.
+    ///
+    /// Unlike [`Extend::extend()`] this never builds an empty map bottom-up.
+    pub fn try_extend<K: AsRef<[u8]>, I: IntoIterator<Item = (K, T)>>(
+        &mut self,
+        iter: I,
+    ) -> Result<(), RadixError> {
+        for (key, value) in iter {
+            self.try_insert(key, value)?;
+        }
+        Ok(())
     }
 
     /// Removes a key from the map, returning the value at the key if the key
     /// was previously in the map.
     #[inline(always)]
     pub fn remove<K: AsRef<[u8]>>(&mut self, key: K) -> Option<T> {
-        let removed = self.root.remove(key.as_ref());
-        self.size -= removed.is_some() as usize;
-        removed
+        let key = key.as_ref();
+        let alloc = Budget::new(&self.alloc, &mut self.memory_used, None);
+        let removed = self.root.take(&alloc, key)?;
+        // The key is counted out before the tree is compacted, which
+        // allocates.
+        self.size -= 1;
+        self.root.compact(&alloc, self.policy, key);
+        Self::update_summaries(&mut self.root, &self.summary, key);
+        Some(removed)
     }
 
     /// Returns a reference to the value corresponding to the key.
@@ -66,10 +364,17 @@ impl<T> RadixMap<T> {
         self.root.get(key.as_ref())
     }
 
-    /// Returns a mutable reference to the value corresponding to the key.
+    /// Returns a guard that mutably dereferences to the value corresponding
+    /// to the key.
+    ///
+    /// The cached summaries on the path to the key are recomputed when the
+    /// guard is dropped. Maps without a summary can use [`Self::get_mut()`]
+    /// instead.
     #[inline(always)]
-    pub fn get_mut<K: AsRef<[u8]>>(&mut self, key: K) -> Option<&mut T> {
-        self.root.get_mut(key.as_ref())
+    pub fn get_mut_guarded<K: AsRef<[u8]>>(&mut self, key: K) -> Option<ValueMut<'_, T, S>> {
+        let key = key.as_ref();
+        let value: *mut T = self.root.get_mut(key)?;
+        Some(ValueMut::new(&mut self.root, &self.summary, value, key))
     }
 
     /// Returns `true` if this map contains a value for the specified key.
@@ -78,73 +383,148 @@ impl<T> RadixMap<T> {
         self.get(key).is_some()
     }
 
+    /// Returns the entry with the largest key smaller than or equal to `key`.
+    #[inline(always)]
+    pub fn floor<K: AsRef<[u8]>>(&self, key: K) -> Option<(Box<[u8]>, &T)> {
+        neighbor::floor(&self.root, key.as_ref(), true)
+    }
+
+    /// Returns the entry with the smallest key greater than or equal to
+    /// `key`.
+    #[inline(always)]
+    pub fn ceiling<K: AsRef<[u8]>>(&self, key: K) -> Option<(Box<[u8]>, &T)> {
+        neighbor::ceiling(&self.root, key.as_ref(), true)
+    }
+
+    /// Returns the entry with the largest key strictly smaller than `key`.
+    #[inline(always)]
+    pub fn lower<K: AsRef<[u8]>>(&self, key: K) -> Option<(Box<[u8]>, &T)> {
+        neighbor::floor(&self.root, key.as_ref(), false)
+    }
+
+    /// Returns the entry with the smallest key strictly greater than `key`.
+    #[inline(always)]
+    pub fn higher<K: AsRef<[u8]>>(&self, key: K) -> Option<(Box<[u8]>, &T)> {
+        neighbor::ceiling(&self.root, key.as_ref(), false)
+    }
+
     /// Gets an iterator over the entries of the map, sorted by key.
     /// This iterator allocates a boxed slice for each item. If you
     /// only need to access values consider using [`Self::values()`] instead.
     #[inline(always)]
-    pub fn iter(&self) -> Iter<T, MapKV<T>> {
+    pub fn iter(&self) -> Iter<'_, T, MapKV<'_, T>, S::Output> {
         self.get_iter()
     }
 
-    /// Gets a mutable iterator over the entries of the map, sorted by key.
-    /// This iterator allocates a boxed slice for each item. If you only
-    /// need to access values consider using [`Self::values_mut()`] instead.
-    #[inline(always)]
-    pub fn iter_mut(&mut self) -> IterMut<T, MapKVMut<T>> {
-        self.get_iter_mut()
-    }
-
     /// Gets an iterator over the entries of the map matching a given prefix, sorted by key.
     ///
     /// This iterator allocates a boxed slice for each item. If you only
     /// need to access values consider using [`Self::prefix_values()`] instead.
     #[inline(always)]
-    pub fn prefix_iter<K: AsRef<[u8]>>(&self, prefix: K) -> Iter<T, MapKV<T>> {
+    pub fn prefix_iter<K: AsRef<[u8]>>(&self, prefix: K) -> Iter<'_, T, MapKV<'_, T>, S::Output> {
         self.get_prefix_iter(prefix)
     }
 
-    /// Gets a mutable iterator over the entries of the map matching a given prefix, sorted by key.
+    /// Lists up to `max_keys` entries whose keys start with `prefix` and sort
+    /// after `start_after`, like S3's ListObjects.
     ///
-    /// This iterator allocates a boxed slice for each item. If you only
-    /// need to access values consider using [`Self::prefix_values_mut()`] instead.
+    /// Keys that contain `delimiter` after the prefix are rolled up into a
+    /// common prefix that ends with the delimiter and counts as one key. The
+    /// entries below a common prefix are skipped without being visited. An
+    /// empty delimiter rolls nothing up. A common prefix is only listed if it
+    /// sorts after `start_after`, so a continuation token never lists the
+    /// same common prefix twice.
+    pub fn list<K: AsRef<[u8]>, D: AsRef<[u8]>>(
+        &self,
+        prefix: K,
+        delimiter: D,
+        start_after: Option<&[u8]>,
+        max_keys: usize,
+    ) -> Listing<'_, T> {
+        list::list(
+            &self.root,
+            prefix.as_ref(),
+            delimiter.as_ref(),
+            start_after,
+            max_keys,
+        )
+    }
+
+    /// Gets an iterator over the entries whose key is within `max_distance`
+    /// edits of `query`, sorted by key. Each item holds the key, its
+    /// Levenshtein distance to the query and the value.
     #[inline(always)]
-    pub fn prefix_iter_mut<K: AsRef<[u8]>>(&mut self, prefix: K) -> IterMut<T, MapKVMut<T>> {
-        self.get_prefix_iter_mut(prefix)
+    pub fn fuzzy_search<K: AsRef<[u8]>>(
+        &self,
+        query: K,
+        max_distance: usize,
+    ) -> FuzzySearch<'_, T, S::Output> {
+        self.fuzzy_search_with(query, Fuzzy::new(max_distance))
     }
 
-    /// Gets an iterator over the values of the map, in order by key.
+    /// Same as [`Self::fuzzy_search()`] with the distance options of `fuzzy`.
     #[inline(always)]
-    pub fn values(&self) -> Iter<T, MapV<T>> {
-        self.get_iter()
+    pub fn fuzzy_search_with<K: AsRef<[u8]>>(
+        &self,
+        query: K,
+        fuzzy: Fuzzy,
+    ) -> FuzzySearch<'_, T, S::Output> {
+        FuzzySearch::new(&self.root, query.as_ref(), fuzzy)
     }
 
-    /// Gets a mutable iterator over the values of the map, in order by key.
+    /// Gets an iterator over the entries whose key matches `aut`, sorted by
+    /// key. Subtrees are skipped once the automaton can't match anymore.
+    /// Pass a [`Regex`](crate::Regex) to search by regular expression.
     #[inline(always)]
-    pub fn values_mut(&mut self) -> IterMut<T, MapVMut<T>> {
-        self.get_iter_mut()
+    pub fn search<M: Automaton>(&self, aut: M) -> Search<'_, T, M, S::Output> {
+        Search::new(&self.root, aut)
     }
 
-    /// Gets an iterator over the values of the map matching a given prefix, in order by key.
+    /// Gets an iterator over the entries whose key has the length of `query`
+    /// and differs from it in at most `max_bits` bits, sorted by key. Each
+    /// item holds the key, its Hamming distance to the query and the value.
     #[inline(always)]
-    pub fn prefix_values<K: AsRef<[u8]>>(&self, prefix: K) -> Iter<T, MapV<T>> {
-        self.get_prefix_iter(prefix)
+    pub fn hamming_search<K: AsRef<[u8]>>(
+        &self,
+        query: K,
+        max_bits: u32,
+    ) -> HammingSearch<'_, T, S::Output> {
+        HammingSearch::new(&self.root, query.as_ref(), max_bits)
     }
 
-    /// Gets a mutable iterator over the values of the map matching a given prefix, in order by key.
+    /// Gets an iterator over the `k` entries whose key has the length of
+    /// `query` and the smallest Hamming distance to it, ordered by distance
+    /// and then by key.
     #[inline(always)]
-    pub fn prefix_values_mut<K: AsRef<[u8]>>(&mut self, prefix: K) -> IterMut<T, MapVMut<T>> {
-        self.get_prefix_iter_mut(prefix)
+    pub fn hamming_nearest<K: AsRef<[u8]>>(
+        &self,
+        query: K,
+        k: usize,
+    ) -> HammingNearest<'_, T, S::Output> {
+        HammingNearest::new(&self.root, query.as_ref(), k)
+    }
+
+    /// Gets an iterator over the values of the map, in order by key.
+    #[inline(always)]
+    pub fn values(&self) -> Iter<'_, T, MapV<'_, T>, S::Output> {
+        self.get_iter()
+    }
+
+    /// Gets an iterator over the values of the map matching a given prefix, in order by key.
+    #[inline(always)]
+    pub fn prefix_values<K: AsRef<[u8]>>(&self, prefix: K) -> Iter<'_, T, MapV<'_, T>, S::Output> {
+        self.get_prefix_iter(prefix)
     }
 
     /// Gets an iterator over the keys of the map, in order by key.
     #[inline(always)]
-    pub fn keys(&self) -> Iter<T, MapK<T>> {
+    pub fn keys(&self) -> Iter<'_, T, MapK<'_, T>, S::Output> {
         self.get_iter()
     }
 
     /// Gets an iterator over the keys of the map matching a given prefix, in order by key.
     #[inline(always)]
-    pub fn prefix_keys<K: AsRef<[u8]>>(&self, prefix: K) -> Iter<T, MapK<T>> {
+    pub fn prefix_keys<K: AsRef<[u8]>>(&self, prefix: K) -> Iter<'_, T, MapK<'_, T>, S::Output> {
         self.get_prefix_iter(prefix)
     }
 
@@ -152,30 +532,56 @@ impl<T> RadixMap<T> {
     /// way is to use the range syntax `min..max`, thus `range(min..max)` will yield elements from min
     /// (inclusive) to max (exclusive). The range may also be entered as `(Bound<T>, Bound<T>)`.
     #[inline(always)]
-    pub fn range<K: AsRef<[u8]>, B: RangeBounds<K>>(&self, bounds: B) -> Range<T, K, B> {
+    pub fn range<K: AsRef<[u8]>, B: RangeBounds<K>>(
+        &self,
+        bounds: B,
+    ) -> Range<'_, T, K, B, S::Output> {
         Range::new(self.get_iter(), bounds)
     }
 
-    /// Constructs a mutable iterator over a sub-range of elements in the map. The simplest
-    /// way is to use the range syntax `min..max`, thus `range(min..max)` will yield elements from min
-    /// (inclusive) to max (exclusive). The range may also be entered as `(Bound<T>, Bound<T>)`.
-    #[inline(always)]
-    pub fn range_mut<K: AsRef<[u8]>, B: RangeBounds<K>>(&mut self, bounds: B) -> RangeMut<T, K, B> {
-        RangeMut::new(self.get_iter_mut(), bounds)
+    /// Returns the summary of the values whose keys start with `prefix`, or
+    /// `None` if there are none. Takes time proportional to the length of the
+    /// prefix since every subtree caches its summary.
+    pub fn summary_prefix<K: AsRef<[u8]>>(&self, prefix: K) -> Option<S::Output> {
+        let prefix = prefix.as_ref();
+        if prefix.is_empty() {
+            return self.root.summary(&self.summary);
+        }
+        let (_, node) = self.root.find_prefix(prefix)?;
+        node.summary(&self.summary)
     }
 
-    fn get_iter<'a, M: IterMap<'a, T>>(&'a self) -> Iter<'a, T, M> {
-        Iter::new(Some(&self.root), vec![])
+    /// Returns the summary of the values whose keys are within `bounds`, or
+    /// `None` if there are none. Only the nodes on the paths to both ends of
+    /// the range and their children are visited, the subtrees between them
+    /// contribute their cached summaries.
+    pub fn summary_range<K: AsRef<[u8]>, B: RangeBounds<K>>(&self, bounds: B) -> Option<S::Output> {
+        range_summary(&self.root, &self.summary, &bounds)
     }
 
-    fn get_iter_mut<'a, M: IterMapMut<'a, T>>(&'a mut self) -> IterMut<'a, T, M> {
-        IterMut::new(Some(&mut self.root), vec![])
+    /// Returns memory and shape statistics of the map.
+    pub fn stats(&self) -> TreeStats {
+        TreeStats::new(Some(&self.root))
+    }
+
+    /// Returns memory and shape statistics of the subtree holding all entries
+    /// whose keys start with a given prefix.
+    pub fn prefix_stats<K: AsRef<[u8]>>(&self, prefix: K) -> TreeStats {
+        let prefix = prefix.as_ref();
+        if prefix.is_empty() {
+            return self.stats();
+        }
+        TreeStats::new(self.root.find_prefix(prefix).map(|(_, node)| node))
+    }
+
+    fn get_iter<'a, M: IterMap<'a, T>>(&'a self) -> Iter<'a, T, M, S::Output> {
+        Iter::new(Some(&self.root), vec![])
     }
 
     fn get_prefix_iter<'a, M: IterMap<'a, T>, K: AsRef<[u8]>>(
         &'a self,
         prefix: K,
-    ) -> Iter<'a, T, M> {
+    ) -> Iter<'a, T, M, S::Output> {
         match self.root.find_prefix(prefix.as_ref()) {
             Some((prefix_len, prefix_node)) => {
                 Iter::new(Some(prefix_node), prefix.as_ref()[..prefix_len].to_vec())
@@ -184,59 +590,251 @@ impl<T> RadixMap<T> {
         }
     }
 
-    fn get_prefix_iter_mut<'a, M: IterMapMut<'a, T>, K: AsRef<[u8]>>(
-        &'a mut self,
-        prefix: K,
-    ) -> IterMut<'a, T, M> {
-        match self.root.find_prefix_mut(prefix.as_ref()) {
-            Some((prefix_len, prefix_node)) => {
-                IterMut::new(Some(prefix_node), prefix.as_ref()[..prefix_len].to_vec())
+    #[inline(always)]
+    pub(crate) fn root(&self) -> &Node<T, S::Output> {
+        &self.root
+    }
+
+    /// Builds the tree of an empty map bottom-up from the leading items of
+    /// `iter` that are sorted by key. Returns the first item that breaks the
+    /// sort order.
+    ///
+    /// Returns [`RadixError::CapacityExceeded`] if the tree would exceed the
+    /// memory limit and [`RadixError::AllocError`] if the allocator fails.
+    /// The map is left empty in both cases.
+    fn extend_sorted<K: AsRef<[u8]>, I: Iterator<Item = (K, T)>>(
+        &mut self,
+        iter: &mut I,
+    ) -> Result<Option<(K, T)>, RadixError> {
+        assert!(self.is_empty(), "Map must be empty");
+
+        let alloc = Budget::new(&self.alloc, &mut self.memory_used, self.memory_limit);
+        let mut builder = SortedBuilder::new(&alloc);
+        let mut unsorted = None;
+        let mut pushed = Ok(());
+        for (key, value) in iter {
+            if !builder.is_ordered(key.as_ref()) {
+                unsorted = Some((key, value));
+                break;
+            }
+            pushed = builder.push(key.as_ref(), value);
+            if pushed.is_err() {
+                break;
             }
-            None => IterMut::new(None, vec![]),
         }
+
+        let built = pushed.and_then(|()| builder.build());
+        let (root, size) = built.map_err(|err| match alloc.exceeded() {
+            true => RadixError::CapacityExceeded,
+            false => err,
+        })?;
+
+        mem::replace(&mut self.root, root).drop_in(&alloc);
+        self.size = size;
+        if mem::size_of::<S::Output>() != 0 {
+            self.root.update_all_summaries(&self.summary);
+        }
+        Ok(unsorted)
     }
 
+    /// Recomputes the cached summaries on the path to a key that was inserted
+    /// or removed.
     #[inline(always)]
-    pub(crate) fn root(&self) -> &Node<T> {
-        &self.root
+    fn update_summaries(root: &mut Node<T, S::Output>, summary: &S, key: &[u8]) {
+        if mem::size_of::<S::Output>() != 0 {
+            root.update_summaries(summary, key);
+        }
+    }
+}
+
+// Order statistics and top-k completion need particular summaries, so they
+// are available on the maps whose summary contains them, alone or as part of
+// a tuple.
+impl<T, A: NodeAllocator, S: Summary<T>> RadixMap<T, A, S> {
+    /// Returns the number of entries whose keys start with `prefix`.
+    #[inline]
+    pub fn count_prefix<K: AsRef<[u8]>, I>(&self, prefix: K) -> usize
+    where
+        S: Contains<T, Count, I>,
+    {
+        self.summary_prefix(prefix).map_or(0, S::project)
+    }
+
+    /// Returns the number of entries whose keys are within `bounds`.
+    #[inline]
+    pub fn count_range<K: AsRef<[u8]>, B: RangeBounds<K>, I>(&self, bounds: B) -> usize
+    where
+        S: Contains<T, Count, I>,
+    {
+        self.summary_range(bounds).map_or(0, S::project)
+    }
+
+    /// Returns the number of keys smaller than `key`, which is the index of
+    /// `key` in key order if the map contains it.
+    #[inline]
+    pub fn rank<K: AsRef<[u8]>, I>(&self, key: K) -> usize
+    where
+        S: Contains<T, Count, I>,
+    {
+        self.count_range(..key.as_ref())
+    }
+
+    /// Returns the entry with the given index in key order, the inverse of
+    /// [`Self::rank()`].
+    #[inline]
+    pub fn select<I>(&self, index: usize) -> Option<(Box<[u8]>, &T)>
+    where
+        S: Contains<T, Count, I>,
+    {
+        self.iter_from_index(index).next()
+    }
+
+    /// Gets an iterator over the entries of the map starting at the one with
+    /// the given index, sorted by key. Finding the first entry takes time
+    /// proportional to the depth of the tree times the number of children of
+    /// the nodes on the way.
+    pub fn iter_from_index<I>(&self, index: usize) -> Iter<'_, T, MapKV<'_, T>, S::Output>
+    where
+        S: Contains<T, Count, I>,
+    {
+        Iter::from_index(&self.root, &self.summary, index)
+    }
+
+    /// Gets an iterator over the `k` entries with the highest scores among
+    /// those whose keys start with `prefix`, ordered by descending score and
+    /// then by key. Subtrees are visited best first by the highest score
+    /// cached in them, so subtrees that can't make the cut are skipped.
+    pub fn top_k<P: AsRef<[u8]>, F: Fn(&T) -> K, K: Ord + Copy, I>(
+        &self,
+        prefix: P,
+        k: usize,
+    ) -> TopK<'_, T, S, F, K>
+    where
+        S: Contains<T, MaxScore<F>, I>,
+    {
+        let prefix = prefix.as_ref();
+        if prefix.is_empty() {
+            return TopK::new(Some(&self.root), vec![], &self.summary, k);
+        }
+        match self.root.find_prefix(prefix) {
+            Some((offset, node)) => {
+                let prefix = [&prefix[..offset], node.key()].concat();
+                TopK::new(Some(node), prefix, &self.summary, k)
+            }
+            None => TopK::new(None, vec![], &self.summary, k),
+        }
+    }
+}
+
+impl<T, A: NodeAllocator, F: Fn(&T) -> K, K: Ord + Copy> RadixMap<T, A, MaxScore<F>> {
+    /// Creates an empty map that ranks its values by `score`, for use with
+    /// [`Self::top_k()`].
+    pub fn with_score_in(score: F, alloc: A) -> Self {
+        RadixMap::with_summary_in(MaxScore::new(score), alloc)
+    }
+}
+
+impl<T, A: NodeAllocator, S: Summary<T>> Drop for RadixMap<T, A, S> {
+    fn drop(&mut self) {
+        unsafe { ptr::read(&self.root) }.drop_in(&self.alloc);
     }
 }
 
-impl<T> IntoIterator for RadixMap<T> {
+impl<T, A: NodeAllocator, S: Summary<T>> IntoIterator for RadixMap<T, A, S> {
     type Item = (Box<[u8]>, T);
-    type IntoIter = IntoIter<T>;
+    type IntoIter = IntoIter<T, A, S::Output>;
 
     fn into_iter(self) -> Self::IntoIter {
-        IntoIter::new(self.root)
+        let map = ManuallyDrop::new(self);
+        unsafe { IntoIter::new(ptr::read(&map.root), ptr::read(&map.alloc)) }
     }
 }
 
 impl<K: AsRef<[u8]>, T, const N: usize> From<[(K, T); N]> for RadixMap<T> {
     fn from(items: [(K, T); N]) -> Self {
-        let mut map = RadixMap::new();
-        for (key, value) in items {
-            map.insert(key, value);
-        }
-        map
+        RadixMap::from_iter(items)
     }
 }
 
-impl<K: AsRef<[u8]>, T> FromIterator<(K, T)> for RadixMap<T> {
+impl<K: AsRef<[u8]>, T, A: NodeAllocator + Default, S: Summary<T> + Default> FromIterator<(K, T)>
+    for RadixMap<T, A, S>
+{
     fn from_iter<I: IntoIterator<Item = (K, T)>>(iter: I) -> Self {
-        let mut map = RadixMap::new();
-        for (key, value) in iter {
-            map.insert(key, value);
-        }
+        let mut map = RadixMap::default();
+        map.extend(iter);
         map
     }
 }
 
+impl<K: AsRef<[u8]>, T, A: NodeAllocator, S: Summary<T>> Extend<(K, T)> for RadixMap<T, A, S> {
+    /// Builds an empty map bottom-up for as long as the items are sorted by
+    /// key and falls back to inserting the remaining items one by one.
+    ///
+    /// A map with a memory limit inserts all items one by one, so like
+    /// [`RadixMap::insert()`] it panics at the first item that would exceed
+    /// the limit and keeps the items before it.
+    fn extend<I: IntoIterator<Item = (K, T)>>(&mut self, iter: I) {
+        let mut iter = iter.into_iter();
+        let unsorted = match self.is_empty() && self.memory_limit.is_none() {
+            true => match self.extend_sorted(&mut iter) {
+                Ok(unsorted) => unsorted,
+                Err(err) => panic!("{}", err),
+            },
+            false => None,
+        };
+        for (key, value) in unsorted.into_iter().chain(iter) {
+            self.insert(key, value);
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    use crate::slab::SlabAllocator;
+
+    use std::alloc::Layout;
+    use std::cell::Cell;
+    use std::collections::BTreeMap;
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+    use std::ptr::NonNull;
     use std::rc::Rc;
 
+    #[derive(Default)]
+    struct CountingAllocator {
+        allocated_bytes: Cell<usize>,
+        live_blocks: Cell<usize>,
+        allocations: Cell<usize>,
+        /// Allocations fail once `allocations` reaches this count.
+        fail_at: Cell<Option<usize>>,
+        /// Failing allocations panic instead of returning `None`.
+        panics: Cell<bool>,
+    }
+
+    unsafe impl NodeAllocator for CountingAllocator {
+        fn allocate(&self, layout: Layout) -> Option<NonNull<u8>> {
+            if self.fail_at.get() == Some(self.allocations.get()) {
+                if self.panics.get() {
+                    panic!("allocation failed");
+                }
+                return None;
+            }
+            self.allocated_bytes
+                .set(self.allocated_bytes.get() + layout.size());
+            self.live_blocks.set(self.live_blocks.get() + 1);
+            self.allocations.set(self.allocations.get() + 1);
+            Global.allocate(layout)
+        }
+
+        unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+            self.allocated_bytes
+                .set(self.allocated_bytes.get() - layout.size());
+            self.live_blocks.set(self.live_blocks.get() - 1);
+            Global.deallocate(ptr, layout)
+        }
+    }
+
     fn populated_map() -> RadixMap<u32> {
         let mut m = RadixMap::new();
 
@@ -273,6 +871,10 @@ mod tests {
         assert_eq!(m.get("abc;"), None);
         assert_eq!(m.get("abc;1"), None);
         assert_eq!(m.get(""), None);
+
+        assert_eq!(mem::take(m.get_mut("c").unwrap()), 4);
+        assert_eq!(m.get("c"), Some(&0));
+        assert_eq!(m.get_mut("d"), None);
     }
 
     #[test]
@@ -302,6 +904,53 @@ mod tests {
         assert_eq!(m.get("c").unwrap(), &4);
     }
 
+    #[test]
+    fn test_remove_merges_chains() {
+        const DEPTH: usize = 50;
+        // "b", "ab", "aab", ... branch off a path of "a"s ending in "a" * DEPTH.
+        let branches: Vec<Vec<u8>> = (0..DEPTH)
+            .map(|len| [vec![b'a'; len], vec![b'b']].concat())
+            .collect();
+        let last = vec![b'a'; DEPTH];
+
+        for reverse in [false, true] {
+            let mut m = RadixMap::new();
+            m.insert(&last, DEPTH);
+            for (i, key) in branches.iter().enumerate() {
+                m.insert(key, i);
+            }
+            // The root and a node per branch and per "a"
+            assert_eq!(m.stats().node_count, 2 * DEPTH + 1);
+
+            let mut removed: Vec<_> = (0..DEPTH - 1).collect();
+            if reverse {
+                removed.reverse();
+            }
+            for i in removed {
+                assert_eq!(m.remove(&branches[i]), Some(i));
+                // Only the root is left with a single child, once "b" is
+                // removed. Other nodes without a value are merged.
+                let single_child = m.get(&branches[0]).is_none() as usize;
+                assert_eq!(m.stats().fanout_histogram[1], single_child);
+            }
+            // The root, the remaining path of "a"s and its two leaves
+            assert_eq!(m.stats().node_count, 4);
+            assert_eq!(m.get(&last), Some(&DEPTH));
+            assert_eq!(m.get(&branches[DEPTH - 1]), Some(&(DEPTH - 1)));
+        }
+    }
+
+    #[test]
+    fn test_remove_empty_key() {
+        let mut m = RadixMap::new();
+        m.insert("", 1);
+        m.insert("abc", 2);
+
+        assert_eq!(m.remove(""), Some(1));
+        assert_eq!(m.get("abc"), Some(&2));
+        assert_eq!(m.iter().count(), 1);
+    }
+
     #[test]
     fn test_with_long_keys() {
         let mut m = RadixMap::new();
@@ -362,10 +1011,10 @@ mod tests {
         // Test iter
         assert_eq!(m.iter().count(), 4);
         assert_eq!(m.prefix_iter(&key_a).count(), 2);
-        assert_eq!(m.prefix_iter(&[0]).count(), 2);
-        assert_eq!(m.prefix_iter(&[1]).count(), 1);
-        assert_eq!(m.prefix_iter(&[2]).count(), 1);
-        assert_eq!(m.prefix_iter(&[3]).count(), 0);
+        assert_eq!(m.prefix_iter([0]).count(), 2);
+        assert_eq!(m.prefix_iter([1]).count(), 1);
+        assert_eq!(m.prefix_iter([2]).count(), 1);
+        assert_eq!(m.prefix_iter([3]).count(), 0);
         assert_eq!(m.prefix_iter(&key_d).count(), 0);
     }
 
@@ -493,6 +1142,14 @@ mod tests {
 
         let mut it = m.prefix_iter(b"abd");
         assert_eq!(it.next(), None);
+
+        // The prefix diverges from a key that has children matching the
+        // rest of the prefix.
+        let mut m = populated_map();
+        m.insert("cadx", 6);
+        assert_eq!(m.prefix_iter(b"cax").next(), None);
+        assert_eq!(m.prefix_iter_mut(b"cax").next(), None);
+        assert_eq!(m.prefix_iter(b"cadx").count(), 1);
     }
 
     #[test]
@@ -642,6 +1299,54 @@ mod tests {
         assert_eq!(m.get("bb"), Some(&66));
     }
 
+    #[test]
+    fn test_stats() {
+        let m = populated_map();
+        let stats = m.stats();
+
+        // "" -> ["ab" -> ["b;0", "c;0"], "c" -> ["ad"]]
+        assert_eq!(stats.node_count, 6);
+        assert_eq!(stats.valued_node_count, 5);
+        assert_eq!(stats.unvalued_internal_node_count, 1);
+        // Only "ad" is short enough to be stored with its value inline.
+        assert_eq!(stats.inline_leaf_count, 1);
+        assert_eq!(stats.depth_histogram, vec![1, 2, 3]);
+        assert_eq!(
+            stats.edge_len_histogram,
+            BTreeMap::from([(0, 1), (1, 1), (2, 2), (3, 2)])
+        );
+        assert_eq!(stats.fanout_histogram, vec![3, 1, 2]);
+        assert!(stats.allocated_bytes > 0);
+
+        let stats = m.prefix_stats("a");
+        assert_eq!(stats.node_count, 3);
+        assert_eq!(stats.valued_node_count, 3);
+        assert_eq!(stats.depth_histogram, vec![1, 2]);
+        assert_eq!(stats.fanout_histogram, vec![2, 0, 1]);
+        assert!(stats.allocated_bytes < m.stats().allocated_bytes);
+
+        assert_eq!(m.prefix_stats("ca").valued_node_count, 1);
+        assert_eq!(m.prefix_stats("d"), TreeStats::default());
+        assert_eq!(m.prefix_stats(""), m.stats());
+    }
+
+    #[test]
+    fn test_stats_with_long_keys() {
+        let mut m = RadixMap::new();
+        m.insert(vec![0; 600], 1);
+        m.insert(vec![1; 10], 2);
+
+        let stats = m.stats();
+        // Long keys are stored in a single node
+        assert_eq!(stats.node_count, 3);
+        assert_eq!(stats.valued_node_count, 2);
+        assert_eq!(
+            stats.edge_len_histogram,
+            BTreeMap::from([(0, 1), (10, 1), (600, 1)])
+        );
+        assert_eq!(stats.depth_histogram, vec![1, 2]);
+    }
+
     #[test]
     fn test_from() {
         let map = RadixMap::<u64>::from([("foo", 1), ("bar", 2), ("baz", 3), ("foo", 4)]);
@@ -670,6 +1375,101 @@ mod tests {
         assert!(it.next().is_none());
     }
 
+    #[test]
+    fn test_from_sorted_iter() {
+        let map =
+            RadixMap::from_sorted_iter([("", 0), ("ab", 3), ("abb;0", 2), ("abc;0", 1), ("c", 4)])
+                .unwrap();
+
+        assert_eq!(map.len(), 5);
+        assert_eq!(map.get(""), Some(&0));
+        assert_eq!(map.get("ab"), Some(&3));
+        assert_eq!(map.get("abb;0"), Some(&2));
+        assert_eq!(map.get("abc;0"), Some(&1));
+        assert_eq!(map.get("c"), Some(&4));
+        assert_eq!(map.get("abb"), None);
+
+        let keys: Vec<Box<[u8]>> = map.keys().collect();
+        assert_eq!(keys.len(), 5);
+        assert_eq!(keys[1].as_ref(), b"ab");
+        assert_eq!(keys[4].as_ref(), b"c");
+    }
+
+    #[test]
+    fn test_from_sorted_iter_duplicates() {
+        let map =
+            RadixMap::from_sorted_iter([("bar", 1), ("bar", 2), ("foo", 3), ("foo", 4)]).unwrap();
+
+        assert_eq!(map.len(), 2);
+        assert_eq!(map.get("bar"), Some(&2));
+        assert_eq!(map.get("foo"), Some(&4));
+    }
+
+    #[test]
+    fn test_from_sorted_iter_unsorted() {
+        let rc = Rc::new(());
+        let res = RadixMap::from_sorted_iter([
+            ("bar", rc.clone()),
+            ("foo", rc.clone()),
+            ("baz", rc.clone()),
+        ]);
+
+        assert_eq!(res.unwrap_err(), RadixError::UnsortedKeys);
+        assert_eq!(Rc::strong_count(&rc), 1);
+    }
+
+    #[test]
+    fn test_from_sorted_iter_with_long_keys() {
+        let mut keys: Vec<Vec<u8>> = (0..100_u32)
+            .map(|i| {
+                let mut key = vec![(i % 3) as u8; 200 + i as usize * 7];
+                key.extend(i.to_be_bytes());
+                key
+            })
+            .collect();
+        keys.sort();
+
+        let map = RadixMap::from_sorted_iter(keys.iter().map(|k| (k, k.len()))).unwrap();
+
+        assert_eq!(map.len(), keys.len());
+        for key in keys.iter() {
+            assert_eq!(map.get(key), Some(&key.len()));
+        }
+        for ((k1, v1), k2) in map.iter().zip(keys.iter()) {
+            assert_eq!(k1.as_ref(), k2.as_slice());
+            assert_eq!(*v1, k2.len());
+        }
+    }
+
+    #[test]
+    fn test_from_iterator_partially_sorted() {
+        let map: RadixMap<u64> = vec![("bar", 1), ("baz", 2), ("foo", 3), ("abc", 4), ("baz", 5)]
+            .into_iter()
+            .collect();
+
+        assert_eq!(map.len(), 4);
+
+        let mut it = map.iter();
+        assert_eq!(it.next(), Some(("abc".as_bytes().into(), &4)));
+        assert_eq!(it.next(), Some(("bar".as_bytes().into(), &1)));
+        assert_eq!(it.next(), Some(("baz".as_bytes().into(), &5)));
+        assert_eq!(it.next(), Some(("foo".as_bytes().into(), &3)));
+        assert!(it.next().is_none());
+    }
+
+    #[test]
+    fn test_extend() {
+        let mut map = RadixMap::new();
+        map.extend([("bar", 1), ("baz", 2)]);
+        assert_eq!(map.len(), 2);
+
+        map.extend([("foo", 3), ("bar", 4)]);
+        assert_eq!(map.len(), 3);
+        assert_eq!(map.get("bar"), Some(&4));
+        assert_eq!(map.get("baz"), Some(&2));
+        assert_eq!(map.get("foo"), Some(&3));
+    }
+
     #[test]
     fn test_into_iter() {
         let m = populated_map();
@@ -728,4 +1528,619 @@ mod tests {
 
         assert_eq!(Rc::strong_count(&rc), 1);
     }
+
+    #[test]
+    fn test_custom_allocator() {
+        let alloc = CountingAllocator::default();
+
+        let mut m = RadixMap::new_in(&alloc);
+        for i in 0..100u32 {
+            m.insert(i.to_string(), i);
+        }
+        assert_eq!(alloc.allocated_bytes.get(), m.stats().allocated_bytes);
+        assert_eq!(alloc.allocated_bytes.get(), m.memory_usage());
+        assert_eq!(
+            alloc.live_blocks.get(),
+            m.stats().node_count - m.stats().inline_leaf_count
+        );
+
+        for i in (0..100u32).step_by(3) {
+            assert_eq!(m.remove(i.to_string()), Some(i));
+        }
+        assert_eq!(alloc.allocated_bytes.get(), m.stats().allocated_bytes);
+        assert_eq!(alloc.allocated_bytes.get(), m.memory_usage());
+        assert_eq!(
+            alloc.live_blocks.get(),
+            m.stats().node_count - m.stats().inline_leaf_count
+        );
+
+        drop(m);
+        assert_eq!(alloc.allocated_bytes.get(), 0);
+        assert_eq!(alloc.live_blocks.get(), 0);
+
+        let m = RadixMap::from_sorted_iter_in([("a", 1), ("ab", 2), ("b", 3)], &alloc).unwrap();
+        assert_eq!(alloc.allocated_bytes.get(), m.stats().allocated_bytes);
+
+        let mut it = m.into_iter();
+        let _ = it.next().unwrap();
+        drop(it);
+        assert_eq!(alloc.live_blocks.get(), 0);
+    }
+
+    #[test]
+    fn test_slab_allocator() {
+        let mut m = RadixMap::new_in(SlabAllocator::new());
+        let mut expected = std::collections::BTreeMap::new();
+        for i in 0..2000u32 {
+            let key = format!("{}", i.wrapping_mul(2654435761)).repeat(1 + i as usize % 40);
+            m.insert(&key, i);
+            expected.insert(key.into_bytes().into_boxed_slice(), i);
+        }
+        let keys: Vec<_> = expected.keys().cloned().collect();
+        for key in keys.iter().step_by(3) {
+            assert_eq!(m.remove(key), expected.remove(key));
+        }
+
+        assert_eq!(m.len(), expected.len());
+        assert!(m.iter().eq(expected.iter().map(|(k, v)| (k.clone(), v))));
+
+        let reserved = m.allocator().reserved_bytes();
+        for (key, value) in expected.iter() {
+            assert_eq!(m.remove(key), Some(*value));
+        }
+        for (key, value) in expected.iter() {
+            m.insert(key, *value);
+        }
+        // Blocks freed by removals are reused.
+        assert_eq!(m.allocator().reserved_bytes(), reserved);
+    }
+
+    #[test]
+    fn test_clear() {
+        let rc = Rc::new(());
+
+        let mut m = RadixMap::new_in(SlabAllocator::new());
+        m.insert("a", rc.clone());
+        m.insert("aba", rc.clone());
+        m.insert("cat", rc.clone());
+        m.clear();
+
+        assert!(m.is_empty());
+        assert_eq!(m.iter().count(), 0);
+        assert_eq!(Rc::strong_count(&rc), 1);
+
+        let mut m = RadixMap::new_in(SlabAllocator::new());
+        for i in 0..1000u32 {
+            m.insert(i.to_string(), i);
+        }
+        let reserved = m.allocator().reserved_bytes();
+        m.clear();
+
+        assert!(m.is_empty());
+        assert_eq!(m.get("1"), None);
+        for i in 0..1000u32 {
+            m.insert(i.to_string(), i);
+        }
+        assert_eq!(m.len(), 1000);
+        assert_eq!(m.get("999"), Some(&999));
+        assert_eq!(m.allocator().reserved_bytes(), reserved);
+    }
+
+    #[test]
+    fn test_growth_policy() {
+        let policies = [
+            GrowthPolicy::Exact,
+            GrowthPolicy::Doubling,
+            GrowthPolicy::SizeClasses,
+        ];
+        for policy in policies {
+            let mut m = RadixMap::with_growth_policy(policy);
+            assert_eq!(m.growth_policy(), policy);
+
+            let mut expected = std::collections::BTreeMap::new();
+            for i in 0..3000u32 {
+                let key = format!("{:x}", i.wrapping_mul(2654435761) % 5000);
+                assert_eq!(m.insert(&key, i), expected.insert(key, i));
+            }
+            for i in 0..3000u32 {
+                let key = format!("{:x}", i.wrapping_mul(2246822519) % 5000);
+                assert_eq!(m.remove(&key), expected.remove(&key));
+            }
+            assert_eq!(m.len(), expected.len());
+            assert!(m
+                .iter()
+                .eq(expected.iter().map(|(k, v)| (k.as_bytes().into(), v))));
+
+            m.shrink_to_fit();
+            assert!(m
+                .iter()
+                .eq(expected.iter().map(|(k, v)| (k.as_bytes().into(), v))));
+        }
+    }
+
+    #[test]
+    fn test_growth_policy_allocations() {
+        let exact = CountingAllocator::default();
+        let doubling = CountingAllocator::default();
+
+        let mut m_exact = RadixMap::with_growth_policy_in(GrowthPolicy::Exact, &exact);
+        let mut m_doubling = RadixMap::with_growth_policy_in(GrowthPolicy::Doubling, &doubling);
+        for i in 0..=255u8 {
+            m_exact.insert([i], i);
+            m_doubling.insert([i], i);
+        }
+        // Leaves are inlined, so only the root is reallocated per key or per
+        // doubling of its capacity.
+        assert_eq!(exact.allocations.get(), 1 + 256);
+        assert_eq!(doubling.allocations.get(), 1 + 9);
+        assert_eq!(
+            doubling.allocated_bytes.get(),
+            m_doubling.stats().allocated_bytes
+        );
+
+        for i in 0..=200u8 {
+            m_doubling.remove([i]);
+        }
+        assert_eq!(
+            doubling.allocated_bytes.get(),
+            m_doubling.stats().allocated_bytes
+        );
+        assert!(m_doubling.root.children_capacity() > m_doubling.root.children().len());
+
+        for i in 0..=200u8 {
+            m_exact.remove([i]);
+        }
+        m_doubling.shrink_to_fit();
+        assert_eq!(
+            m_doubling.stats().allocated_bytes,
+            m_exact.stats().allocated_bytes
+        );
+        assert_eq!(doubling.allocated_bytes.get(), exact.allocated_bytes.get());
+        assert!(m_doubling.iter().eq(m_exact.iter()));
+    }
+
+    #[test]
+    fn test_large_values() {
+        let alloc = CountingAllocator::default();
+
+        let mut m = RadixMap::new_in(&alloc);
+        let mut expected = std::collections::BTreeMap::new();
+        for i in 0..500u32 {
+            let key = format!("{:x}", i.wrapping_mul(2654435761));
+            let value = (key.clone(), [i as u64; 25]);
+            m.insert(&key, value.clone());
+            expected.insert(key, value);
+        }
+        // One block per value on top of the nodes.
+        let stats = m.stats();
+        assert_eq!(
+            alloc.live_blocks.get(),
+            stats.node_count - stats.inline_leaf_count + m.len()
+        );
+
+        for (_, v) in m.iter_mut() {
+            v.1[0] += 1;
+        }
+        for v in expected.values_mut() {
+            v.1[0] += 1;
+        }
+        let keys: Vec<_> = expected.keys().cloned().collect();
+        for key in keys.iter().step_by(3) {
+            assert_eq!(m.remove(key), expected.remove(key));
+        }
+        assert!(m
+            .iter()
+            .eq(expected.iter().map(|(k, v)| (k.as_bytes().into(), v))));
+
+        let mut it = m.into_iter();
+        assert!(it.next().is_some());
+        drop(it);
+        assert_eq!(alloc.live_blocks.get(), 0);
+    }
+
+    /// Runs `f` on a thread with a 64 KB stack.
+    fn with_small_stack(f: impl FnOnce() + Send + 'static) {
+        std::thread::Builder::new()
+            .stack_size(64 * 1024)
+            .spawn(f)
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+
+    #[test]
+    fn test_deep_tree() {
+        with_small_stack(|| {
+            const DEPTH: usize = 1000;
+            // Every key is a prefix of the next one, so each is a level.
+            let keys: Vec<Vec<u8>> = (1..=DEPTH).map(|len| vec![b'a'; len]).collect();
+
+            let mut m = RadixMap::new();
+            for (i, key) in keys.iter().enumerate() {
+                assert_eq!(m.insert(key, i), None);
+            }
+            assert_eq!(m.stats().depth_histogram.len(), DEPTH + 1);
+            for (i, key) in keys.iter().enumerate() {
+                assert_eq!(m.get(key), Some(&i));
+            }
+            *m.get_mut(&keys[DEPTH - 1]).unwrap() += 1;
+            assert_eq!(m.get(&keys[DEPTH - 1]), Some(&DEPTH));
+            assert_eq!(m.prefix_values(&keys[DEPTH - 10]).count(), 10);
+            assert_eq!(m.iter().count(), DEPTH);
+            // The entries before the start of a range are skipped one by one
+            assert_eq!(m.range(keys[DEPTH / 2].as_slice()..).count(), DEPTH / 2);
+            assert_eq!(m.range(keys[DEPTH - 1].as_slice()..).count(), 1);
+            for (_, v) in m.range_mut(keys[DEPTH - 2].as_slice()..) {
+                *v += 1;
+            }
+            assert_eq!(m.get(&keys[DEPTH - 2]), Some(&(DEPTH - 1)));
+            *m.get_mut(&keys[DEPTH - 2]).unwrap() -= 1;
+            *m.get_mut(&keys[DEPTH - 1]).unwrap() -= 1;
+
+            let copy = RadixMap::from_sorted_iter(m.iter().map(|(k, v)| (k, *v))).unwrap();
+            assert_eq!(copy.len(), DEPTH);
+
+            for (i, key) in keys.iter().enumerate().step_by(2) {
+                assert_eq!(m.remove(key), Some(i));
+            }
+            assert_eq!(m.len(), DEPTH / 2);
+            assert_eq!(m.get(&keys[1]), Some(&1));
+
+            let mut it = copy.into_iter();
+            assert!(it.next().is_some());
+            drop(it);
+            drop(m);
+        });
+    }
+
+    #[test]
+    fn test_huge_keys() {
+        with_small_stack(|| {
+            let key_a = vec![7u8; 4 << 20];
+            let mut key_b = key_a.clone();
+            key_b[2 << 20] = 8;
+
+            let mut m = RadixMap::new();
+            m.insert(&key_a, 1);
+            m.insert(&key_b, 2);
+            // The root, the shared prefix and a node per key
+            assert_eq!(m.stats().node_count, 4);
+            assert_eq!(m.get(&key_a), Some(&1));
+            assert_eq!(m.get(&key_b), Some(&2));
+            assert_eq!(m.get(&key_a[1..]), None);
+            assert_eq!(m.prefix_iter(&key_a[..1 << 20]).count(), 2);
+
+            assert_eq!(m.remove(&key_a), Some(1));
+            // The shared prefix has no value and is merged with key_b
+            assert_eq!(m.stats().node_count, 2);
+            assert_eq!(m.get(&key_b), Some(&2));
+        });
+    }
+
+    fn check_try_insert<T: Clone + PartialEq + std::fmt::Debug>(value: T) {
+        let keys: [&[u8]; 10] = [
+            b"abc",
+            b"abd",
+            b"ab",
+            b"a",
+            b"abcdefghijklmnopqrstuvwxyz",
+            b"abcdefghijklmnop",
+            b"abcdefghijklmnopqrstuvwxyz0123456789",
+            b"abcdefghijklmnopqrstuvwxyz0123",
+            b"b",
+            b"",
+        ];
+        // Fail each allocation of each insert in turn
+        for fail_at in 0..8 {
+            let alloc = CountingAllocator::default();
+            let mut m: RadixMap<T, _> = RadixMap::new_in(&alloc);
+            for key in keys {
+                let entries: Vec<_> = m.iter().map(|(k, v)| (k, v.clone())).collect();
+                let stats = m.stats();
+                let allocated_bytes = alloc.allocated_bytes.get();
+                let live_blocks = alloc.live_blocks.get();
+
+                alloc.fail_at.set(Some(alloc.allocations.get() + fail_at));
+                match m.try_insert(key, value.clone()) {
+                    Ok(old) => assert_eq!(old, None),
+                    Err(err) => {
+                        assert_eq!(err, RadixError::AllocError);
+                        let after: Vec<_> = m.iter().map(|(k, v)| (k, v.clone())).collect();
+                        assert_eq!(after, entries);
+                        assert_eq!(m.stats(), stats);
+                        assert_eq!(alloc.allocated_bytes.get(), allocated_bytes);
+                        assert_eq!(alloc.live_blocks.get(), live_blocks);
+                        assert_eq!(m.memory_usage(), allocated_bytes);
+                    }
+                }
+                alloc.fail_at.set(None);
+                m.insert(key, value.clone());
+            }
+            assert_eq!(m.len(), keys.len());
+            assert_eq!(alloc.allocated_bytes.get(), m.memory_usage());
+            drop(m);
+            assert_eq!(alloc.live_blocks.get(), 0);
+        }
+    }
+
+    #[test]
+    fn test_try_insert() {
+        check_try_insert(1u32);
+        check_try_insert(Rc::new(1u32));
+        check_try_insert([7u64; 25]);
+
+        let value = Rc::new(());
+        let alloc = CountingAllocator::default();
+        let mut m = RadixMap::new_in(&alloc);
+        alloc.fail_at.set(Some(alloc.allocations.get()));
+        assert_eq!(
+            m.try_insert("a long key that is not inlined", value.clone()),
+            Err(RadixError::AllocError)
+        );
+        // The value is dropped
+        assert_eq!(Rc::strong_count(&value), 1);
+        assert!(m.is_empty());
+
+        alloc.fail_at.set(None);
+        let mut m = RadixMap::new_in(&alloc);
+        assert_eq!(m.try_insert("a", 1), Ok(None));
+        assert_eq!(m.try_insert("a", 2), Ok(Some(1)));
+        assert_eq!(m.try_extend([("b", 3), ("c", 4)]), Ok(()));
+        assert_eq!(m.len(), 3);
+    }
+
+    #[test]
+    fn test_memory_limit() {
+        let mut m = RadixMap::with_memory_limit(1024);
+        assert_eq!(m.memory_limit(), Some(1024));
+        assert_eq!(RadixMap::<u32>::new().memory_limit(), None);
+
+        let mut inserted = 0;
+        let err = loop {
+            let entries = m.len();
+            let memory_usage = m.memory_usage();
+            let stats = m.stats();
+            match m.try_insert(format!("key-{}", inserted * 7919), inserted) {
+                Ok(_) => inserted += 1,
+                Err(err) => {
+                    assert_eq!(m.len(), entries);
+                    assert_eq!(m.memory_usage(), memory_usage);
+                    assert_eq!(m.stats(), stats);
+                    break err;
+                }
+            }
+            assert!(m.memory_usage() <= 1024);
+        };
+        assert_eq!(err, RadixError::CapacityExceeded);
+        assert!(inserted > 0);
+        assert_eq!(m.len(), inserted);
+        assert_eq!(m.memory_usage(), m.stats().allocated_bytes);
+
+        // Replacing a value does not allocate
+        assert_eq!(m.try_insert("key-0", 10), Ok(Some(0)));
+
+        let err = m.try_extend((0..1000).map(|i| (format!("other-{}", i), i)));
+        assert_eq!(err, Err(RadixError::CapacityExceeded));
+        assert!(m.memory_usage() <= 1024);
+
+        // Removing frees memory for new keys
+        let len = m.len();
+        let keys: Vec<_> = m.keys().take(len / 2).collect();
+        for key in keys.iter() {
+            m.remove(key);
+        }
+        assert_eq!(m.memory_usage(), m.stats().allocated_bytes);
+        assert!(m.try_insert("key-0", 0).is_ok());
+
+        m.clear();
+        assert_eq!(m.memory_usage(), m.stats().allocated_bytes);
+    }
+
+    #[test]
+    #[should_panic(expected = "memory limit exceeded")]
+    fn test_memory_limit_insert_panics() {
+        let mut m = RadixMap::with_memory_limit(0);
+        m.insert("a long key that is not inlined", 1);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_memory_limit_extend() {
+        let items: Vec<_> = (0..1000).map(|i| (format!("key-{:04}", i), i)).collect();
+
+        let mut m = RadixMap::with_memory_limit(1024);
+        let result = catch_unwind(AssertUnwindSafe(|| m.extend(items.iter().cloned())));
+        let err = *result.unwrap_err().downcast::<String>().unwrap();
+        assert_eq!(err, RadixError::CapacityExceeded.to_string());
+        // The items before the one that exceeded the limit were inserted
+        assert!(!m.is_empty());
+        assert!(m.iter().map(|(k, v)| (k, *v)).eq(items[..m.len()]
+            .iter()
+            .map(|(k, v)| (k.as_bytes().into(), *v))));
+        assert!(m.memory_usage() <= 1024);
+        assert_eq!(m.memory_usage(), m.stats().allocated_bytes);
+
+        let mut m = RadixMap::with_memory_limit(1 << 20);
+        m.extend(items.iter().cloned());
+        assert_eq!(m.len(), items.len());
+        assert_eq!(m.memory_usage(), m.stats().allocated_bytes);
+    }
+
+    #[test]
+    fn test_from_sorted_iter_alloc_error() {
+        let items: Vec<_> = (0..100)
+            .map(|i| (format!("key-{:03}", i), Rc::new(i)))
+            .collect();
+        let alloc = CountingAllocator::default();
+        // The first allocation is the root of the empty map
+        for fail_at in [1, 2, 10, 50] {
+            alloc.fail_at.set(Some(alloc.allocations.get() + fail_at));
+            let m = RadixMap::from_sorted_iter_in(items.iter().cloned(), &alloc);
+            assert_eq!(m.err(), Some(RadixError::AllocError));
+            // The nodes allocated so far and the values are freed
+            assert_eq!(alloc.live_blocks.get(), 0);
+            assert!(items.iter().all(|(_, value)| Rc::strong_count(value) == 1));
+        }
+        alloc.fail_at.set(None);
+        let m = RadixMap::from_sorted_iter_in(items.iter().cloned(), &alloc).unwrap();
+        assert_eq!(m.len(), items.len());
+    }
+
+    /// Value whose destructor panics if it was created with `explode`. The
+    /// padding makes values above the embedding limit allocated separately.
+    struct Bomb<const N: usize> {
+        drops: Rc<Cell<usize>>,
+        explode: bool,
+        _padding: [u8; N],
+    }
+
+    impl<const N: usize> Drop for Bomb<N> {
+        fn drop(&mut self) {
+            self.drops.set(self.drops.get() + 1);
+            if self.explode {
+                panic!("bomb");
+            }
+        }
+    }
+
+    fn bombs<const N: usize>(
+        drops: &Rc<Cell<usize>>,
+        explode: usize,
+    ) -> impl Iterator<Item = (String, Bomb<N>)> + '_ {
+        (0..100).map(move |i| {
+            let value = Bomb {
+                drops: drops.clone(),
+                explode: i == explode,
+                _padding: [0; N],
+            };
+            (format!("{}", i * 7), value)
+        })
+    }
+
+    fn check_panicking_value<const N: usize>() {
+        let alloc = CountingAllocator::default();
+        let drops = Rc::new(Cell::new(0));
+        let populated_map = || {
+            let mut m = RadixMap::new_in(&alloc);
+            m.extend(bombs::<N>(&drops, 50));
+            m
+        };
+
+        // Drop
+        let m = populated_map();
+        assert!(catch_unwind(AssertUnwindSafe(|| drop(m))).is_err());
+        assert_eq!(drops.get(), 100);
+        assert_eq!(alloc.live_blocks.get(), 0);
+
+        // Clear
+        drops.set(0);
+        let mut m = populated_map();
+        assert!(catch_unwind(AssertUnwindSafe(|| m.clear())).is_err());
+        assert_eq!(drops.get(), 100);
+        assert!(m.is_empty());
+        assert_eq!(m.iter().count(), 0);
+        assert_eq!(m.memory_usage(), alloc.allocated_bytes.get());
+        m.extend(bombs::<N>(&drops, usize::MAX));
+        assert_eq!(m.len(), 100);
+        drop(m);
+        assert_eq!(alloc.live_blocks.get(), 0);
+
+        // Into iterator
+        drops.set(0);
+        let m = populated_map();
+        let mut it = m.into_iter();
+        for (_, value) in it.by_ref().take(10) {
+            assert!(!value.explode);
+        }
+        assert!(catch_unwind(AssertUnwindSafe(|| drop(it))).is_err());
+        assert_eq!(drops.get(), 100);
+        assert_eq!(alloc.live_blocks.get(), 0);
+
+        // Duplicate keys while building a map
+        drops.set(0);
+        let mut m = RadixMap::new_in(&alloc);
+        let items = bombs::<N>(&drops, 0).take(2).map(|(_, v)| ("key", v));
+        assert!(catch_unwind(AssertUnwindSafe(|| m.extend(items))).is_err());
+        assert_eq!(drops.get(), 2);
+        assert!(m.is_empty());
+        drop(m);
+        assert_eq!(alloc.live_blocks.get(), 0);
+    }
+
+    #[test]
+    fn test_panicking_value() {
+        check_panicking_value::<0>();
+        check_panicking_value::<100>();
+    }
+
+    fn check_entries<A: NodeAllocator>(m: &RadixMap<Rc<u32>, A>, expected: &BTreeMap<String, u32>) {
+        assert_eq!(m.len(), expected.len());
+        assert!(m
+            .iter()
+            .map(|(k, v)| (k, **v))
+            .eq(expected.iter().map(|(k, v)| (k.as_bytes().into(), *v))));
+    }
+
+    #[test]
+    fn test_remove_alloc_error() {
+        let keys = ["abc", "abcd", "abce", "ab", "abcdef", "b", "bc", "a"];
+        for panics in [false, true] {
+            for fail_at in 0..4 {
+                let alloc = CountingAllocator::default();
+                let mut m = RadixMap::new_in(&alloc);
+                let mut expected = BTreeMap::new();
+                let value = Rc::new(0);
+                for (i, key) in keys.iter().enumerate() {
+                    m.insert(key, Rc::new(i as u32));
+                    expected.insert(key.to_string(), i as u32);
+                }
+                m.insert("value", value.clone());
+                expected.insert("value".to_string(), 0);
+                for key in keys.iter().rev() {
+                    alloc.fail_at.set(Some(alloc.allocations.get() + fail_at));
+                    alloc.panics.set(panics);
+                    let removed = catch_unwind(AssertUnwindSafe(|| m.remove(key)));
+                    alloc.fail_at.set(None);
+                    // The key is removed even if compacting the tree panics
+                    let expected_value = expected.remove(*key);
+                    if let Ok(removed) = removed {
+                        assert_eq!(removed.map(|v| *v), expected_value);
+                    }
+                    check_entries(&m, &expected);
+                    assert_eq!(m.memory_usage(), alloc.allocated_bytes.get());
+                }
+                assert_eq!(m.len(), 1);
+                drop(m);
+                assert_eq!(Rc::strong_count(&value), 1);
+                assert_eq!(alloc.live_blocks.get(), 0);
+            }
+        }
+    }
+
+    #[test]
+    fn test_insert_panicking_allocator() {
+        let keys = ["abc", "abcd", "abce", "ab", "abcdef", "b", "bc", "a"];
+        for fail_at in 0..4 {
+            let alloc = CountingAllocator::default();
+            let mut m = RadixMap::new_in(&alloc);
+            let mut expected = BTreeMap::new();
+            alloc.panics.set(true);
+            for (i, key) in keys.iter().enumerate() {
+                alloc.fail_at.set(Some(alloc.allocations.get() + fail_at));
+                let inserted = catch_unwind(AssertUnwindSafe(|| m.insert(key, Rc::new(i as u32))));
+                if inserted.is_ok() {
+                    expected.insert(key.to_string(), i as u32);
+                }
+                check_entries(&m, &expected);
+                assert_eq!(m.memory_usage(), alloc.allocated_bytes.get());
+            }
+            alloc.fail_at.set(None);
+            for key in keys {
+                m.insert(key, Rc::new(0));
+            }
+            assert_eq!(m.len(), keys.len());
+            drop(m);
+            assert_eq!(alloc.live_blocks.get(), 0);
+        }
+    }
 }
@@ -0,0 +1,223 @@
+use alloc::boxed::Box;
+
+use crate::longest_common_prefix;
+use crate::node::Node;
+
+/// An entry before the path to a key: either the last entry of a subtree
+/// or the value of a node on the path, with the length of the key before
+/// the subtree or up to the node.
+enum Before<'a, T, U> {
+    Subtree(usize, &'a Node<T, U>),
+    Value(usize, &'a T),
+}
+
+/// Returns the first entry of the subtree of `node`, whose key follows
+/// `prefix`.
+fn first<'a, T, U>(prefix: &[u8], mut node: &'a Node<T, U>) -> (Box<[u8]>, &'a T) {
+    let mut key = prefix.to_vec();
+    loop {
+        key.extend_from_slice(node.key());
+        if let Some(value) = node.value() {
+            return (key.into(), value);
+        }
+        // Nodes other than the root only lack a value if they have children.
+        node = &node.children()[0];
+    }
+}
+
+/// Returns the last entry of the subtree of `node`, whose key follows
+/// `prefix`.
+fn last<'a, T, U>(prefix: &[u8], mut node: &'a Node<T, U>) -> (Box<[u8]>, &'a T) {
+    let mut key = prefix.to_vec();
+    loop {
+        key.extend_from_slice(node.key());
+        match node.children().last() {
+            Some(child) => node = child,
+            None => return (key.into(), node.value().expect("leaf without a value")),
+        }
+    }
+}
+
+/// Returns the entry with the smallest key greater than `key`, or equal to
+/// it if `inclusive`.
+///
+/// Walks down the path to `key` and remembers the child after the one taken
+/// at the deepest level, whose first entry is the answer if the path ends
+/// without one.
+pub(crate) fn ceiling<'a, T, U>(
+    root: &'a Node<T, U>,
+    key: &[u8],
+    inclusive: bool,
+) -> Option<(Box<[u8]>, &'a T)> {
+    let mut next: Option<(usize, &Node<T, U>)> = None;
+    let mut node = root;
+    let mut offset = 0;
+    let after = loop {
+        let suffix = &key[offset..];
+        if suffix.is_empty() {
+            if let Some(value) = node.value().filter(|_| inclusive) {
+                return Some((key.into(), value));
+            }
+            // Every key below the node is greater.
+            break node.children().first();
+        }
+
+        let children = node.children();
+        let (prefix_len, child_idx) = longest_common_prefix(node, suffix);
+        if prefix_len == 0 {
+            // The children from the insert index on start with a greater byte.
+            break children.get(child_idx);
+        }
+        let child = &children[child_idx];
+        if prefix_len == child.key().len() {
+            if let Some(sibling) = children.get(child_idx + 1) {
+                next = Some((offset, sibling));
+            }
+            node = child;
+            offset += prefix_len;
+            continue;
+        }
+        if prefix_len == suffix.len() || suffix[prefix_len] < child.key()[prefix_len] {
+            // The key sorts before all keys of the child.
+            break Some(child);
+        }
+        break children.get(child_idx + 1);
+    };
+    let (offset, node) = after.map(|child| (offset, child)).or(next)?;
+    Some(first(&key[..offset], node))
+}
+
+/// Returns the entry with the largest key smaller than `key`, or equal to
+/// it if `inclusive`.
+///
+/// Walks down the path to `key` and remembers the entry before the child
+/// taken at the deepest level, which is the answer if the path ends without
+/// one.
+pub(crate) fn floor<'a, T, U>(
+    root: &'a Node<T, U>,
+    key: &[u8],
+    inclusive: bool,
+) -> Option<(Box<[u8]>, &'a T)> {
+    let mut prev: Option<Before<'a, T, U>> = None;
+    let mut node = root;
+    let mut offset = 0;
+    let before = loop {
+        let suffix = &key[offset..];
+        if suffix.is_empty() {
+            if let Some(value) = node.value().filter(|_| inclusive) {
+                return Some((key.into(), value));
+            }
+            // Every key below the node is greater.
+            break None;
+        }
+
+        let children = node.children();
+        let (prefix_len, child_idx) = longest_common_prefix(node, suffix);
+        // The children before the index and the node's own key are smaller.
+        let here = match child_idx {
+            0 => node.value().map(|value| Before::Value(offset, value)),
+            _ => Some(Before::Subtree(offset, &children[child_idx - 1])),
+        };
+        if prefix_len == 0 {
+            break here;
+        }
+        let child = &children[child_idx];
+        if prefix_len == child.key().len() {
+            prev = here.or(prev);
+            node = child;
+            offset += prefix_len;
+            continue;
+        }
+        if prefix_len < suffix.len() && suffix[prefix_len] > child.key()[prefix_len] {
+            // The key sorts after all keys of the child.
+            break Some(Before::Subtree(offset, child));
+        }
+        break here;
+    };
+    match before.or(prev)? {
+        Before::Subtree(offset, node) => Some(last(&key[..offset], node)),
+        Before::Value(offset, value) => Some((key[..offset].into(), value)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use crate::map::RadixMap;
+
+    fn keys() -> Vec<Vec<u8>> {
+        let mut rng = StdRng::seed_from_u64(7);
+        (0..600)
+            .map(|_| {
+                let len = rng.gen_range(0..6);
+                (0..len).map(|_| b"bdf"[rng.gen_range(0..3)]).collect()
+            })
+            .collect()
+    }
+
+    fn find(entry: Option<&(Box<[u8]>, usize)>) -> Option<(Box<[u8]>, &usize)> {
+        entry.map(|(key, value)| (key.clone(), value))
+    }
+
+    #[test]
+    fn test_neighbors_match_brute_force() {
+        let keys = keys();
+        let map: RadixMap<usize> = keys.iter().enumerate().map(|(i, k)| (k, i)).collect();
+        let sorted: Vec<(Box<[u8]>, usize)> = map.iter().map(|(k, v)| (k, *v)).collect();
+
+        // Queries include every stored key and keys between and around them.
+        let mut queries = keys.clone();
+        for key in &keys {
+            for byte in [b'a', b'b', b'c', b'f', b'g'] {
+                queries.push([key.as_slice(), &[byte]].concat());
+            }
+            if let Some((_, init)) = key.split_last() {
+                queries.push([init, b"c"].concat());
+            }
+        }
+        queries.push(vec![]);
+
+        for query in &queries {
+            let query = query.as_slice();
+            assert_eq!(
+                map.floor(query),
+                find(sorted.iter().rev().find(|(k, _)| &k[..] <= query)),
+                "{:?}",
+                query
+            );
+            assert_eq!(
+                map.lower(query),
+                find(sorted.iter().rev().find(|(k, _)| &k[..] < query)),
+                "{:?}",
+                query
+            );
+            assert_eq!(
+                map.ceiling(query),
+                find(sorted.iter().find(|(k, _)| &k[..] >= query)),
+                "{:?}",
+                query
+            );
+            assert_eq!(
+                map.higher(query),
+                find(sorted.iter().find(|(k, _)| &k[..] > query)),
+                "{:?}",
+                query
+            );
+        }
+    }
+
+    #[test]
+    fn test_neighbors_empty() {
+        let mut map = RadixMap::new();
+        assert_eq!(map.floor("a"), None);
+        assert_eq!(map.ceiling(""), None);
+
+        map.insert("", 0);
+        assert_eq!(map.floor("a"), Some((b""[..].into(), &0)));
+        assert_eq!(map.floor(""), Some((b""[..].into(), &0)));
+        assert_eq!(map.lower(""), None);
+        assert_eq!(map.higher(""), None);
+    }
+}
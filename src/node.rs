@@ -1,76 +1,213 @@
-use std::alloc::{alloc, dealloc, realloc, Layout};
-use std::marker::PhantomData;
-use std::mem::size_of;
-use std::ptr;
-use std::slice::{from_raw_parts, from_raw_parts_mut};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::alloc::Layout;
+use core::cell::UnsafeCell;
+use core::marker::PhantomData;
+use core::mem::{self, align_of, size_of, MaybeUninit};
+use core::ptr;
+use core::slice::{from_raw_parts, from_raw_parts_mut};
 
 use bitflags::bitflags;
 
+use crate::allocator::NodeAllocator;
+use crate::bytes::find_byte;
+use crate::error::RadixError;
+use crate::growth::GrowthPolicy;
 use crate::longest_common_prefix;
+use crate::summary::Summary;
+
+/// Nodes with capacity for more children than this keep an index mapping
+/// each byte to the position of the child whose key starts with it. Smaller
+/// nodes search the first bytes of their children, which are stored next to
+/// each other in the node.
+const DENSE_THRESHOLD: usize = 48;
+
+/// Values larger than this are allocated separately and nodes only store a
+/// pointer to them, so that reallocating a node does not copy the value.
+const MAX_EMBEDDED_VALUE_SIZE: usize = 64;
 
 bitflags! {
     struct Flags: u8 {
-        const VALUE_ALLOCATED = 0b0000_0001;
-        const VALUE_INITIALIZED = 0b0000_0010;
-        const HAS_CHILDREN = 0b0000_0100;
+        /// Set only in the header of inline leaves. Allocated nodes are
+        /// aligned to at least two bytes so the lowest bit of their pointer
+        /// is never set.
+        const INLINE = 0b0000_0001;
+        const VALUE_ALLOCATED = 0b0000_0010;
+        const VALUE_INITIALIZED = 0b0000_0100;
+        const HAS_CHILDREN = 0b0000_1000;
+        /// The summary slot of a node with children holds the summary of
+        /// its subtree.
+        const HAS_SUMMARY = 0b0001_0000;
     }
 }
 
 #[derive(Debug)]
-pub struct Node<T> {
+pub struct Node<T, U = ()> {
+    /// Either a pointer to an allocated node or an inline leaf. Inline leaves
+    /// store their flags, key and value directly in the pointer's bytes
+    /// using the same layout as allocated nodes up to the value. A leaf is
+    /// inlined if its key and value fit and is allocated once it gains
+    /// children. Inline leaves are only used on little-endian targets, where
+    /// the flags byte overlaps the lowest byte of the pointer.
+    ///
     /// Layout:
     ///     - flags: u8
-    ///     - key_len: u8
+    ///     - key_len: LEB128 varint
     ///     - key: [u8; key_len]
-    ///     - value: size_of<T> (optional - Flags::VALUE_ALLOCATED)
+    ///     - value: T or *mut T if T is larger than MAX_EMBEDDED_VALUE_SIZE (optional - Flags::VALUE_ALLOCATED)
     ///     - children_count: u8 (optional - Flags::HAS_CHILDREN)
-    ///     - children: [Node<T>; children_count] (optional - Flags::HAS_CHILDREN)
-    data: ptr::NonNull<u8>,
-    _phantom: PhantomData<T>,
+    ///     - children_capacity: u8 (optional - Flags::HAS_CHILDREN)
+    ///     - first bytes of children's keys: [u8; children_capacity] (optional - Flags::HAS_CHILDREN)
+    ///     - index: [u8; 256] (optional - children_capacity > DENSE_THRESHOLD)
+    ///     - children: [Node<T, U>; children_capacity] (optional - Flags::HAS_CHILDREN)
+    ///     - summary: U (optional - Flags::HAS_CHILDREN, initialized - Flags::HAS_SUMMARY)
+    /// Counts and capacities are stored decremented by one.
+    /// The value, children and summary are preceded by padding to their
+    /// alignment as computed by `Layout::extend`. Leaves have no summary
+    /// slot, their summary is computed from the value.
+    data: UnsafeCell<MaybeUninit<ptr::NonNull<u8>>>,
+    _phantom: PhantomData<(T, U)>,
 }
 
-unsafe impl<T: Send> Send for Node<T> {}
-unsafe impl<T: Sync> Sync for Node<T> {}
+unsafe impl<T: Send, U: Send> Send for Node<T, U> {}
+unsafe impl<T: Sync, U: Sync> Sync for Node<T, U> {}
+
+impl<T, U> Node<T, U> {
+    /// Whether values are allocated separately from nodes.
+    const BOXED_VALUE: bool = size_of::<T>() > MAX_EMBEDDED_VALUE_SIZE;
 
-impl<T> Node<T> {
     #[inline]
-    pub(crate) fn new(key: &[u8]) -> Self {
-        assert!(key.len() < 256, "Key length must be < 256");
+    pub(crate) fn new<A: NodeAllocator>(alloc: &A, key: &[u8]) -> Self {
         // Allocate
         let flags = Flags::empty();
-        let data = Self::alloc(flags, key.len());
+        let node = Node::from_ptr(Self::alloc(alloc, flags, key.len(), 0));
         // Write key
         unsafe {
-            ptr::write(data.as_ptr().add(1), key.len() as u8);
-            ptr::copy(key.as_ptr(), data.as_ptr().add(2), key.len());
+            node.write_key_len(key.len());
+            ptr::copy(key.as_ptr(), node.key_ptr(), key.len());
         }
 
-        Node {
-            data,
-            _phantom: PhantomData::default(),
-        }
+        node
     }
 
+    #[cfg(test)]
     #[inline]
-    fn new_with_value(key: &[u8], value: T) -> Self {
-        assert!(key.len() < 256, "Key length must be < 256");
-        // Allocate
+    fn new_with_value<A: NodeAllocator>(alloc: &A, key: &[u8], value: T) -> Self {
+        Self::try_new_with_value(alloc, key, value).expect("allocation failed")
+    }
+
+    /// Same as [`Self::new_with_value()`] but returns an error and drops the
+    /// value if an allocation fails.
+    #[inline]
+    fn try_new_with_value<A: NodeAllocator>(
+        alloc: &A,
+        key: &[u8],
+        value: T,
+    ) -> Result<Self, RadixError> {
         let mut flags = Flags::empty();
         flags.set(Flags::VALUE_ALLOCATED, true);
         flags.set(Flags::VALUE_INITIALIZED, true);
-        let data = Self::alloc(flags, key.len());
+        let mut unlinked = Unlinked::<T, U, A>::new(alloc);
+        let storage = *unlinked.storage.insert(Self::try_alloc_value(alloc)?);
+        let mut node = if Self::fits_inline(key.len()) {
+            Self::new_inline(flags)
+        } else {
+            Node::from_ptr(Self::try_alloc(alloc, flags, key.len(), 0)?)
+        };
+        unlinked.storage = None;
         unsafe {
             // Write key
-            ptr::write(data.as_ptr().add(1), key.len() as u8);
-            ptr::copy(key.as_ptr(), data.as_ptr().add(2), key.len());
+            node.write_key_len(key.len());
+            ptr::copy(key.as_ptr(), node.key_ptr(), key.len());
             // Write value
-            ptr::write(data.as_ptr().add(2 + key.len()) as *mut T, value);
+            node.write_value_in(storage, value);
         }
 
+        Ok(node)
+    }
+
+    #[inline(always)]
+    fn from_ptr(data: ptr::NonNull<u8>) -> Self {
         Node {
-            data,
-            _phantom: PhantomData::default(),
+            data: UnsafeCell::new(MaybeUninit::new(data)),
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Creates an inline leaf with an uninitialized key and value.
+    #[inline(always)]
+    fn new_inline(flags: Flags) -> Self {
+        let node = Node {
+            data: UnsafeCell::new(MaybeUninit::uninit()),
+            _phantom: PhantomData,
+        };
+        unsafe { ptr::write(node.data.get() as *mut u8, (flags | Flags::INLINE).bits()) };
+        node
+    }
+
+    /// Returns `true` if a leaf with a key of `key_len` bytes can be inlined.
+    #[inline(always)]
+    fn fits_inline(key_len: usize) -> bool {
+        cfg!(target_endian = "little")
+            && Self::value_layout().align() <= align_of::<Self>()
+            && Self::value_offset(key_len) + Self::value_layout().size() <= size_of::<Self>()
+    }
+
+    /// Creates a node with its final key, value and children using a single
+    /// allocation. If the allocation fails the value and the children are
+    /// dropped.
+    #[inline]
+    pub(crate) fn try_from_parts<A: NodeAllocator>(
+        alloc: &A,
+        key: &[u8],
+        value: Option<T>,
+        children: Vec<Node<T, U>>,
+    ) -> Result<Self, RadixError> {
+        assert!(children.len() <= 256, "Children array is full");
+        // Allocate
+        let mut flags = Flags::empty();
+        flags.set(Flags::VALUE_ALLOCATED, value.is_some());
+        flags.set(Flags::VALUE_INITIALIZED, value.is_some());
+        flags.set(Flags::HAS_CHILDREN, !children.is_empty());
+        let inline = value.is_some() && children.is_empty() && Self::fits_inline(key.len());
+        let mut unlinked = Unlinked::<T, U, A>::new(alloc);
+        unlinked.children = children;
+        if value.is_some() {
+            unlinked.storage = Some(Self::try_alloc_value(alloc)?);
+        }
+        let mut node = if inline {
+            Self::new_inline(flags)
+        } else {
+            Node::from_ptr(Self::try_alloc(
+                alloc,
+                flags,
+                key.len(),
+                unlinked.children.len(),
+            )?)
+        };
+        let storage = unlinked.storage.take();
+        let mut children = mem::take(&mut unlinked.children);
+        unsafe {
+            // Write key
+            node.write_key_len(key.len());
+            ptr::copy(key.as_ptr(), node.key_ptr(), key.len());
+            // Write value
+            if let (Some(value), Some(storage)) = (value, storage) {
+                node.write_value_in(storage, value);
+            }
+            // Move children
+            if !children.is_empty() {
+                ptr::write(node.children_len_ptr(), (children.len() - 1) as u8);
+                ptr::write(node.children_len_ptr().add(1), (children.len() - 1) as u8);
+                for (i, child) in children.iter().enumerate() {
+                    ptr::write(node.first_bytes_ptr().add(i), child.key()[0]);
+                }
+                ptr::copy_nonoverlapping(children.as_ptr(), node.children_ptr(), children.len());
+                children.set_len(0);
+            }
         }
+        node.update_index();
+        Ok(node)
     }
 
     // Exposed API
@@ -96,17 +233,17 @@ impl<T> Node<T> {
     }
 
     #[inline]
-    pub(crate) fn take_value(&mut self) -> Option<T> {
+    pub(crate) fn take_value<A: NodeAllocator>(&mut self, alloc: &A) -> Option<T> {
         if self.flags().contains(Flags::VALUE_INITIALIZED) {
             self.set_flags(Flags::VALUE_INITIALIZED, false);
-            Some(unsafe { ptr::read(self.value_ptr()) })
+            Some(unsafe { self.read_value(alloc) })
         } else {
             None
         }
     }
 
     #[inline(always)]
-    pub(crate) fn children(&self) -> &[Node<T>] {
+    pub(crate) fn children(&self) -> &[Node<T, U>] {
         if !self.flags().contains(Flags::HAS_CHILDREN) {
             return &[];
         }
@@ -115,7 +252,7 @@ impl<T> Node<T> {
     }
 
     #[inline(always)]
-    pub(crate) fn children_mut(&mut self) -> &mut [Node<T>] {
+    pub(crate) fn children_mut(&mut self) -> &mut [Node<T, U>] {
         if !self.flags().contains(Flags::HAS_CHILDREN) {
             return &mut [];
         }
@@ -123,121 +260,263 @@ impl<T> Node<T> {
         unsafe { from_raw_parts_mut(self.children_ptr(), *self.children_len_ptr() as usize + 1) }
     }
 
-    /// Returns an iterator over node's children that deallocates this node's
-    /// children after iteration.
+    /// Returns the position of the child whose key starts with `byte`. If there
+    /// is no such child, returns the position at which it would be inserted.
     #[inline(always)]
-    pub(crate) fn take_children(&mut self) -> TakeChildren<'_, T> {
-        TakeChildren::new(self)
+    pub(crate) fn child_index(&self, byte: u8) -> Result<usize, usize> {
+        let first_bytes = self.first_bytes();
+        if self.children_capacity() > DENSE_THRESHOLD {
+            let idx = unsafe { *self.index_ptr().add(byte as usize) } as usize;
+            if first_bytes[idx] == byte {
+                return Ok(idx);
+            }
+            return Err(find_byte(first_bytes, byte).unwrap_err());
+        }
+        find_byte(first_bytes, byte)
     }
 
-    #[inline]
-    pub(crate) fn insert(&mut self, key: &[u8], value: T) -> Option<T> {
-        if key.is_empty() {
-            return self.replace_value(value);
-        }
-
-        let (prefix_len, child_idx) = longest_common_prefix(self.children(), key);
-        if prefix_len == 0 {
-            // No child shares a prefix with the key
-            if key.len() > 255 {
-                // Key length is greater than 255. Insert a child with key len == 255 and insert
-                // the remainder into it.
-                self.insert_child(child_idx, Node::new(&key[..255]));
-                return self.children_mut()[child_idx].insert(&key[255..], value);
+    /// First bytes of the children's keys.
+    #[inline(always)]
+    fn first_bytes(&self) -> &[u8] {
+        if !self.flags().contains(Flags::HAS_CHILDREN) {
+            return &[];
+        }
+
+        unsafe {
+            from_raw_parts(
+                self.first_bytes_ptr(),
+                *self.children_len_ptr() as usize + 1,
+            )
+        }
+    }
+
+    #[inline(always)]
+    pub(crate) fn children_capacity(&self) -> usize {
+        if !self.flags().contains(Flags::HAS_CHILDREN) {
+            return 0;
+        }
+
+        unsafe { *self.children_len_ptr().add(1) as usize + 1 }
+    }
+
+    /// Reallocates the children array of this node and all nodes below it to
+    /// fit the number of children exactly.
+    pub(crate) fn shrink_to_fit<A: NodeAllocator>(&mut self, alloc: &A) {
+        let mut stack = vec![self];
+        while let Some(node) = stack.pop() {
+            let children_count = node.children().len();
+            if children_count < node.children_capacity() {
+                node.realloc(
+                    alloc,
+                    node.flags(),
+                    node.key_len(),
+                    children_count,
+                    children_count,
+                );
+                node.update_index();
             }
-            // Insert a new child at child_idx offset
-            self.insert_child(child_idx, Node::new_with_value(key, value));
-            return None;
+            stack.extend(node.children_mut().iter_mut());
         }
+    }
+
+    /// Returns an iterator over node's children. The node is left without
+    /// children and the children not taken are dropped with the iterator.
+    #[cfg(test)]
+    pub(crate) fn take_children<'a, A: NodeAllocator>(
+        &mut self,
+        alloc: &'a A,
+    ) -> TakeChildren<'a, T, A, U> {
+        TakeChildren::new(self, alloc)
+    }
+
+    /// Returns an iterator over node's children and deallocates the node
+    /// once it is dropped. The node's value must have been taken.
+    #[inline(always)]
+    pub(crate) fn into_children<A: NodeAllocator>(self, alloc: &A) -> TakeChildren<'_, T, A, U> {
+        TakeChildren::from_node(self, alloc)
+    }
+
+    #[cfg(test)]
+    #[inline]
+    pub(crate) fn insert<A: NodeAllocator>(
+        &mut self,
+        alloc: &A,
+        policy: GrowthPolicy,
+        key: &[u8],
+        value: T,
+    ) -> Option<T> {
+        self.try_insert(alloc, policy, key, value)
+            .expect("allocation failed")
+    }
 
-        // Some child shares a prefix with the key
-        let children = self.children_mut();
-        if prefix_len == children[child_idx].key().len() {
+    /// Same as [`Self::insert()`] but returns an error if an allocation
+    /// fails. The tree is left unchanged and the value is dropped in that
+    /// case.
+    #[inline]
+    pub(crate) fn try_insert<A: NodeAllocator>(
+        &mut self,
+        alloc: &A,
+        policy: GrowthPolicy,
+        key: &[u8],
+        value: T,
+    ) -> Result<Option<T>, RadixError> {
+        let mut node = self;
+        let mut key = key;
+        loop {
+            if key.is_empty() {
+                return node.try_replace_value(alloc, value);
+            }
+
+            let (prefix_len, child_idx) = longest_common_prefix(node, key);
+            if prefix_len == 0 {
+                // No child shares a prefix with the key. Insert a new child at child_idx offset
+                let child = Node::try_new_with_value(alloc, key, value)?;
+                node.try_insert_child(alloc, policy, child_idx, child)?;
+                return Ok(None);
+            }
+
+            // Some child shares a prefix with the key
+            let children = node.children_mut();
+            if prefix_len < children[child_idx].key().len() {
+                // Only a portion of child's key shares prefix with the inserted key
+                Self::try_split_child(alloc, policy, children, child_idx, key, prefix_len, value)?;
+                return Ok(None);
+            }
             // Child's key is a prefix of the inserted key
-            return children[child_idx].insert(&key[prefix_len..], value);
+            node = &mut children[child_idx];
+            key = &key[prefix_len..];
         }
+    }
 
-        // Only a portion of child's key shares prefix with the inserted key
-        Self::split_child(children, child_idx, prefix_len, key, value)
+    #[cfg(test)]
+    #[inline]
+    pub(crate) fn remove<A: NodeAllocator>(
+        &mut self,
+        alloc: &A,
+        policy: GrowthPolicy,
+        key: &[u8],
+    ) -> Option<T> {
+        let removed = self.take(alloc, key)?;
+        self.compact(alloc, policy, key);
+        Some(removed)
     }
 
+    /// Moves the value of `key` out of the tree without changing its shape,
+    /// which [`Self::compact()`] restores afterwards.
     #[inline]
-    pub(crate) fn remove(&mut self, key: &[u8]) -> Option<T> {
-        if key.is_empty() {
-            let removed = self.take_value();
-            if (self.children().len() == 1)
-                && (self.key_len() + self.children()[0].key().len() < 256)
-            {
-                // If the node has only one child and len(node key + child key) < 256
-                // then we can merge the nodes together.
-                let mut child_node = self.remove_child(0);
-                self.extend_key(child_node.key());
-                if let Some(v) = child_node.take_value() {
-                    self.replace_value(v);
-                }
-                self.move_children(child_node);
-            }
-            return removed;
+    pub(crate) fn take<A: NodeAllocator>(&mut self, alloc: &A, key: &[u8]) -> Option<T> {
+        let mut node = self;
+        let mut key = key;
+        while !key.is_empty() {
+            let (prefix_len, child_idx) = node.select_next_child(key)?;
+            node = &mut node.children_mut()[child_idx];
+            key = &key[prefix_len..];
         }
+        node.take_value(alloc)
+    }
 
-        match self.select_next_child(key) {
-            Some((prefix_len, child_idx)) => {
-                let children = self.children_mut();
-                let removed = children[child_idx].remove(&key[prefix_len..]);
+    /// Merges the node of `key`, whose value was taken, with its only child
+    /// and removes the nodes left empty on the path to it. A parent left
+    /// without a value and with a single child is merged with that child, so
+    /// removals never leave chains of non-branching nodes. Nodes that can't
+    /// be reallocated are left as they are, which keeps the tree valid.
+    #[inline]
+    pub(crate) fn compact<A: NodeAllocator>(
+        &mut self,
+        alloc: &A,
+        policy: GrowthPolicy,
+        key: &[u8],
+    ) {
+        // Nodes on the path to the key and the positions of the children
+        // taken. Nodes are only modified bottom up, so the pointers stay
+        // valid.
+        let mut path: Vec<(*mut Node<T, U>, usize)> = vec![];
+        let mut node: *mut Node<T, U> = self;
+        let mut key = key;
+        while !key.is_empty() {
+            let Some((prefix_len, child_idx)) = (unsafe { (*node).select_next_child(key) }) else {
+                return;
+            };
+            path.push((node, child_idx));
+            node = unsafe { &mut (*node).children_mut()[child_idx] };
+            key = &key[prefix_len..];
+        }
 
-                if removed.is_some() && children[child_idx].is_empty() {
-                    self.remove_child(child_idx);
-                }
+        unsafe { (*node).merge_only_child(alloc) };
 
-                removed
+        // Remove nodes left empty
+        for (parent, child_idx) in path.into_iter().rev() {
+            let parent = unsafe { &mut *parent };
+            if !parent.children()[child_idx].is_empty() {
+                break;
             }
-            None => None,
+            match parent.try_remove_child(alloc, policy, child_idx) {
+                Ok(child) => child.drop_in(alloc),
+                Err(_) => break,
+            }
+            parent.inline_leaf(alloc);
+            parent.merge_only_child(alloc);
         }
     }
 
+    /// Merges the node with its child if it has no value and a single child.
     #[inline]
-    pub(crate) fn get(&self, key: &[u8]) -> Option<&T> {
-        if key.is_empty() {
-            return self.value();
+    fn merge_only_child<A: NodeAllocator>(&mut self, alloc: &A) {
+        // The root's key is always empty and is never merged with its child.
+        if (self.key_len() > 0) && (self.children().len() == 1) && self.value().is_none() {
+            let _ = self.try_merge_child(alloc);
         }
+    }
 
-        match self.select_next_child(key) {
-            Some((prefix_len, child_idx)) => self.children()[child_idx].get(&key[prefix_len..]),
-            None => None,
+    #[inline]
+    pub(crate) fn get(&self, key: &[u8]) -> Option<&T> {
+        let mut node = self;
+        let mut key = key;
+        while !key.is_empty() {
+            let (prefix_len, child_idx) = node.select_next_child(key)?;
+            node = &node.children()[child_idx];
+            key = &key[prefix_len..];
         }
+        node.value()
     }
 
     #[inline]
     pub(crate) fn get_mut(&mut self, key: &[u8]) -> Option<&mut T> {
-        if key.is_empty() {
-            return self.value_mut();
-        }
-
-        match self.select_next_child(key) {
-            Some((prefix_len, child_idx)) => {
-                self.children_mut()[child_idx].get_mut(&key[prefix_len..])
-            }
-            None => None,
+        let mut node = self;
+        let mut key = key;
+        while !key.is_empty() {
+            let (prefix_len, child_idx) = node.select_next_child(key)?;
+            node = &mut node.children_mut()[child_idx];
+            key = &key[prefix_len..];
         }
+        node.value_mut()
     }
 
-    /// Returns a reference to a node which matches a given prefix.
+    /// Returns a reference to a node which matches a given prefix and the
+    /// offset of the node's key in the prefix.
     #[inline]
-    pub(crate) fn find_prefix(&self, prefix: &[u8]) -> Option<(usize, &Node<T>)> {
-        let (prefix_len, child_idx) = longest_common_prefix(self.children(), prefix);
-        if prefix_len == 0 {
-            // No child matches the prefix
-            return None;
-        }
+    pub(crate) fn find_prefix(&self, prefix: &[u8]) -> Option<(usize, &Node<T, U>)> {
+        let mut node = self;
+        let mut offset = 0;
+        loop {
+            let suffix = &prefix[offset..];
+            let (prefix_len, child_idx) = longest_common_prefix(node, suffix);
+            if prefix_len == 0 {
+                // No child matches the prefix
+                return None;
+            }
 
-        let suffix = &prefix[prefix_len..];
-        let child = &self.children()[child_idx];
-        if suffix.is_empty() {
-            return Some((0, child));
+            let child = &node.children()[child_idx];
+            if prefix_len == suffix.len() {
+                return Some((offset, child));
+            }
+            if prefix_len < child.key().len() {
+                // The prefix diverges from the child's key
+                return None;
+            }
+            node = child;
+            offset += prefix_len;
         }
-        child
-            .find_prefix(&prefix[prefix_len..])
-            .map(|(k, n)| (prefix_len + k, n))
     }
 
     /// Returns a mutable reference to a node which matches a given prefix.
@@ -245,21 +524,44 @@ impl<T> Node<T> {
     /// to the value and not to mutate the node's structure (e.g. by calling
     /// insert or remove).
     #[inline]
-    pub(crate) fn find_prefix_mut(&mut self, prefix: &[u8]) -> Option<(usize, &mut Node<T>)> {
-        let (prefix_len, child_idx) = longest_common_prefix(self.children(), prefix);
-        if prefix_len == 0 {
-            // No child matches the prefix
-            return None;
+    pub(crate) fn find_prefix_mut(&mut self, prefix: &[u8]) -> Option<(usize, &mut Node<T, U>)> {
+        let mut node = self;
+        let mut offset = 0;
+        loop {
+            let suffix = &prefix[offset..];
+            let (prefix_len, child_idx) = longest_common_prefix(node, suffix);
+            if prefix_len == 0 {
+                // No child matches the prefix
+                return None;
+            }
+
+            let child = &mut node.children_mut()[child_idx];
+            if prefix_len == suffix.len() {
+                return Some((offset, child));
+            }
+            if prefix_len < child.key().len() {
+                // The prefix diverges from the child's key
+                return None;
+            }
+            node = child;
+            offset += prefix_len;
         }
+    }
 
-        let suffix = &prefix[prefix_len..];
-        let child = &mut self.children_mut()[child_idx];
-        if suffix.is_empty() {
-            return Some((0, child));
+    /// Returns the number of bytes allocated for this node.
+    #[inline(always)]
+    pub(crate) fn allocated_size(&self) -> usize {
+        if self.is_inline() {
+            return 0;
         }
-        child
-            .find_prefix_mut(&prefix[prefix_len..])
-            .map(|(k, n)| (prefix_len + k, n))
+        self.curr_layout().size()
+    }
+
+    /// Returns `true` if this is a leaf stored without an allocation.
+    #[inline(always)]
+    pub(crate) fn is_inline(&self) -> bool {
+        cfg!(target_endian = "little")
+            && unsafe { *(self.data.get() as *const u8) } & Flags::INLINE.bits() != 0
     }
 
     #[inline(always)]
@@ -269,22 +571,33 @@ impl<T> Node<T> {
 
     // Memory management
     #[inline(always)]
-    fn create_layout(flags: Flags, key_len: usize, children_count: usize) -> Layout {
-        let mut layout = Layout::array::<u8>(key_len + 2).expect("invalid layout");
+    fn create_layout(flags: Flags, key_len: usize, children_capacity: usize) -> Layout {
+        // Keeps the lowest bit of the pointer clear for inline leaves.
+        let mut layout = Layout::array::<u8>(Self::key_offset(key_len) + key_len)
+            .and_then(|l| l.align_to(2))
+            .expect("invalid layout");
 
         if flags.contains(Flags::VALUE_ALLOCATED) {
-            layout = layout.extend(Layout::new::<T>()).expect("invalid layout").0;
+            layout = layout
+                .extend(Self::value_layout())
+                .expect("invalid layout")
+                .0;
         }
 
         if flags.contains(Flags::HAS_CHILDREN) {
+            let mut bytes_len = 2 + children_capacity;
+            if children_capacity > DENSE_THRESHOLD {
+                bytes_len += 256;
+            }
             layout = layout
-                .extend(Layout::new::<u8>())
+                .extend(Layout::array::<u8>(bytes_len).expect("invalid layout"))
                 .expect("invalid layout")
                 .0;
             layout = layout
-                .extend(Layout::array::<Node<T>>(children_count).expect("invalid layout"))
+                .extend(Layout::array::<Node<T, U>>(children_capacity).expect("invalid layout"))
                 .expect("invalid layout")
                 .0;
+            layout = layout.extend(Layout::new::<U>()).expect("invalid layout").0;
         }
 
         layout.pad_to_align()
@@ -292,188 +605,587 @@ impl<T> Node<T> {
 
     #[inline(always)]
     fn flags(&self) -> Flags {
-        unsafe { Flags::from_bits(*self.data.as_ptr()).expect("invalid flags") }
+        unsafe { Flags::from_bits(*self.data_ptr()).expect("invalid flags") }
     }
 
     #[inline(always)]
     fn set_flags(&self, other: Flags, value: bool) {
         let mut flags = self.flags();
         flags.set(other, value);
-        unsafe { ptr::write(self.data.as_ptr(), flags.bits()) }
+        unsafe { ptr::write(self.data_ptr(), flags.bits()) }
     }
 
     #[inline(always)]
     fn curr_layout(&self) -> Layout {
-        Self::create_layout(self.flags(), self.key_len(), self.children().len())
+        Self::create_layout(self.flags(), self.key_len(), self.children_capacity())
     }
 
     #[inline(always)]
-    fn alloc(flags: Flags, key_len: usize) -> ptr::NonNull<u8> {
-        let layout = Self::create_layout(flags, key_len, 0);
-        let ptr = unsafe { alloc(layout) };
-        let data = ptr::NonNull::new(ptr).expect("allocation failed");
+    fn alloc<A: NodeAllocator>(
+        alloc: &A,
+        flags: Flags,
+        key_len: usize,
+        children_capacity: usize,
+    ) -> ptr::NonNull<u8> {
+        Self::try_alloc(alloc, flags, key_len, children_capacity).expect("allocation failed")
+    }
+
+    #[inline(always)]
+    fn try_alloc<A: NodeAllocator>(
+        alloc: &A,
+        flags: Flags,
+        key_len: usize,
+        children_capacity: usize,
+    ) -> Result<ptr::NonNull<u8>, RadixError> {
+        let layout = Self::create_layout(flags, key_len, children_capacity);
+        let data = alloc.allocate(layout).ok_or(RadixError::AllocError)?;
         unsafe {
             ptr::write(data.as_ptr(), flags.bits());
         }
-        data
+        Ok(data)
     }
 
-    #[inline(always)]
-    fn realloc(
+    /// Same as [`Self::try_realloc()`] but panics if the allocation fails.
+    #[inline]
+    fn realloc<A: NodeAllocator>(
         &mut self,
-        old_layout: Layout,
+        alloc: &A,
         new_flags: Flags,
         key_len: usize,
         children_count: usize,
+        children_capacity: usize,
     ) {
-        let new_layout = Self::create_layout(new_flags, key_len, children_count);
-        let new_ptr = unsafe { realloc(self.data.as_ptr(), old_layout, new_layout.size()) };
-        self.data = ptr::NonNull::new(new_ptr).expect("allocation failed");
+        self.try_realloc(alloc, new_flags, key_len, children_count, children_capacity)
+            .expect("allocation failed")
+    }
+
+    /// Changes the node's layout and writes the new flags, key length,
+    /// children count and capacity. The key, the value, the index and the
+    /// first `children_count` children with their first bytes are moved to
+    /// their new offsets. The key can't shrink. Callers changing the children
+    /// have to rebuild the index. If the allocation fails the node keeps its
+    /// layout and the kept sections. Sections that are not kept may be
+    /// overwritten if the node shrinks.
+    #[inline]
+    fn try_realloc<A: NodeAllocator>(
+        &mut self,
+        alloc: &A,
+        new_flags: Flags,
+        key_len: usize,
+        children_count: usize,
+        children_capacity: usize,
+    ) -> Result<(), RadixError> {
+        debug_assert!(children_count <= children_capacity);
+        debug_assert!(key_len >= self.key_len());
+        debug_assert!(!self.is_inline());
+        let old_layout = self.curr_layout();
+        let new_layout = Self::create_layout(new_flags, key_len, children_capacity);
+        let sections = self.sections(new_flags, 0, key_len, children_count, children_capacity);
+
         unsafe {
-            ptr::write(self.data.as_ptr(), new_flags.bits());
+            if new_layout.size() >= old_layout.size() {
+                let data = alloc
+                    .reallocate(self.heap_ptr(), old_layout, new_layout)
+                    .ok_or(RadixError::AllocError)?;
+                self.set_heap_ptr(data);
+                move_sections(self.data_ptr(), &sections);
+            } else {
+                // Sections are moved before the block shrinks and moved back
+                // if it can't be shrunk or the allocator panics.
+                move_sections(self.data_ptr(), &sections);
+                let rollback = Rollback {
+                    node: &mut *self,
+                    sections,
+                };
+                let data = alloc
+                    .reallocate(rollback.node.heap_ptr(), old_layout, new_layout)
+                    .ok_or(RadixError::AllocError)?;
+                mem::forget(rollback);
+                self.set_heap_ptr(data);
+            }
+            self.write_header(new_flags, key_len, children_count, children_capacity);
         }
+        Ok(())
     }
 
+    /// Returns the (old offset, new offset, size) of each section that is
+    /// kept when the node's layout changes. The new key starts at
+    /// `key_start` in the old one.
+    #[inline]
+    fn sections(
+        &self,
+        new_flags: Flags,
+        key_start: usize,
+        key_len: usize,
+        children_count: usize,
+        children_capacity: usize,
+    ) -> [(usize, usize, usize); 6] {
+        let old_flags = self.flags();
+        let old_key_len = self.key_len();
+        let old_children_count = self.children().len();
+        let old_children_capacity = self.children_capacity();
+
+        let mut sections = [(0, 0, 0); 6];
+        sections[0] = (
+            Self::key_offset(old_key_len) + key_start,
+            Self::key_offset(key_len),
+            (old_key_len - key_start).min(key_len),
+        );
+        if old_flags.contains(Flags::VALUE_ALLOCATED) && new_flags.contains(Flags::VALUE_ALLOCATED)
+        {
+            sections[1] = (
+                Self::value_offset(old_key_len),
+                Self::value_offset(key_len),
+                Self::value_layout().size(),
+            );
+        }
+        if old_flags.contains(Flags::HAS_CHILDREN) && new_flags.contains(Flags::HAS_CHILDREN) {
+            let moved_count = old_children_count.min(children_count);
+            sections[2] = (
+                Self::children_len_offset(old_flags, old_key_len) + 2,
+                Self::children_len_offset(new_flags, key_len) + 2,
+                moved_count,
+            );
+            if old_children_capacity > DENSE_THRESHOLD && children_capacity > DENSE_THRESHOLD {
+                sections[3] = (
+                    sections[2].0 + old_children_capacity,
+                    sections[2].1 + children_capacity,
+                    256,
+                );
+            }
+            sections[4] = (
+                Self::children_offset(old_flags, old_key_len, old_children_capacity),
+                Self::children_offset(new_flags, key_len, children_capacity),
+                moved_count * size_of::<Node<T, U>>(),
+            );
+            sections[5] = (
+                Self::summary_offset(old_flags, old_key_len, old_children_capacity),
+                Self::summary_offset(new_flags, key_len, children_capacity),
+                size_of::<U>(),
+            );
+        }
+        sections
+    }
+
+    /// Writes the flags, key length, children count and capacity.
     #[inline(always)]
-    fn data_size(&self) -> usize {
-        let mut size = 2 + self.key_len();
-        if self.flags().contains(Flags::VALUE_ALLOCATED) {
-            size += size_of::<T>();
+    unsafe fn write_header(
+        &self,
+        flags: Flags,
+        key_len: usize,
+        children_count: usize,
+        children_capacity: usize,
+    ) {
+        ptr::write(self.data_ptr(), flags.bits());
+        self.write_key_len(key_len);
+        if flags.contains(Flags::HAS_CHILDREN) {
+            ptr::write(self.children_len_ptr(), (children_count - 1) as u8);
+            ptr::write(
+                self.children_len_ptr().add(1),
+                (children_capacity - 1) as u8,
+            );
         }
-        if self.flags().contains(Flags::HAS_CHILDREN) {
-            size += 1 + self.children().len() * size_of::<Node<T>>();
+    }
+
+    /// Moves an inline leaf into an allocated node.
+    #[cfg(test)]
+    fn promote<A: NodeAllocator>(&mut self, alloc: &A) {
+        self.try_promote(alloc).expect("allocation failed")
+    }
+
+    /// Same as [`Self::promote()`] but returns an error and leaves the leaf
+    /// inline if the allocation fails.
+    fn try_promote<A: NodeAllocator>(&mut self, alloc: &A) -> Result<(), RadixError> {
+        if !self.is_inline() {
+            return Ok(());
+        }
+        let flags = self.flags() - Flags::INLINE;
+        let data = Self::try_alloc(alloc, flags, self.key_len(), 0)?;
+        unsafe {
+            // Copy key length, key and value
+            ptr::copy_nonoverlapping(
+                self.data_ptr().add(1),
+                data.as_ptr().add(1),
+                Self::children_len_offset(flags, self.key_len()) - 1,
+            );
+        }
+        self.set_heap_ptr(data);
+        Ok(())
+    }
+
+    /// Moves a leaf with a value into the node's pointer if it fits. The
+    /// root, whose key is always empty, is never inlined.
+    fn inline_leaf<A: NodeAllocator>(&mut self, alloc: &A) {
+        let flags = self.flags();
+        if self.is_inline()
+            || flags.contains(Flags::HAS_CHILDREN)
+            || !flags.contains(Flags::VALUE_INITIALIZED)
+            || self.key_len() == 0
+            || !Self::fits_inline(self.key_len())
+        {
+            return;
+        }
+        let heap_ptr = self.heap_ptr();
+        let layout = self.curr_layout();
+        let node = Self::new_inline(flags);
+        unsafe {
+            // Copy key length, key and value
+            ptr::copy_nonoverlapping(
+                heap_ptr.as_ptr().add(1),
+                node.data_ptr().add(1),
+                Self::children_len_offset(flags, self.key_len()) - 1,
+            );
+            alloc.deallocate(heap_ptr, layout);
+            ptr::write(self, node);
         }
-        size
     }
 
+    /// Returns a pointer to the node's flags. This is the start of the
+    /// allocation or of the inline leaf.
     #[inline(always)]
+    fn data_ptr(&self) -> *mut u8 {
+        if self.is_inline() {
+            self.data.get() as *mut u8
+        } else {
+            self.heap_ptr().as_ptr()
+        }
+    }
+
+    #[inline(always)]
+    fn heap_ptr(&self) -> ptr::NonNull<u8> {
+        debug_assert!(!self.is_inline());
+        unsafe { (*self.data.get()).assume_init() }
+    }
+
+    #[inline(always)]
+    fn set_heap_ptr(&mut self, data: ptr::NonNull<u8>) {
+        *self.data.get_mut() = MaybeUninit::new(data);
+    }
+
+    #[inline]
     fn key_len(&self) -> usize {
-        unsafe { *self.key_len_ptr() as usize }
+        unsafe {
+            let ptr = self.data_ptr().add(1);
+            // Most keys are shorter than 128 bytes
+            if *ptr < 0x80 {
+                return *ptr as usize;
+            }
+            read_varint(ptr)
+        }
     }
 
     #[inline(always)]
-    unsafe fn key_len_ptr(&self) -> *mut u8 {
-        self.data.as_ptr().add(1)
+    unsafe fn write_key_len(&self, key_len: usize) {
+        write_varint(self.data_ptr().add(1), key_len);
     }
 
     #[inline(always)]
     unsafe fn key_ptr(&self) -> *mut u8 {
-        self.data.as_ptr().add(2)
+        self.data_ptr().add(Self::key_offset(self.key_len()))
     }
 
+    /// Returns a pointer to the value, which is either embedded in the node
+    /// or allocated separately.
     #[inline(always)]
     unsafe fn value_ptr(&self) -> *mut T {
+        let slot = self.value_slot_ptr();
+        if Self::BOXED_VALUE {
+            *(slot as *mut *mut T)
+        } else {
+            slot as *mut T
+        }
+    }
+
+    #[inline(always)]
+    unsafe fn value_slot_ptr(&self) -> *mut u8 {
         assert!(self.flags().contains(Flags::VALUE_ALLOCATED));
-        self.data.as_ptr().add(2 + self.key_len()) as *mut T
+        self.data_ptr().add(Self::value_offset(self.key_len()))
     }
 
+    /// Writes the value into an allocated but uninitialized value slot using
+    /// `storage` returned by [`Self::try_alloc_value()`].
     #[inline(always)]
+    unsafe fn write_value_in(&mut self, storage: ptr::NonNull<T>, value: T) {
+        let slot = self.value_slot_ptr();
+        if Self::BOXED_VALUE {
+            ptr::write(storage.as_ptr(), value);
+            ptr::write(slot as *mut *mut T, storage.as_ptr());
+        } else {
+            ptr::write(slot as *mut T, value);
+        }
+    }
+
+    /// Allocates the storage of a value that does not fit in nodes. Returns
+    /// a dangling pointer if values are stored in nodes.
+    #[inline(always)]
+    fn try_alloc_value<A: NodeAllocator>(alloc: &A) -> Result<ptr::NonNull<T>, RadixError> {
+        if !Self::BOXED_VALUE {
+            return Ok(ptr::NonNull::dangling());
+        }
+        alloc
+            .allocate(Layout::new::<T>())
+            .map(|ptr| ptr.cast())
+            .ok_or(RadixError::AllocError)
+    }
+
+    /// Deallocates unused storage returned by [`Self::try_alloc_value()`].
+    #[inline(always)]
+    unsafe fn dealloc_value<A: NodeAllocator>(alloc: &A, storage: ptr::NonNull<T>) {
+        if Self::BOXED_VALUE {
+            alloc.deallocate(storage.cast(), Layout::new::<T>());
+        }
+    }
+
+    /// Moves the value out of its slot. The slot is left uninitialized.
+    #[inline(always)]
+    unsafe fn read_value<A: NodeAllocator>(&self, alloc: &A) -> T {
+        let ptr = self.value_ptr();
+        let value = ptr::read(ptr);
+        if Self::BOXED_VALUE {
+            alloc.deallocate(
+                ptr::NonNull::new_unchecked(ptr as *mut u8),
+                Layout::new::<T>(),
+            );
+        }
+        value
+    }
+
+    /// Layout of the value slot of a node.
+    #[inline(always)]
+    fn value_layout() -> Layout {
+        if Self::BOXED_VALUE {
+            Layout::new::<*mut T>()
+        } else {
+            Layout::new::<T>()
+        }
+    }
+
+    #[inline]
     unsafe fn children_len_ptr(&self) -> *mut u8 {
         assert!(self.flags().contains(Flags::HAS_CHILDREN));
-        let mut ptr = self.data.as_ptr().add(2 + self.key_len());
-        if self.flags().contains(Flags::VALUE_ALLOCATED) {
-            ptr = ptr.add(size_of::<T>());
+        self.data_ptr()
+            .add(Self::children_len_offset(self.flags(), self.key_len()))
+    }
+
+    #[inline(always)]
+    unsafe fn first_bytes_ptr(&self) -> *mut u8 {
+        self.children_len_ptr().add(2)
+    }
+
+    #[inline(always)]
+    unsafe fn index_ptr(&self) -> *mut u8 {
+        self.first_bytes_ptr().add(self.children_capacity())
+    }
+
+    #[inline]
+    unsafe fn children_ptr(&self) -> *mut Node<T, U> {
+        assert!(self.flags().contains(Flags::HAS_CHILDREN));
+        self.data_ptr().add(Self::children_offset(
+            self.flags(),
+            self.key_len(),
+            self.children_capacity(),
+        )) as *mut Node<T, U>
+    }
+
+    #[inline]
+    unsafe fn summary_ptr(&self) -> *mut U {
+        assert!(self.flags().contains(Flags::HAS_CHILDREN));
+        self.data_ptr().add(Self::summary_offset(
+            self.flags(),
+            self.key_len(),
+            self.children_capacity(),
+        )) as *mut U
+    }
+
+    // Offsets must match the ones computed by `create_layout`.
+    #[inline(always)]
+    fn key_offset(key_len: usize) -> usize {
+        1 + varint_len(key_len)
+    }
+
+    #[inline(always)]
+    fn value_offset(key_len: usize) -> usize {
+        align_up(
+            Self::key_offset(key_len) + key_len,
+            Self::value_layout().align(),
+        )
+    }
+
+    #[inline(always)]
+    fn children_len_offset(flags: Flags, key_len: usize) -> usize {
+        if flags.contains(Flags::VALUE_ALLOCATED) {
+            Self::value_offset(key_len) + Self::value_layout().size()
+        } else {
+            Self::key_offset(key_len) + key_len
+        }
+    }
+
+    #[inline(always)]
+    fn children_offset(flags: Flags, key_len: usize, children_capacity: usize) -> usize {
+        let mut offset = Self::children_len_offset(flags, key_len) + 2 + children_capacity;
+        if children_capacity > DENSE_THRESHOLD {
+            offset += 256;
         }
-        ptr
+        align_up(offset, align_of::<Node<T, U>>())
     }
 
     #[inline(always)]
-    unsafe fn children_ptr(&self) -> *mut Node<T> {
-        self.children_len_ptr().add(1) as *mut Node<T>
+    fn summary_offset(flags: Flags, key_len: usize, children_capacity: usize) -> usize {
+        align_up(
+            Self::children_offset(flags, key_len, children_capacity)
+                + children_capacity * size_of::<Node<T, U>>(),
+            align_of::<U>(),
+        )
+    }
+
+    /// Rebuilds the index of a dense node from the first bytes of its children.
+    #[inline]
+    fn update_index(&mut self) {
+        let first_bytes = self.first_bytes();
+        if self.children_capacity() > DENSE_THRESHOLD {
+            unsafe {
+                let index = self.index_ptr();
+                ptr::write_bytes(index, 0, 256);
+                for (idx, &b) in first_bytes.iter().enumerate() {
+                    ptr::write(index.add(b as usize), idx as u8);
+                }
+            }
+        }
     }
 
     // Key access methods
+    #[cfg(test)]
+    #[inline]
+    fn strip_key_prefix<A: NodeAllocator>(&mut self, alloc: &A, prefix_len: usize) {
+        self.try_strip_key_prefix(alloc, prefix_len)
+            .expect("allocation failed")
+    }
+
+    /// Same as [`Self::strip_key_prefix()`] but returns an error and leaves
+    /// the node unchanged if the allocation fails.
     #[inline]
-    fn strip_key_prefix(&mut self, prefix_len: usize) {
+    fn try_strip_key_prefix<A: NodeAllocator>(
+        &mut self,
+        alloc: &A,
+        prefix_len: usize,
+    ) -> Result<(), RadixError> {
         assert!(prefix_len <= self.key_len(), "Invalid prefix len");
 
-        let old_layout = self.curr_layout();
         let new_key_len = self.key_len() - prefix_len;
-        let copy_size = self.data_size() - prefix_len - 2;
+        if self.is_inline() {
+            unsafe {
+                // Shift key left
+                ptr::copy(self.key_ptr().add(prefix_len), self.key_ptr(), new_key_len);
+                // Shift value left. Inline leaves always have a value allocated.
+                let data = self.data_ptr();
+                ptr::copy(
+                    data.add(Self::value_offset(self.key_len())),
+                    data.add(Self::value_offset(new_key_len)),
+                    Self::value_layout().size(),
+                );
+                self.write_key_len(new_key_len);
+            }
+            return Ok(());
+        }
+
+        // The node is copied to a new block rather than shrunk in place so
+        // that the stripped prefix is not overwritten before the allocation
+        // succeeds.
+        let flags = self.flags();
+        let children_count = self.children().len();
+        let children_capacity = self.children_capacity();
+        let sections = self.sections(
+            flags,
+            prefix_len,
+            new_key_len,
+            children_count,
+            children_capacity,
+        );
+        let data = Self::try_alloc(alloc, flags, new_key_len, children_capacity)?;
         unsafe {
-            // Write new length
-            ptr::write(self.key_len_ptr(), new_key_len as u8);
-            // Shift left
-            ptr::copy(self.key_ptr().add(prefix_len), self.key_ptr(), copy_size)
+            for &(src, dst, size) in sections.iter() {
+                ptr::copy_nonoverlapping(self.data_ptr().add(src), data.as_ptr().add(dst), size);
+            }
+            alloc.deallocate(self.heap_ptr(), self.curr_layout());
+            self.set_heap_ptr(data);
+            self.write_header(flags, new_key_len, children_count, children_capacity);
         }
-        self.realloc(old_layout, self.flags(), new_key_len, self.children().len());
+        // A shorter key may fit inline now
+        self.inline_leaf(alloc);
+        Ok(())
     }
 
+    #[cfg(test)]
     #[inline]
-    fn extend_key(&mut self, suffix: &[u8]) {
-        let new_key_len = self.key_len() + suffix.len();
-        assert!(new_key_len < 256, "Cannot extend key. Suffix is too long.");
+    fn extend_key<A: NodeAllocator>(&mut self, alloc: &A, suffix: &[u8]) {
+        let key_len = self.key_len();
+        let new_key_len = key_len + suffix.len();
 
         self.realloc(
-            self.curr_layout(),
+            alloc,
             self.flags(),
             new_key_len,
             self.children().len(),
+            self.children_capacity(),
         );
 
         unsafe {
-            // Shift value + children right
-            ptr::copy(
-                self.key_ptr().add(self.key_len()),
-                self.key_ptr().add(new_key_len),
-                self.data_size() - self.key_len() - 2,
-            );
             // Extend key
-            ptr::copy(
-                suffix.as_ptr(),
-                self.key_ptr().add(self.key_len()),
-                suffix.len(),
-            );
-            // Write new key length
-            ptr::write(self.key_len_ptr(), new_key_len as u8);
+            ptr::copy(suffix.as_ptr(), self.key_ptr().add(key_len), suffix.len());
         }
     }
 
     // Value access methods
+    #[cfg(test)]
     #[inline]
-    fn replace_value(&mut self, value: T) -> Option<T> {
+    fn replace_value<A: NodeAllocator>(&mut self, alloc: &A, value: T) -> Option<T> {
+        self.try_replace_value(alloc, value)
+            .expect("allocation failed")
+    }
+
+    /// Same as [`Self::replace_value()`] but returns an error, drops the
+    /// value and leaves the node unchanged if an allocation fails.
+    #[inline]
+    fn try_replace_value<A: NodeAllocator>(
+        &mut self,
+        alloc: &A,
+        value: T,
+    ) -> Result<Option<T>, RadixError> {
+        if self.flags().contains(Flags::VALUE_INITIALIZED) {
+            // Replace old value
+            return Ok(Some(unsafe {
+                mem::replace::<T>(&mut *self.value_ptr(), value)
+            }));
+        }
+
+        let mut unlinked = Unlinked::<T, U, A>::new(alloc);
+        let storage = *unlinked.storage.insert(Self::try_alloc_value(alloc)?);
         if !self.flags().contains(Flags::VALUE_ALLOCATED) {
             // Allocate value if it's not allocated
-            let children_count = self.children().len();
-
             let mut new_flags = self.flags();
             new_flags.set(Flags::VALUE_ALLOCATED, true);
-            self.realloc(
-                self.curr_layout(),
+            self.try_realloc(
+                alloc,
                 new_flags,
                 self.key_len(),
-                children_count,
-            );
-
-            if self.flags().contains(Flags::HAS_CHILDREN) {
-                // Move children to the right to make space for value
-                unsafe {
-                    ptr::copy(
-                        self.value_ptr() as *mut u8,
-                        self.children_len_ptr(),
-                        1 + children_count * size_of::<Node<T>>(),
-                    )
-                }
-            }
+                self.children().len(),
+                self.children_capacity(),
+            )?;
         }
+        unlinked.storage = None;
 
-        if self.flags().contains(Flags::VALUE_INITIALIZED) {
-            // Replace old value
-            Some(unsafe { std::mem::replace::<T>(&mut *self.value_ptr(), value) })
-        } else {
-            // Write value and set initialized flag
-            unsafe {
-                ptr::write(self.value_ptr(), value);
-            }
-            self.set_flags(Flags::VALUE_INITIALIZED, true);
-            None
+        // Write value and set initialized flag
+        unsafe {
+            self.write_value_in(storage, value);
         }
+        self.set_flags(Flags::VALUE_INITIALIZED, true);
+        Ok(None)
     }
 
     // Children access methods
     #[inline(always)]
     fn select_next_child(&self, key: &[u8]) -> Option<(usize, usize)> {
-        let (prefix_len, child_idx) = longest_common_prefix(self.children(), key);
+        let (prefix_len, child_idx) = longest_common_prefix(self, key);
         if (prefix_len == 0) || (prefix_len < self.children()[child_idx].key().len()) {
             // There is no or only a partial match in which case the
             // key does not exist in the tree.
@@ -482,92 +1194,225 @@ impl<T> Node<T> {
         Some((prefix_len, child_idx))
     }
 
+    #[cfg(test)]
     #[inline]
-    fn insert_child(&mut self, idx: usize, node: Node<T>) {
-        assert!(idx <= self.children().len(), "invalid offset");
-        assert!(self.children().len() < 256, "Children array is full");
+    fn insert_child<A: NodeAllocator>(
+        &mut self,
+        alloc: &A,
+        policy: GrowthPolicy,
+        idx: usize,
+        node: Node<T, U>,
+    ) {
+        self.try_insert_child(alloc, policy, idx, node)
+            .expect("allocation failed")
+    }
 
-        if !self.flags().contains(Flags::HAS_CHILDREN) {
-            // Allocate children
-            let mut new_flags = self.flags();
-            new_flags.set(Flags::HAS_CHILDREN, true);
-            self.realloc(self.curr_layout(), new_flags, self.key_len(), 1);
-            // Insert at 0th position
-            unsafe {
-                ptr::write(self.children_len_ptr(), 0);
-                ptr::write(self.children_ptr(), node);
+    /// Same as [`Self::insert_child()`] but returns an error, drops `node`
+    /// and leaves this node unchanged if an allocation fails.
+    #[inline]
+    fn try_insert_child<A: NodeAllocator>(
+        &mut self,
+        alloc: &A,
+        policy: GrowthPolicy,
+        idx: usize,
+        node: Node<T, U>,
+    ) -> Result<(), RadixError> {
+        let mut unlinked = Unlinked::<T, U, A>::new(alloc);
+        unlinked.node = Some(node);
+        let children_count = self.children().len();
+        assert!(idx <= children_count, "invalid offset");
+        assert!(children_count < 256, "Children array is full");
+
+        if children_count == self.children_capacity() {
+            // Grow
+            let was_inline = self.is_inline();
+            let grown = self.try_promote(alloc).and_then(|_| {
+                let mut new_flags = self.flags();
+                new_flags.set(Flags::HAS_CHILDREN, true);
+                self.try_realloc(
+                    alloc,
+                    new_flags,
+                    self.key_len(),
+                    children_count + 1,
+                    policy.capacity(children_count + 1),
+                )
+            });
+            if let Err(err) = grown {
+                if was_inline {
+                    self.inline_leaf(alloc);
+                }
+                return Err(err);
             }
         } else {
-            // Grow
-            self.realloc(
-                self.curr_layout(),
-                self.flags(),
-                self.key_len(),
-                self.children().len() + 1,
-            );
+            unsafe { ptr::write(self.children_len_ptr(), children_count as u8) };
+        }
+        let node = unlinked.node.take().unwrap();
 
-            // Insert
-            unsafe {
-                // Shift children to the right
-                let node_ptr = self.children_ptr();
-                ptr::copy(
-                    node_ptr.add(idx),
-                    node_ptr.add(idx + 1),
-                    self.children().len() - idx,
-                );
-                // Write new node
-                ptr::write(node_ptr.add(idx), node);
-                // Increment count
-                ptr::write(self.children_len_ptr(), *self.children_len_ptr() + 1);
-            }
+        // Insert
+        unsafe {
+            // Shift children and their first bytes to the right
+            let bytes_ptr = self.first_bytes_ptr();
+            ptr::copy(
+                bytes_ptr.add(idx),
+                bytes_ptr.add(idx + 1),
+                children_count - idx,
+            );
+            let node_ptr = self.children_ptr();
+            ptr::copy(
+                node_ptr.add(idx),
+                node_ptr.add(idx + 1),
+                children_count - idx,
+            );
+            // Write new node
+            ptr::write(bytes_ptr.add(idx), node.key()[0]);
+            ptr::write(node_ptr.add(idx), node);
         }
+        self.update_index();
+        Ok(())
     }
 
+    #[cfg(test)]
     #[inline(always)]
-    pub(super) fn push_child(&mut self, node: Node<T>) {
-        self.insert_child(self.children().len(), node);
+    pub(super) fn push_child<A: NodeAllocator>(
+        &mut self,
+        alloc: &A,
+        policy: GrowthPolicy,
+        node: Node<T, U>,
+    ) {
+        self.insert_child(alloc, policy, self.children().len(), node);
     }
 
+    #[cfg(test)]
     #[inline]
-    fn remove_child(&mut self, idx: usize) -> Node<T> {
+    fn remove_child<A: NodeAllocator>(
+        &mut self,
+        alloc: &A,
+        policy: GrowthPolicy,
+        idx: usize,
+    ) -> Node<T, U> {
+        self.try_remove_child(alloc, policy, idx)
+            .expect("allocation failed")
+    }
+
+    /// Same as [`Self::remove_child()`] but returns an error and leaves the
+    /// node unchanged if the only child is removed and the children array
+    /// can't be deallocated. Children arrays that can't be shrunk keep their
+    /// capacity.
+    #[inline]
+    fn try_remove_child<A: NodeAllocator>(
+        &mut self,
+        alloc: &A,
+        policy: GrowthPolicy,
+        idx: usize,
+    ) -> Result<Node<T, U>, RadixError> {
         assert!(idx < self.children().len(), "invalid offset");
 
         if self.flags().contains(Flags::HAS_CHILDREN) {
             let removed = unsafe { ptr::read(self.children_ptr().add(idx)) };
             if self.children().len() == 1 {
-                // Deallocate children
-                let mut new_flags = self.flags();
-                new_flags.set(Flags::HAS_CHILDREN, false);
-                self.realloc(self.curr_layout(), new_flags, self.key_len(), 0);
+                // Deallocate children. The key and the value don't move, so
+                // the child stays in place if this fails.
+                let new_flags = self.flags() - Flags::HAS_CHILDREN - Flags::HAS_SUMMARY;
+                self.try_realloc(alloc, new_flags, self.key_len(), 0, 0)?;
             } else {
-                assert!(self.children().len() > 1);
+                let children_count = self.children().len();
+                assert!(children_count > 1);
                 unsafe {
-                    // Decrement count
-                    ptr::write(self.children_len_ptr(), *self.children_len_ptr() - 1);
-                    // Shift children to the left
+                    // Shift children and their first bytes to the left
+                    let bytes_ptr = self.first_bytes_ptr();
+                    ptr::copy(
+                        bytes_ptr.add(idx + 1),
+                        bytes_ptr.add(idx),
+                        children_count - idx - 1,
+                    );
                     let node_ptr = self.children_ptr();
                     ptr::copy(
                         node_ptr.add(idx + 1),
                         node_ptr.add(idx),
-                        self.children().len() - idx,
+                        children_count - idx - 1,
                     );
                 }
-                // Shrink
-                self.realloc(
-                    self.curr_layout(),
-                    self.flags(),
-                    self.key_len(),
-                    self.children().len(),
-                );
+                let shrunk = match policy.shrink(children_count - 1, self.children_capacity()) {
+                    // Shrink
+                    Some(capacity) => self
+                        .try_realloc(
+                            alloc,
+                            self.flags(),
+                            self.key_len(),
+                            children_count - 1,
+                            capacity,
+                        )
+                        .is_ok(),
+                    None => false,
+                };
+                if !shrunk {
+                    unsafe { ptr::write(self.children_len_ptr(), (children_count - 2) as u8) }
+                }
+                self.update_index();
             }
-            removed
+            Ok(removed)
         } else {
             panic!("Cannot remove child. Node has not children.");
         }
     }
 
+    /// Replaces this node, which must have no value, with its only child
+    /// whose key is prefixed with this node's key. Returns an error and
+    /// leaves both nodes unchanged if the allocation fails.
+    fn try_merge_child<A: NodeAllocator>(&mut self, alloc: &A) -> Result<(), RadixError> {
+        debug_assert!(self.value().is_none() && self.children().len() == 1);
+        let child = unsafe { ptr::read(self.children_ptr()) };
+        let flags = child.flags() - Flags::INLINE;
+        let prefix_len = self.key_len();
+        let key_len = prefix_len + child.key_len();
+        let children_count = child.children().len();
+        let children_capacity = child.children_capacity();
+
+        let data = Self::try_alloc(alloc, flags, key_len, children_capacity)?;
+        let mut node = Node::from_ptr(data);
+        unsafe {
+            node.write_header(flags, key_len, children_count, children_capacity);
+            // Copy keys
+            ptr::copy_nonoverlapping(self.key_ptr(), node.key_ptr(), prefix_len);
+            ptr::copy_nonoverlapping(
+                child.key_ptr(),
+                node.key_ptr().add(prefix_len),
+                child.key_len(),
+            );
+            // Copy value
+            if flags.contains(Flags::VALUE_ALLOCATED) {
+                ptr::copy_nonoverlapping(
+                    child.value_slot_ptr(),
+                    node.value_slot_ptr(),
+                    Self::value_layout().size(),
+                );
+            }
+            // Copy children
+            if flags.contains(Flags::HAS_CHILDREN) {
+                ptr::copy_nonoverlapping(
+                    child.first_bytes_ptr(),
+                    node.first_bytes_ptr(),
+                    children_count,
+                );
+                ptr::copy_nonoverlapping(child.children_ptr(), node.children_ptr(), children_count);
+                ptr::copy_nonoverlapping(child.summary_ptr(), node.summary_ptr(), 1);
+                node.update_index();
+            }
+            // Deallocate both nodes
+            if !child.is_inline() {
+                alloc.deallocate(child.heap_ptr(), child.curr_layout());
+            }
+            alloc.deallocate(self.heap_ptr(), self.curr_layout());
+            ptr::write(self, node);
+        }
+        self.inline_leaf(alloc);
+        Ok(())
+    }
+
+    /// Moves all children of `src_node` to this node and deallocates `src_node`.
+    #[cfg(test)]
     #[inline]
-    fn move_children(&mut self, mut src_node: Node<T>) {
+    fn move_children<A: NodeAllocator>(&mut self, alloc: &A, mut src_node: Node<T, U>) {
         assert_eq!(
             self.children().len(),
             0,
@@ -576,143 +1421,449 @@ impl<T> Node<T> {
         let src_count = src_node.children().len();
         if src_count == 0 {
             // Nothing to move
+            src_node.drop_in(alloc);
             return;
         }
+        self.promote(alloc);
 
         // Allocate children
         let mut new_flags = self.flags();
         new_flags.set(Flags::HAS_CHILDREN, true);
-        self.realloc(self.curr_layout(), new_flags, self.key_len(), src_count);
+        self.realloc(alloc, new_flags, self.key_len(), src_count, src_count);
 
         // Copy from src node to self
         unsafe {
-            ptr::copy(
-                src_node.children_len_ptr(),
-                self.children_len_ptr(),
-                1 + src_count * size_of::<Node<T>>(),
+            ptr::copy_nonoverlapping(
+                src_node.first_bytes_ptr(),
+                self.first_bytes_ptr(),
+                src_count,
             );
+            ptr::copy_nonoverlapping(src_node.children_ptr(), self.children_ptr(), src_count);
         }
+        self.update_index();
 
         // Deallocate src node children
-        src_node.dealloc_children();
+        src_node.dealloc_children(alloc);
+        src_node.drop_in(alloc);
     }
 
-    fn dealloc_children(&mut self) {
+    #[cfg(test)]
+    fn dealloc_children<A: NodeAllocator>(&mut self, alloc: &A) {
         let mut flags = self.flags();
         if !flags.contains(Flags::HAS_CHILDREN) {
             panic!("Node has no children");
         }
 
-        flags.set(Flags::HAS_CHILDREN, false);
-        self.realloc(self.curr_layout(), flags, self.key_len(), 0);
+        flags.remove(Flags::HAS_CHILDREN | Flags::HAS_SUMMARY);
+        self.realloc(alloc, flags, self.key_len(), 0, 0);
     }
 
-    #[inline(always)]
-    fn split_child(
-        children: &mut [Node<T>],
+    /// Splits the child at `idx` into a node with the first `prefix_len`
+    /// bytes of its key and the child with the rest of it, and inserts the
+    /// value of `key`, which shares exactly `prefix_len` bytes with the
+    /// child's key, into the new node or a new leaf below it. Every
+    /// allocation is made before the tree is changed, so that it is left
+    /// unchanged and the value is dropped if one fails.
+    fn try_split_child<A: NodeAllocator>(
+        alloc: &A,
+        policy: GrowthPolicy,
+        children: &mut [Node<T, U>],
         idx: usize,
-        prefix_len: usize,
         key: &[u8],
+        prefix_len: usize,
         value: T,
-    ) -> Option<T> {
-        // Replace node with new (uninitialized) node
-        let mut old = std::mem::replace(&mut children[idx], Node::new(&key[..prefix_len]));
-        // Update old node's key
-        old.strip_key_prefix(prefix_len);
-        // Initialize new node's children with the old node
-        children[idx].push_child(old);
-        // Insert into the new node
-        children[idx].insert(&key[prefix_len..], value)
+    ) -> Result<(), RadixError> {
+        let suffix = &key[prefix_len..];
+        let mut flags = Flags::HAS_CHILDREN;
+        let mut unlinked = Unlinked::<T, U, A>::new(alloc);
+        let value = if suffix.is_empty() {
+            flags |= Flags::VALUE_ALLOCATED | Flags::VALUE_INITIALIZED;
+            unlinked.storage = Some(Self::try_alloc_value(alloc)?);
+            Some(value)
+        } else {
+            unlinked.node = Some(Node::try_new_with_value(alloc, suffix, value)?);
+            None
+        };
+        let children_count = 1 + unlinked.node.is_some() as usize;
+        let children_capacity = policy.capacity(children_count);
+
+        let data = Self::try_alloc(alloc, flags, prefix_len, children_capacity)?;
+        let layout = Self::create_layout(flags, prefix_len, children_capacity);
+        unlinked.block = Some((data, layout));
+        children[idx].try_strip_key_prefix(alloc, prefix_len)?;
+
+        // Nothing can fail from here on
+        let (storage, leaf) = (unlinked.storage.take(), unlinked.node.take());
+        unlinked.block = None;
+        let mut node = Node::from_ptr(data);
+        unsafe {
+            node.write_header(flags, prefix_len, children_count, children_capacity);
+            ptr::copy_nonoverlapping(key.as_ptr(), node.key_ptr(), prefix_len);
+            if let (Some(storage), Some(value)) = (storage, value) {
+                node.write_value_in(storage, value);
+            }
+            let mut split = [Some(ptr::read(&children[idx])), leaf];
+            if let [Some(old), Some(leaf)] = &split {
+                if leaf.key()[0] < old.key()[0] {
+                    split.swap(0, 1);
+                }
+            }
+            for (i, child) in split.into_iter().flatten().enumerate() {
+                ptr::write(node.first_bytes_ptr().add(i), child.key()[0]);
+                ptr::write(node.children_ptr().add(i), child);
+            }
+            node.update_index();
+            ptr::write(&mut children[idx], node);
+        }
+        Ok(())
+    }
+
+    /// Drops the node's value and children and deallocates the node.
+    pub(crate) fn drop_in<A: NodeAllocator>(self, alloc: &A) {
+        // The node itself is not pushed so that dropping a leaf does not
+        // allocate.
+        let mut dropper = Dropper::<T, U, A> {
+            stack: vec![],
+            alloc,
+        };
+        dropper.drop_node(self);
+        dropper.drain();
+    }
+
+    /// Drops the nodes and all nodes below them.
+    pub(crate) fn drop_all_in<A: NodeAllocator>(nodes: Vec<Node<T, U>>, alloc: &A) {
+        let mut dropper = Dropper::<T, U, A> {
+            stack: nodes,
+            alloc,
+        };
+        dropper.drain();
     }
 }
 
-impl<T> Drop for Node<T> {
-    fn drop(&mut self) {
-        if self.flags().contains(Flags::VALUE_INITIALIZED) {
-            // Drop value
-            let _value = unsafe { ptr::read(self.value_ptr()) };
+impl<T, U: Copy> Node<T, U> {
+    /// Returns the summary of the values in this subtree, `None` if it holds
+    /// none. Nodes with children return the summary cached by
+    /// [`Self::update_summary()`] if there is one.
+    pub(crate) fn summary<S: Summary<T, Output = U>>(&self, summary: &S) -> Option<U> {
+        if self.flags().contains(Flags::HAS_SUMMARY) {
+            return Some(unsafe { *self.summary_ptr() });
         }
-        if self.flags().contains(Flags::HAS_CHILDREN) {
+        let mut result = self.value().map(|value| summary.summarize(value));
+        for child in self.children() {
+            if let Some(child) = child.summary(summary) {
+                result = Some(match result {
+                    Some(result) => summary.combine(result, child),
+                    None => child,
+                });
+            }
+        }
+        result
+    }
+
+    /// Recomputes the cached summary of this node from its value and the
+    /// summaries of its children.
+    fn update_summary<S: Summary<T, Output = U>>(&mut self, summary: &S) {
+        if !self.flags().contains(Flags::HAS_CHILDREN) {
+            return;
+        }
+        self.set_flags(Flags::HAS_SUMMARY, false);
+        if let Some(result) = self.summary(summary) {
+            unsafe { ptr::write(self.summary_ptr(), result) };
+            self.set_flags(Flags::HAS_SUMMARY, true);
+        }
+    }
+
+    /// Recomputes the cached summaries of the nodes on the path to `key`,
+    /// bottom-up. Inserting or removing a key only changes nodes on its path,
+    /// including the node the path ends in after a split or a merge.
+    pub(crate) fn update_summaries<S: Summary<T, Output = U>>(&mut self, summary: &S, key: &[u8]) {
+        let mut path: Vec<*mut Node<T, U>> = vec![self];
+        let mut node: *mut Node<T, U> = self;
+        let mut key = key;
+        while !key.is_empty() {
+            let (prefix_len, child_idx) = longest_common_prefix(unsafe { &*node }, key);
+            if prefix_len == 0 {
+                break;
+            }
+            let child = unsafe { &mut (*node).children_mut()[child_idx] };
+            let matched = prefix_len == child.key().len();
+            node = child;
+            path.push(node);
+            if !matched {
+                break;
+            }
+            key = &key[prefix_len..];
+        }
+        for node in path.into_iter().rev() {
+            unsafe { (*node).update_summary(summary) };
+        }
+    }
+
+    /// Recomputes the cached summaries of all nodes in this subtree.
+    pub(crate) fn update_all_summaries<S: Summary<T, Output = U>>(&mut self, summary: &S) {
+        // Every node is listed after its parent, so updating them in reverse
+        // updates children first.
+        let mut nodes: Vec<*mut Node<T, U>> = vec![self];
+        let mut i = 0;
+        while i < nodes.len() {
+            for child in unsafe { (*nodes[i]).children_mut() } {
+                if !child.children().is_empty() {
+                    nodes.push(child);
+                }
+            }
+            i += 1;
+        }
+        for node in nodes.into_iter().rev() {
+            unsafe { (*node).update_summary(summary) };
+        }
+    }
+}
+
+/// Drops nodes with a stack instead of recursion. If a value's destructor
+/// panics the remaining nodes are dropped while unwinding.
+struct Dropper<'a, T, U, A: NodeAllocator> {
+    stack: Vec<Node<T, U>>,
+    alloc: &'a A,
+}
+
+impl<T, U, A: NodeAllocator> Dropper<'_, T, U, A> {
+    fn drop_node(&mut self, node: Node<T, U>) {
+        let mut value = None;
+        if node.flags().contains(Flags::VALUE_INITIALIZED) {
+            value = Some(unsafe { node.read_value(self.alloc) });
+        }
+        if node.flags().contains(Flags::HAS_CHILDREN) {
             // Drop children
             unsafe {
-                let node_ptr = self.children_ptr();
-                for i in 0..self.children().len() {
-                    let _node = ptr::read(node_ptr.add(i));
+                let node_ptr = node.children_ptr();
+                for i in 0..node.children().len() {
+                    self.stack.push(ptr::read(node_ptr.add(i)));
                 }
             }
         }
         // Deallocate
+        if !node.is_inline() {
+            unsafe {
+                self.alloc.deallocate(node.heap_ptr(), node.curr_layout());
+            }
+        }
+        // The value is dropped last, when the node is no longer reachable.
+        drop(value);
+    }
+
+    fn drain(&mut self) {
+        while let Some(node) = self.stack.pop() {
+            self.drop_node(node);
+        }
+    }
+}
+
+impl<T, U, A: NodeAllocator> Drop for Dropper<'_, T, U, A> {
+    fn drop(&mut self) {
+        // Only runs with nodes left if a value's destructor panicked.
+        self.drain();
+    }
+}
+
+/// Allocations and nodes that are not linked into the tree yet. They are
+/// freed when this is dropped, so that nothing leaks if building a node fails
+/// or the allocator panics.
+struct Unlinked<'a, T, U, A: NodeAllocator> {
+    alloc: &'a A,
+    storage: Option<ptr::NonNull<T>>,
+    block: Option<(ptr::NonNull<u8>, Layout)>,
+    node: Option<Node<T, U>>,
+    children: Vec<Node<T, U>>,
+}
+
+impl<'a, T, U, A: NodeAllocator> Unlinked<'a, T, U, A> {
+    fn new(alloc: &'a A) -> Self {
+        Unlinked {
+            alloc,
+            storage: None,
+            block: None,
+            node: None,
+            children: vec![],
+        }
+    }
+}
+
+impl<T, U, A: NodeAllocator> Drop for Unlinked<'_, T, U, A> {
+    fn drop(&mut self) {
+        unsafe {
+            if let Some(storage) = self.storage.take() {
+                Node::<T, U>::dealloc_value(self.alloc, storage);
+            }
+            if let Some((block, layout)) = self.block.take() {
+                self.alloc.deallocate(block, layout);
+            }
+        }
+        let mut nodes = mem::take(&mut self.children);
+        nodes.extend(self.node.take());
+        Node::drop_all_in(nodes, self.alloc);
+    }
+}
+
+/// Moves the sections of a node that failed to shrink back to their old
+/// offsets when dropped.
+struct Rollback<'a, T, U> {
+    node: &'a mut Node<T, U>,
+    sections: [(usize, usize, usize); 6],
+}
+
+impl<T, U> Drop for Rollback<'_, T, U> {
+    fn drop(&mut self) {
+        let sections = self.sections.map(|(src, dst, size)| (dst, src, size));
+        unsafe { move_sections(self.node.data_ptr(), &sections) };
+        self.node.update_index();
+    }
+}
+
+/// Iterator over the children taken out of a node. The block that holds
+/// them is deallocated when the iterator is dropped, together with the
+/// children that were not taken.
+pub(crate) struct TakeChildren<'a, T, A: NodeAllocator, U = ()> {
+    alloc: &'a A,
+    block: Option<(ptr::NonNull<u8>, Layout)>,
+    children: *const Node<T, U>,
+    start_idx: usize,
+    end_idx: usize,
+}
+
+impl<'a, T, U, A: NodeAllocator> TakeChildren<'a, T, A, U> {
+    /// Moves the node without its children to a new block and keeps the old
+    /// one. The node is left unchanged if the allocation fails.
+    #[cfg(test)]
+    fn new(node: &mut Node<T, U>, alloc: &'a A) -> Self {
+        if !node.flags().contains(Flags::HAS_CHILDREN) {
+            return Self::empty(alloc);
+        }
+        let flags = node.flags() - Flags::HAS_CHILDREN - Flags::HAS_SUMMARY;
+        let data = Node::<T, U>::alloc(alloc, flags, node.key_len(), 0);
         unsafe {
-            dealloc(self.data.as_ptr(), self.curr_layout());
+            // Copy key length, key and value
+            ptr::copy_nonoverlapping(
+                node.data_ptr().add(1),
+                data.as_ptr().add(1),
+                Node::<T, U>::children_len_offset(flags, node.key_len()) - 1,
+            );
         }
+        let old = mem::replace(node, Node::from_ptr(data));
+        Self::from_node(old, alloc)
     }
-}
 
-pub(crate) struct TakeChildren<'a, T> {
-    node: &'a mut Node<T>,
-    start_idx: usize,
-    end_idx: usize,
-}
+    fn empty(alloc: &'a A) -> Self {
+        TakeChildren {
+            alloc,
+            block: None,
+            children: ptr::null(),
+            start_idx: 0,
+            end_idx: 0,
+        }
+    }
 
-impl<'a, T> TakeChildren<'a, T> {
-    fn new(node: &'a mut Node<T>) -> Self {
-        let children_count = node.children().len();
+    /// Takes over the node's block. The node's value must have been taken.
+    fn from_node(node: Node<T, U>, alloc: &'a A) -> Self {
+        debug_assert!(node.value().is_none());
+        if node.is_inline() {
+            return Self::empty(alloc);
+        }
+        let children = node.children();
         TakeChildren {
-            node,
+            alloc,
+            block: Some((node.heap_ptr(), node.curr_layout())),
+            children: children.as_ptr(),
             start_idx: 0,
-            end_idx: children_count,
+            end_idx: children.len(),
         }
     }
 
-    fn read_child(&self, idx: usize) -> Node<T> {
-        unsafe { ptr::read(self.node.children_ptr().add(idx)) }
+    fn read_child(&self, idx: usize) -> Node<T, U> {
+        unsafe { ptr::read(self.children.add(idx)) }
     }
 }
 
-impl<'a, T> Drop for TakeChildren<'a, T> {
+impl<'a, T, U, A: NodeAllocator> Drop for TakeChildren<'a, T, A, U> {
     fn drop(&mut self) {
-        if self.node.flags().contains(Flags::HAS_CHILDREN) {
-            for idx in self.start_idx..self.end_idx {
-                let _child = self.read_child(idx);
-            }
-            self.node.dealloc_children();
+        let children = self.by_ref().collect();
+        if let Some((block, layout)) = self.block.take() {
+            unsafe { self.alloc.deallocate(block, layout) };
         }
+        Node::drop_all_in(children, self.alloc);
     }
 }
 
-impl<'a, T> Iterator for TakeChildren<'a, T> {
-    type Item = Node<T>;
+impl<'a, T, U, A: NodeAllocator> Iterator for TakeChildren<'a, T, A, U> {
+    type Item = Node<T, U>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if !self.node.flags().contains(Flags::HAS_CHILDREN) {
+        if self.start_idx == self.end_idx {
             return None;
         }
 
         let child = self.read_child(self.start_idx);
         self.start_idx += 1;
-
-        if self.start_idx == self.end_idx {
-            self.node.dealloc_children();
-        }
-
         Some(child)
     }
 }
 
-impl<'a, T> DoubleEndedIterator for TakeChildren<'a, T> {
+impl<'a, T, U, A: NodeAllocator> DoubleEndedIterator for TakeChildren<'a, T, A, U> {
     fn next_back(&mut self) -> Option<Self::Item> {
-        if !self.node.flags().contains(Flags::HAS_CHILDREN) {
+        if self.start_idx == self.end_idx {
             return None;
         }
 
         self.end_idx -= 1;
-        let child = self.read_child(self.end_idx);
+        Some(self.read_child(self.end_idx))
+    }
+}
+
+#[inline(always)]
+const fn align_up(offset: usize, align: usize) -> usize {
+    (offset + align - 1) & !(align - 1)
+}
+
+/// Returns the number of bytes of the LEB128 encoding of `n`.
+#[inline(always)]
+const fn varint_len(n: usize) -> usize {
+    ((usize::BITS - (n | 1).leading_zeros()).div_ceil(7)) as usize
+}
 
-        if self.end_idx == self.start_idx {
-            self.node.dealloc_children();
+#[inline]
+unsafe fn read_varint(mut ptr: *const u8) -> usize {
+    let mut n = 0;
+    let mut shift = 0;
+    loop {
+        let byte = *ptr;
+        n |= ((byte & 0x7f) as usize) << shift;
+        if byte < 0x80 {
+            return n;
         }
+        shift += 7;
+        ptr = ptr.add(1);
+    }
+}
 
-        Some(child)
+#[inline]
+unsafe fn write_varint(mut ptr: *mut u8, mut n: usize) {
+    while n >= 0x80 {
+        ptr::write(ptr, (n as u8) | 0x80);
+        n >>= 7;
+        ptr = ptr.add(1);
+    }
+    ptr::write(ptr, n as u8);
+}
+
+/// Moves `(src, dst, size)` sections of a block sorted by offset. Sections
+/// moving left are moved front to back and sections moving right back to
+/// front so that no section is overwritten before it is moved.
+#[inline]
+unsafe fn move_sections(data: *mut u8, sections: &[(usize, usize, usize)]) {
+    for &(src, dst, size) in sections.iter().filter(|(src, dst, _)| dst < src) {
+        ptr::copy(data.add(src), data.add(dst), size);
+    }
+    for &(src, dst, size) in sections.iter().rev().filter(|(src, dst, _)| dst > src) {
+        ptr::copy(data.add(src), data.add(dst), size);
     }
 }
 
@@ -720,7 +1871,11 @@ impl<'a, T> DoubleEndedIterator for TakeChildren<'a, T> {
 mod tests {
     use super::*;
 
+    use crate::allocator::Global;
+
+    use std::cell::Cell;
     use std::collections::BTreeMap;
+    use std::panic::{catch_unwind, AssertUnwindSafe};
     use std::rc::Rc;
 
     struct NodeIter<'a, V> {
@@ -749,20 +1904,126 @@ mod tests {
         }
     }
 
+    /// Allocator whose allocations always fail.
+    struct FailingAllocator;
+
+    unsafe impl NodeAllocator for FailingAllocator {
+        fn allocate(&self, _: Layout) -> Option<ptr::NonNull<u8>> {
+            None
+        }
+
+        unsafe fn deallocate(&self, ptr: ptr::NonNull<u8>, layout: Layout) {
+            Global.deallocate(ptr, layout)
+        }
+    }
+
+    /// Value whose destructor panics if it was created with `explode`.
+    struct Bomb {
+        drops: Rc<Cell<usize>>,
+        explode: bool,
+    }
+
+    impl Drop for Bomb {
+        fn drop(&mut self) {
+            self.drops.set(self.drops.get() + 1);
+            if self.explode {
+                panic!("bomb");
+            }
+        }
+    }
+
+    fn take_key<V>(node: Option<Node<V>>) -> Vec<u8> {
+        let node = node.unwrap();
+        let key = node.key().to_vec();
+        node.drop_in(&Global);
+        key
+    }
+
     #[test]
     fn test_new() {
-        let node: Node<()> = Node::new(&[1, 2, 3]);
+        let node: Node<()> = Node::new(&Global, &[1, 2, 3]);
         assert_eq!(node.key(), &[1, 2, 3]);
         assert!(!node.flags().contains(Flags::VALUE_ALLOCATED));
         assert!(!node.flags().contains(Flags::VALUE_INITIALIZED));
         assert!(!node.flags().contains(Flags::HAS_CHILDREN));
+
+        node.drop_in(&Global);
+    }
+
+    #[test]
+    fn test_inline_leaf() {
+        let mut node: Node<u16> = Node::new_with_value(&Global, &[1, 2, 3], 7);
+        assert!(node.is_inline());
+        assert_eq!(node.allocated_size(), 0);
+        assert_eq!(node.key(), &[1, 2, 3]);
+        assert_eq!(node.value(), Some(&7));
+        assert_eq!(node.children().len(), 0);
+        assert_eq!(node.child_index(0), Err(0));
+
+        assert_eq!(node.replace_value(&Global, 8), Some(7));
+        *node.value_mut().unwrap() += 1;
+        assert_eq!(node.get(&[]), Some(&9));
+
+        node.strip_key_prefix(&Global, 1);
+        assert!(node.is_inline());
+        assert_eq!(node.key(), &[2, 3]);
+        assert_eq!(node.value(), Some(&9));
+
+        // Gaining a child allocates the node
+        node.insert(&Global, GrowthPolicy::Exact, &[4], 10);
+        assert!(!node.is_inline());
+        assert!(node.children()[0].is_inline());
+        assert_eq!(node.key(), &[2, 3]);
+        assert_eq!(node.get(&[]), Some(&9));
+        assert_eq!(node.get(&[4]), Some(&10));
+
+        // Losing it inlines the node again
+        assert_eq!(node.remove(&Global, GrowthPolicy::Exact, &[4]), Some(10));
+        assert!(node.is_inline());
+        assert_eq!(node.key(), &[2, 3]);
+        assert_eq!(node.take_value(&Global), Some(9));
+        assert_eq!(node.value(), None);
+        node.drop_in(&Global);
+
+        // Keys that do not fit with the value are allocated.
+        let node: Node<u16> = Node::new_with_value(&Global, &[1, 2, 3, 4, 5], 7);
+        assert!(!node.is_inline());
+        node.drop_in(&Global);
+        let node: Node<u64> = Node::new_with_value(&Global, &[1], 7);
+        assert!(!node.is_inline());
+        node.drop_in(&Global);
+    }
+
+    #[test]
+    fn test_inline_leaf_drop() {
+        thread_local!(static DROPPED: std::cell::Cell<usize> = const { std::cell::Cell::new(0) });
+
+        struct Tracked(u8);
+        impl Drop for Tracked {
+            fn drop(&mut self) {
+                DROPPED.with(|d| d.set(d.get() + 1));
+            }
+        }
+
+        let mut root: Node<Tracked> = Node::new(&Global, &[]);
+        for i in 0..100u8 {
+            root.insert(&Global, GrowthPolicy::Exact, &[i / 10, i % 10], Tracked(i));
+        }
+        assert!(root.children()[0].children()[0].is_inline());
+        for i in (0..100u8).step_by(2) {
+            let removed = root.remove(&Global, GrowthPolicy::Exact, &[i / 10, i % 10]);
+            assert_eq!(removed.map(|v| v.0), Some(i));
+        }
+        assert_eq!(DROPPED.with(|d| d.get()), 50);
+        root.drop_in(&Global);
+        assert_eq!(DROPPED.with(|d| d.get()), 100);
     }
 
     #[test]
     fn test_modify_value() {
-        let mut node: Node<u64> = Node::new(&[1, 2, 3]);
-        node.push_child(Node::new(&[1]));
-        node.push_child(Node::new(&[2]));
+        let mut node: Node<u64> = Node::new(&Global, &[1, 2, 3]);
+        node.push_child(&Global, GrowthPolicy::Exact, Node::new(&Global, &[1]));
+        node.push_child(&Global, GrowthPolicy::Exact, Node::new(&Global, &[2]));
 
         assert_eq!(node.key(), &[1, 2, 3]);
         assert_eq!(node.value(), None);
@@ -773,7 +2034,7 @@ mod tests {
         assert_eq!(node.children()[0].key(), &[1]);
         assert_eq!(node.children()[1].key(), &[2]);
 
-        assert_eq!(node.replace_value(123), None);
+        assert_eq!(node.replace_value(&Global, 123), None);
         assert_eq!(node.value(), Some(&123));
         assert!(node.flags().contains(Flags::VALUE_ALLOCATED));
         assert!(node.flags().contains(Flags::VALUE_INITIALIZED));
@@ -782,7 +2043,7 @@ mod tests {
         assert_eq!(node.children()[0].key(), &[1]);
         assert_eq!(node.children()[1].key(), &[2]);
 
-        assert_eq!(node.replace_value(456), Some(123));
+        assert_eq!(node.replace_value(&Global, 456), Some(123));
         assert_eq!(node.value(), Some(&456));
         assert!(node.flags().contains(Flags::VALUE_ALLOCATED));
         assert!(node.flags().contains(Flags::VALUE_INITIALIZED));
@@ -791,7 +2052,7 @@ mod tests {
         assert_eq!(node.children()[0].key(), &[1]);
         assert_eq!(node.children()[1].key(), &[2]);
 
-        assert_eq!(node.take_value(), Some(456));
+        assert_eq!(node.take_value(&Global), Some(456));
         assert_eq!(node.value(), None);
         assert!(node.flags().contains(Flags::VALUE_ALLOCATED));
         assert!(!node.flags().contains(Flags::VALUE_INITIALIZED));
@@ -799,13 +2060,15 @@ mod tests {
         assert_eq!(node.children().len(), 2);
         assert_eq!(node.children()[0].key(), &[1]);
         assert_eq!(node.children()[1].key(), &[2]);
+
+        node.drop_in(&Global);
     }
 
     #[test]
     fn test_modify_key() {
-        let mut node: Node<u64> = Node::new_with_value(&[1, 2, 3, 4, 5], 123);
-        node.push_child(Node::new(&[1]));
-        node.push_child(Node::new(&[2]));
+        let mut node: Node<u64> = Node::new_with_value(&Global, &[1, 2, 3, 4, 5], 123);
+        node.push_child(&Global, GrowthPolicy::Exact, Node::new(&Global, &[1]));
+        node.push_child(&Global, GrowthPolicy::Exact, Node::new(&Global, &[2]));
 
         assert_eq!(node.key(), &[1, 2, 3, 4, 5]);
         assert_eq!(node.value(), Some(&123));
@@ -816,7 +2079,7 @@ mod tests {
         assert_eq!(node.children()[0].key(), &[1]);
         assert_eq!(node.children()[1].key(), &[2]);
 
-        node.strip_key_prefix(2);
+        node.strip_key_prefix(&Global, 2);
         assert_eq!(node.key(), &[3, 4, 5]);
         assert_eq!(node.value(), Some(&123));
         assert!(node.flags().contains(Flags::VALUE_ALLOCATED));
@@ -826,7 +2089,7 @@ mod tests {
         assert_eq!(node.children()[0].key(), &[1]);
         assert_eq!(node.children()[1].key(), &[2]);
 
-        node.extend_key(&[6, 7, 8]);
+        node.extend_key(&Global, &[6, 7, 8]);
         assert_eq!(node.key(), &[3, 4, 5, 6, 7, 8]);
         assert_eq!(node.value(), Some(&123));
         assert!(node.flags().contains(Flags::VALUE_ALLOCATED));
@@ -835,23 +2098,93 @@ mod tests {
         assert_eq!(node.children().len(), 2);
         assert_eq!(node.children()[0].key(), &[1]);
         assert_eq!(node.children()[1].key(), &[2]);
+
+        // Keys whose length takes more than one byte
+        let long_key: Vec<u8> = (0..20000).map(|i| i as u8).collect();
+        node.extend_key(&Global, &long_key);
+        let mut expected = vec![3, 4, 5, 6, 7, 8];
+        expected.extend(&long_key);
+        assert_eq!(node.key(), &expected[..]);
+        assert_eq!(node.value(), Some(&123));
+        assert_eq!(node.children()[1].key(), &[2]);
+
+        node.strip_key_prefix(&Global, 19900);
+        assert_eq!(node.key(), &expected[19900..]);
+        assert_eq!(node.value(), Some(&123));
+        assert_eq!(node.children()[1].key(), &[2]);
+
+        node.drop_in(&Global);
+    }
+
+    #[test]
+    fn test_modify_key_alloc_error() {
+        let mut node: Node<u64> = Node::new_with_value(&Global, &[1; 100], 123);
+        for i in 0..45 {
+            node.push_child(&Global, GrowthPolicy::Doubling, Node::new(&Global, &[i, 1]));
+        }
+        assert_eq!(node.children_capacity(), 64);
+        let check = |node: &Node<u64>| {
+            assert_eq!(node.key(), &[1; 100]);
+            assert_eq!(node.value(), Some(&123));
+            assert_eq!(node.children().len(), 45);
+            for (i, child) in node.children().iter().enumerate() {
+                assert_eq!(child.key(), &[i as u8, 1]);
+                assert_eq!(node.child_index(i as u8), Ok(i));
+            }
+        };
+
+        assert_eq!(
+            node.try_strip_key_prefix(&FailingAllocator, 90),
+            Err(RadixError::AllocError)
+        );
+        check(&node);
+        assert_eq!(
+            node.try_realloc(&FailingAllocator, node.flags(), 110, 45, 64),
+            Err(RadixError::AllocError)
+        );
+        check(&node);
+        // The sections are moved back if the block can't be shrunk
+        assert_eq!(
+            node.try_realloc(&FailingAllocator, node.flags(), 100, 45, 45),
+            Err(RadixError::AllocError)
+        );
+        check(&node);
+        node.drop_in(&Global);
+
+        // Inline leaves stay inline if they can't gain a child
+        let mut leaf: Node<u64> = Node::new_with_value(&Global, &[1], 7);
+        let inline = leaf.is_inline();
+        assert_eq!(
+            leaf.try_insert_child(
+                &FailingAllocator,
+                GrowthPolicy::Exact,
+                0,
+                Node::new_with_value(&Global, &[2], 8)
+            ),
+            Err(RadixError::AllocError)
+        );
+        assert_eq!(leaf.is_inline(), inline);
+        assert_eq!(leaf.key(), &[1]);
+        assert_eq!(leaf.value(), Some(&7));
+        assert!(leaf.children().is_empty());
+        leaf.drop_in(&Global);
     }
 
     #[test]
     fn test_insert() {
-        let mut node = Node::new("".as_bytes());
+        let mut node = Node::new(&Global, "".as_bytes());
         assert_eq!(NodeIter::new(&node).count(), 1);
 
-        node.insert("abc;0".as_bytes(), 1);
+        node.insert(&Global, GrowthPolicy::Exact, "abc;0".as_bytes(), 1);
         assert_eq!(NodeIter::new(&node).count(), 2);
 
-        node.insert("abb;0".as_bytes(), 2);
+        node.insert(&Global, GrowthPolicy::Exact, "abb;0".as_bytes(), 2);
         assert_eq!(NodeIter::new(&node).count(), 4);
 
-        node.insert("ab".as_bytes(), 3);
+        node.insert(&Global, GrowthPolicy::Exact, "ab".as_bytes(), 3);
         assert_eq!(NodeIter::new(&node).count(), 4);
 
-        node.insert("abd".as_bytes(), 4);
+        node.insert(&Global, GrowthPolicy::Exact, "abd".as_bytes(), 4);
         assert_eq!(NodeIter::new(&node).count(), 5);
 
         let mut nodes_map = BTreeMap::new();
@@ -874,11 +2207,13 @@ mod tests {
 
         assert_eq!(nodes_map.get("b;0".as_bytes()).unwrap().children().len(), 0);
         assert_eq!(nodes_map.get("b;0".as_bytes()).unwrap().value(), Some(&2));
+
+        node.drop_in(&Global);
     }
 
     #[test]
-    fn test_insert_with_key_len_greater_than_255() {
-        let mut node = Node::new("".as_bytes());
+    fn test_insert_with_long_keys() {
+        let mut node = Node::new(&Global, "".as_bytes());
         assert_eq!(NodeIter::new(&node).count(), 1);
 
         let key_a = vec![0; 260];
@@ -890,38 +2225,47 @@ mod tests {
         let key_e = vec![1; 512];
         let key_f = vec![2; 510];
 
-        node.insert(&key_a, 1);
-        assert_eq!(NodeIter::new(&node).count(), 3);
+        node.insert(&Global, GrowthPolicy::Exact, &key_a, 1);
+        assert_eq!(NodeIter::new(&node).count(), 2);
         assert_eq!(node.get(&key_a), Some(&1));
 
-        node.insert(&key_b, 2);
-        assert_eq!(NodeIter::new(&node).count(), 4);
+        node.insert(&Global, GrowthPolicy::Exact, &key_b, 2);
+        assert_eq!(NodeIter::new(&node).count(), 3);
         assert_eq!(node.get(&key_b), Some(&2));
 
-        node.insert(&key_c, 3);
-        assert_eq!(NodeIter::new(&node).count(), 5);
+        node.insert(&Global, GrowthPolicy::Exact, &key_c, 3);
+        assert_eq!(NodeIter::new(&node).count(), 4);
         assert_eq!(node.get(&key_c), Some(&3));
 
-        node.insert(&key_d, 4);
-        assert_eq!(NodeIter::new(&node).count(), 7);
+        node.insert(&Global, GrowthPolicy::Exact, &key_d, 4);
+        assert_eq!(NodeIter::new(&node).count(), 5);
         assert_eq!(node.get(&key_d), Some(&4));
 
-        node.insert(&key_e, 5);
-        assert_eq!(NodeIter::new(&node).count(), 10);
+        node.insert(&Global, GrowthPolicy::Exact, &key_e, 5);
+        assert_eq!(NodeIter::new(&node).count(), 6);
         assert_eq!(node.get(&key_e), Some(&5));
 
-        node.insert(&key_f, 6);
-        assert_eq!(NodeIter::new(&node).count(), 12);
+        node.insert(&Global, GrowthPolicy::Exact, &key_f, 6);
+        assert_eq!(NodeIter::new(&node).count(), 7);
+        // Removing the branch at key_a merges its remaining children.
+        assert_eq!(node.remove(&Global, GrowthPolicy::Exact, &key_b), Some(2));
+        assert_eq!(node.remove(&Global, GrowthPolicy::Exact, &key_c), Some(3));
+        assert_eq!(node.remove(&Global, GrowthPolicy::Exact, &key_a), Some(1));
+        assert_eq!(NodeIter::new(&node).count(), 4);
+        assert_eq!(node.children()[0].key(), &key_d[..]);
+        assert_eq!(node.get(&key_d), Some(&4));
         assert_eq!(node.get(&key_f), Some(&6));
+
+        node.drop_in(&Global);
     }
 
     #[test]
     fn test_remove() {
-        let mut node = Node::new(&[]);
-        node.insert("hello".as_bytes(), 0);
-        node.insert("hell".as_bytes(), 1);
-        node.insert("hel".as_bytes(), 2);
-        node.insert("h".as_bytes(), 3);
+        let mut node = Node::new(&Global, &[]);
+        node.insert(&Global, GrowthPolicy::Exact, "hello".as_bytes(), 0);
+        node.insert(&Global, GrowthPolicy::Exact, "hell".as_bytes(), 1);
+        node.insert(&Global, GrowthPolicy::Exact, "hel".as_bytes(), 2);
+        node.insert(&Global, GrowthPolicy::Exact, "h".as_bytes(), 3);
 
         assert_eq!(node.get("h".as_bytes()), Some(&3));
         assert_eq!(node.get("hel".as_bytes()), Some(&2));
@@ -929,98 +2273,297 @@ mod tests {
         assert_eq!(node.get("hello".as_bytes()), Some(&0));
         assert_eq!(NodeIter::new(&node).count(), 5);
 
-        assert_eq!(node.remove("he".as_bytes()), None);
+        assert_eq!(
+            node.remove(&Global, GrowthPolicy::Exact, "he".as_bytes()),
+            None
+        );
         assert_eq!(node.get("h".as_bytes()), Some(&3));
         assert_eq!(node.get("hel".as_bytes()), Some(&2));
         assert_eq!(node.get("hell".as_bytes()), Some(&1));
         assert_eq!(node.get("hello".as_bytes()), Some(&0));
         assert_eq!(NodeIter::new(&node).count(), 5);
 
-        assert_eq!(node.remove("hell".as_bytes()), Some(1));
+        assert_eq!(
+            node.remove(&Global, GrowthPolicy::Exact, "hell".as_bytes()),
+            Some(1)
+        );
         assert_eq!(node.get("h".as_bytes()), Some(&3));
         assert_eq!(node.get("hel".as_bytes()), Some(&2));
         assert_eq!(node.get("hell".as_bytes()), None);
         assert_eq!(node.get("hello".as_bytes()), Some(&0));
         assert_eq!(NodeIter::new(&node).count(), 4);
 
-        assert_eq!(node.remove("hel".as_bytes()), Some(2));
+        assert_eq!(
+            node.remove(&Global, GrowthPolicy::Exact, "hel".as_bytes()),
+            Some(2)
+        );
         assert_eq!(node.get("h".as_bytes()), Some(&3));
         assert_eq!(node.get("hel".as_bytes()), None);
         assert_eq!(node.get("hell".as_bytes()), None);
         assert_eq!(node.get("hello".as_bytes()), Some(&0));
         assert_eq!(NodeIter::new(&node).count(), 3);
 
-        assert_eq!(node.remove("hello".as_bytes()), Some(0));
+        assert_eq!(
+            node.remove(&Global, GrowthPolicy::Exact, "hello".as_bytes()),
+            Some(0)
+        );
         assert_eq!(node.get("h".as_bytes()), Some(&3));
         assert_eq!(node.get("hel".as_bytes()), None);
         assert_eq!(node.get("hell".as_bytes()), None);
         assert_eq!(node.get("hello".as_bytes()), None);
         assert_eq!(NodeIter::new(&node).count(), 2);
 
-        assert_eq!(node.remove("h".as_bytes()), Some(3));
+        assert_eq!(
+            node.remove(&Global, GrowthPolicy::Exact, "h".as_bytes()),
+            Some(3)
+        );
         assert_eq!(node.get("h".as_bytes()), None);
         assert_eq!(node.get("hel".as_bytes()), None);
         assert_eq!(node.get("hell".as_bytes()), None);
         assert_eq!(node.get("hello".as_bytes()), None);
         assert_eq!(NodeIter::new(&node).count(), 1);
 
-        node.insert(&vec![0; 512], 3);
-        assert_eq!(NodeIter::new(&node).count(), 4);
+        node.insert(&Global, GrowthPolicy::Exact, &vec![0; 512], 3);
+        assert_eq!(NodeIter::new(&node).count(), 2);
         assert_eq!(node.get(&vec![0; 512]), Some(&3));
 
-        assert_eq!(node.remove(&vec![0; 512]), Some(3));
+        assert_eq!(
+            node.remove(&Global, GrowthPolicy::Exact, &vec![0; 512]),
+            Some(3)
+        );
+        assert_eq!(NodeIter::new(&node).count(), 1);
+
+        node.drop_in(&Global);
+    }
+
+    #[test]
+    fn test_remove_alloc_error() {
+        let mut node = Node::new(&Global, &[]);
+        node.insert(&Global, GrowthPolicy::Exact, b"abc", 1);
+        node.insert(&Global, GrowthPolicy::Exact, b"abcd", 2);
+        node.insert(&Global, GrowthPolicy::Exact, b"abce", 3);
+        assert_eq!(NodeIter::new(&node).count(), 4);
+
+        // The children array can't shrink and keeps its capacity
+        assert_eq!(
+            node.remove(&FailingAllocator, GrowthPolicy::Exact, b"abce"),
+            Some(3)
+        );
+        assert_eq!(node.children()[0].children().len(), 1);
+        assert_eq!(node.children()[0].children_capacity(), 2);
+
+        // The node can't be merged with its child
+        assert_eq!(
+            node.remove(&FailingAllocator, GrowthPolicy::Exact, b"abc"),
+            Some(1)
+        );
+        assert_eq!(NodeIter::new(&node).count(), 3);
+        assert_eq!(node.get(b"abc"), None);
+        assert_eq!(node.get(b"abcd"), Some(&2));
+
+        // The children array can't be deallocated and the empty child stays
+        assert_eq!(
+            node.remove(&FailingAllocator, GrowthPolicy::Exact, b"abcd"),
+            Some(2)
+        );
+        assert_eq!(NodeIter::new(&node).count(), 3);
+        assert_eq!(node.get(b"abcd"), None);
+
+        // The tree is compacted by later removals
+        node.insert(&Global, GrowthPolicy::Exact, b"abcd", 4);
+        assert_eq!(node.get(b"abcd"), Some(&4));
+        assert_eq!(node.remove(&Global, GrowthPolicy::Exact, b"abcd"), Some(4));
         assert_eq!(NodeIter::new(&node).count(), 1);
+
+        node.drop_in(&Global);
+    }
+
+    #[test]
+    fn test_drop_in_panicking_value() {
+        let drops = Rc::new(Cell::new(0));
+        let mut root: Node<_> = Node::new(&Global, &[]);
+        for i in 0..100u8 {
+            let value = Bomb {
+                drops: drops.clone(),
+                explode: i == 42,
+            };
+            root.insert(&Global, GrowthPolicy::Exact, &[i / 10, i % 10], value);
+        }
+        // The remaining values are dropped after the panic
+        let result = catch_unwind(AssertUnwindSafe(|| root.drop_in(&Global)));
+        assert!(result.is_err());
+        assert_eq!(drops.get(), 100);
+    }
+
+    #[test]
+    fn test_take_children_panicking_value() {
+        let drops = Rc::new(Cell::new(0));
+        let mut root: Node<_> = Node::new(&Global, &[]);
+        for i in 0..4u8 {
+            let value = Bomb {
+                drops: drops.clone(),
+                explode: i == 2,
+            };
+            root.push_child(
+                &Global,
+                GrowthPolicy::Exact,
+                Node::new_with_value(&Global, &[i], value),
+            );
+        }
+
+        let mut children_it = root.take_children(&Global);
+        take_key(children_it.next());
+        let result = catch_unwind(AssertUnwindSafe(|| drop(children_it)));
+        assert!(result.is_err());
+        assert_eq!(drops.get(), 4);
+        assert!(!root.flags().contains(Flags::HAS_CHILDREN));
+
+        root.drop_in(&Global);
     }
 
     // Children tests
     #[test]
     fn test_children_add() {
-        let mut node: Node<()> = Node::new(&[]);
+        let mut node: Node<()> = Node::new(&Global, &[]);
         assert!(!node.flags().contains(Flags::HAS_CHILDREN));
 
         // Push
-        node.push_child(Node::new(&[]));
+        node.push_child(&Global, GrowthPolicy::Exact, Node::new(&Global, &[0, 1]));
         assert!(node.flags().contains(Flags::HAS_CHILDREN));
         assert_eq!(node.children().len(), 1);
-        assert_eq!(node.children()[0].key(), &[]);
+        assert_eq!(node.children()[0].key(), &[0, 1]);
 
         // Push
-        node.push_child(Node::new(&[1, 2]));
+        node.push_child(&Global, GrowthPolicy::Exact, Node::new(&Global, &[1, 2]));
         assert_eq!(node.children().len(), 2);
-        assert_eq!(node.children()[0].key(), &[]);
+        assert_eq!(node.children()[0].key(), &[0, 1]);
         assert_eq!(node.children()[1].key(), &[1, 2]);
 
         // Insert last
-        node.insert_child(2, Node::new(&[3, 4]));
+        node.insert_child(&Global, GrowthPolicy::Exact, 2, Node::new(&Global, &[3, 4]));
         assert_eq!(node.children().len(), 3);
-        assert_eq!(node.children()[0].key(), &[]);
+        assert_eq!(node.children()[0].key(), &[0, 1]);
         assert_eq!(node.children()[1].key(), &[1, 2]);
         assert_eq!(node.children()[2].key(), &[3, 4]);
 
         // Insert mid
-        node.insert_child(2, Node::new(&[2, 3]));
+        node.insert_child(&Global, GrowthPolicy::Exact, 2, Node::new(&Global, &[2, 3]));
         assert_eq!(node.children().len(), 4);
-        assert_eq!(node.children()[0].key(), &[]);
+        assert_eq!(node.children()[0].key(), &[0, 1]);
         assert_eq!(node.children()[1].key(), &[1, 2]);
         assert_eq!(node.children()[2].key(), &[2, 3]);
         assert_eq!(node.children()[3].key(), &[3, 4]);
 
         // Insert first
-        node.insert_child(0, Node::new(&[0, 0]));
+        node.insert_child(&Global, GrowthPolicy::Exact, 0, Node::new(&Global, &[0, 0]));
         assert_eq!(node.children().len(), 5);
         assert_eq!(node.children()[0].key(), &[0, 0]);
-        assert_eq!(node.children()[1].key(), &[]);
+        assert_eq!(node.children()[1].key(), &[0, 1]);
         assert_eq!(node.children()[2].key(), &[1, 2]);
         assert_eq!(node.children()[3].key(), &[2, 3]);
         assert_eq!(node.children()[4].key(), &[3, 4]);
+        assert_eq!(node.first_bytes(), &[0, 0, 1, 2, 3]);
+
+        node.drop_in(&Global);
+    }
+
+    #[test]
+    fn test_children_dense() {
+        let mut node: Node<()> = Node::new(&Global, &[]);
+
+        // Insert children in a scrambled order so that all of them are
+        // shifted while the node crosses the dense threshold.
+        for i in 0..=255_u8 {
+            let byte = i.wrapping_mul(167);
+            let idx = node.child_index(byte).unwrap_err();
+            node.insert_child(
+                &Global,
+                GrowthPolicy::Exact,
+                idx,
+                Node::new(&Global, &[byte, i]),
+            );
+        }
+        assert_eq!(node.children().len(), 256);
+        for byte in 0..=255_u8 {
+            let idx = node.child_index(byte).unwrap();
+            assert_eq!(idx, byte as usize);
+            assert_eq!(node.children()[idx].key()[0], byte);
+        }
+
+        // Remove every other child until the node is sparse again.
+        for byte in (0..=255_u8).filter(|b| b % 2 == 1) {
+            let idx = node.child_index(byte).unwrap();
+            assert_eq!(
+                take_key(Some(node.remove_child(&Global, GrowthPolicy::Exact, idx)))[0],
+                byte
+            );
+            assert_eq!(node.child_index(byte), Err(idx));
+        }
+        assert_eq!(node.children().len(), 128);
+        for byte in (0..=255_u8).filter(|b| b % 2 == 0) {
+            let idx = node.child_index(byte).unwrap();
+            assert_eq!(node.children()[idx].key()[0], byte);
+        }
+
+        // Stripping the key moves the index.
+        let mut parent = Node::new(&Global, &[1, 2, 3]);
+        parent.move_children(&Global, node);
+        parent.strip_key_prefix(&Global, 2);
+        for byte in (0..=255_u8).filter(|b| b % 2 == 0) {
+            assert_eq!(parent.child_index(byte), Ok(byte as usize / 2));
+        }
+
+        parent.drop_in(&Global);
+    }
+
+    #[test]
+    fn test_children_capacity() {
+        let policy = GrowthPolicy::SizeClasses;
+        let mut node: Node<u8> = Node::new(&Global, &[]);
+        node.replace_value(&Global, 0);
+
+        for i in 0..60_u8 {
+            node.push_child(&Global, policy, Node::new(&Global, &[i * 4]));
+            assert_eq!(node.children_capacity(), policy.capacity(i as usize + 1));
+        }
+
+        // The node keeps its dense capacity while most children are removed.
+        for _ in 0..30 {
+            node.remove_child(&Global, policy, 0).drop_in(&Global);
+        }
+        assert_eq!(node.children().len(), 30);
+        assert_eq!(node.children_capacity(), 256);
+        for i in 0..60_u8 {
+            let expected = if i < 30 { Err(0) } else { Ok(i as usize - 30) };
+            assert_eq!(node.child_index(i * 4), expected);
+        }
+
+        // Shrinks once the children would fit into the smaller class twice.
+        for _ in 0..6 {
+            node.remove_child(&Global, policy, 0).drop_in(&Global);
+        }
+        assert_eq!(node.children_capacity(), 48);
+
+        node.shrink_to_fit(&Global);
+        assert_eq!(node.children_capacity(), 24);
+        assert_eq!(node.value(), Some(&0));
+        for i in 36..60_u8 {
+            assert_eq!(node.child_index(i * 4), Ok(i as usize - 36));
+        }
+
+        node.drop_in(&Global);
     }
 
     #[test]
     fn test_children_push_full() {
-        let mut node: Node<()> = Node::new(&[]);
+        let mut node: Node<()> = Node::new(&Global, &[]);
 
         for i in 0..=255_u32 {
-            node.push_child(Node::new(i.to_be_bytes().as_slice()));
+            node.push_child(
+                &Global,
+                GrowthPolicy::Exact,
+                Node::new(&Global, i.to_be_bytes().as_slice()),
+            );
         }
 
         assert_eq!(node.children().len(), 256);
@@ -1030,26 +2573,36 @@ mod tests {
                 i.to_be_bytes().as_slice()
             );
         }
+
+        node.drop_in(&Global);
     }
 
     #[test]
     #[should_panic]
+    // The nodes are leaked by the panic, which Miri reports.
+    #[cfg_attr(miri, ignore)]
     fn test_children_push_more_than_256_items() {
-        let mut node: Node<()> = Node::new(&[]);
+        let mut node: Node<()> = Node::new(&Global, &[]);
 
         for i in 0..=256_u32 {
-            node.push_child(Node::new(i.to_be_bytes().as_slice()));
+            node.push_child(
+                &Global,
+                GrowthPolicy::Exact,
+                Node::new(&Global, i.to_be_bytes().as_slice()),
+            );
         }
+
+        node.drop_in(&Global);
     }
 
     #[test]
     fn test_children_remove() {
-        let mut node: Node<()> = Node::new(&[]);
-        node.push_child(Node::new(&[0, 1]));
-        node.push_child(Node::new(&[1, 2]));
-        node.push_child(Node::new(&[2, 3]));
-        node.push_child(Node::new(&[3, 4]));
-        node.push_child(Node::new(&[4, 5]));
+        let mut node: Node<()> = Node::new(&Global, &[]);
+        node.push_child(&Global, GrowthPolicy::Exact, Node::new(&Global, &[0, 1]));
+        node.push_child(&Global, GrowthPolicy::Exact, Node::new(&Global, &[1, 2]));
+        node.push_child(&Global, GrowthPolicy::Exact, Node::new(&Global, &[2, 3]));
+        node.push_child(&Global, GrowthPolicy::Exact, Node::new(&Global, &[3, 4]));
+        node.push_child(&Global, GrowthPolicy::Exact, Node::new(&Global, &[4, 5]));
 
         assert_eq!(node.children().len(), 5);
         assert!(node.flags().contains(Flags::HAS_CHILDREN));
@@ -1060,7 +2613,10 @@ mod tests {
         assert_eq!(node.children()[4].key(), &[4, 5]);
 
         // Remove first
-        assert_eq!(node.remove_child(0).key(), &[0, 1]);
+        assert_eq!(
+            take_key(Some(node.remove_child(&Global, GrowthPolicy::Exact, 0))),
+            &[0, 1]
+        );
         assert!(node.flags().contains(Flags::HAS_CHILDREN));
         assert_eq!(node.children().len(), 4);
         assert_eq!(node.children()[0].key(), &[1, 2]);
@@ -1069,7 +2625,10 @@ mod tests {
         assert_eq!(node.children()[3].key(), &[4, 5]);
 
         // Remove last
-        assert_eq!(node.remove_child(3).key(), &[4, 5]);
+        assert_eq!(
+            take_key(Some(node.remove_child(&Global, GrowthPolicy::Exact, 3))),
+            &[4, 5]
+        );
         assert!(node.flags().contains(Flags::HAS_CHILDREN));
         assert_eq!(node.children().len(), 3);
         assert_eq!(node.children()[0].key(), &[1, 2]);
@@ -1077,54 +2636,71 @@ mod tests {
         assert_eq!(node.children()[2].key(), &[3, 4]);
 
         // Remove mid
-        assert_eq!(node.remove_child(1).key(), &[2, 3]);
+        assert_eq!(
+            take_key(Some(node.remove_child(&Global, GrowthPolicy::Exact, 1))),
+            &[2, 3]
+        );
         assert!(node.flags().contains(Flags::HAS_CHILDREN));
         assert_eq!(node.children().len(), 2);
         assert_eq!(node.children()[0].key(), &[1, 2]);
         assert_eq!(node.children()[1].key(), &[3, 4]);
 
         // Remove mid
-        assert_eq!(node.remove_child(1).key(), &[3, 4]);
+        assert_eq!(
+            take_key(Some(node.remove_child(&Global, GrowthPolicy::Exact, 1))),
+            &[3, 4]
+        );
         assert!(node.flags().contains(Flags::HAS_CHILDREN));
         assert_eq!(node.children().len(), 1);
         assert_eq!(node.children()[0].key(), &[1, 2]);
+
+        node.drop_in(&Global);
     }
 
     #[test]
     #[should_panic]
+    // The nodes are leaked by the panic, which Miri reports.
+    #[cfg_attr(miri, ignore)]
     fn test_children_remove_invalid_offset() {
-        let mut node: Node<()> = Node::new(&[]);
-        node.push_child(Node::new(&[0, 1]));
-        node.push_child(Node::new(&[1, 2]));
+        let mut node: Node<()> = Node::new(&Global, &[]);
+        node.push_child(&Global, GrowthPolicy::Exact, Node::new(&Global, &[0, 1]));
+        node.push_child(&Global, GrowthPolicy::Exact, Node::new(&Global, &[1, 2]));
 
         assert_eq!(node.children().len(), 2);
         assert_eq!(node.children()[0].key(), &[0, 1]);
         assert_eq!(node.children()[1].key(), &[1, 2]);
 
-        node.remove_child(2);
+        node.remove_child(&Global, GrowthPolicy::Exact, 2);
+
+        node.drop_in(&Global);
     }
 
     #[test]
     fn test_children_remove_last_item() {
-        let mut node: Node<()> = Node::new(&[]);
-        node.push_child(Node::new(&[0, 1]));
+        let mut node: Node<()> = Node::new(&Global, &[]);
+        node.push_child(&Global, GrowthPolicy::Exact, Node::new(&Global, &[0, 1]));
         assert_eq!(node.children().len(), 1);
         assert_eq!(node.children()[0].key(), &[0, 1]);
 
-        assert_eq!(node.remove_child(0).key(), &[0, 1]);
+        assert_eq!(
+            take_key(Some(node.remove_child(&Global, GrowthPolicy::Exact, 0))),
+            &[0, 1]
+        );
         assert_eq!(node.children().len(), 0);
         assert!(!node.flags().contains(Flags::HAS_CHILDREN));
+
+        node.drop_in(&Global);
     }
 
     #[test]
     fn test_find_prefix() {
-        let mut root: Node<u32> = Node::new(&[]);
-        root.insert(b"foo;bar;1", 1);
-        root.insert(b"foo;bar;2", 2);
-        root.insert(b"foo;baz;1", 3);
-        root.insert(b"foo;baz;2", 4);
-        root.insert(b"bar;1", 5);
-        root.insert(b"bar;2", 6);
+        let mut root: Node<u32> = Node::new(&Global, &[]);
+        root.insert(&Global, GrowthPolicy::Exact, b"foo;bar;1", 1);
+        root.insert(&Global, GrowthPolicy::Exact, b"foo;bar;2", 2);
+        root.insert(&Global, GrowthPolicy::Exact, b"foo;baz;1", 3);
+        root.insert(&Global, GrowthPolicy::Exact, b"foo;baz;2", 4);
+        root.insert(&Global, GrowthPolicy::Exact, b"bar;1", 5);
+        root.insert(&Global, GrowthPolicy::Exact, b"bar;2", 6);
 
         let prefix = b"foo;ba";
         for i in 1..=prefix.len() {
@@ -1163,17 +2739,19 @@ mod tests {
         assert!(root.find_prefix(b"foo;bag").is_none());
         assert!(root.find_prefix(b"baz").is_none());
         assert!(root.find_prefix(b"bz").is_none());
+
+        root.drop_in(&Global);
     }
 
     #[test]
     fn test_find_prefix_mut() {
-        let mut root: Node<u32> = Node::new(&[]);
-        root.insert(b"foo;bar;1", 1);
-        root.insert(b"foo;bar;2", 2);
-        root.insert(b"foo;baz;1", 3);
-        root.insert(b"foo;baz;2", 4);
-        root.insert(b"bar;1", 5);
-        root.insert(b"bar;2", 6);
+        let mut root: Node<u32> = Node::new(&Global, &[]);
+        root.insert(&Global, GrowthPolicy::Exact, b"foo;bar;1", 1);
+        root.insert(&Global, GrowthPolicy::Exact, b"foo;bar;2", 2);
+        root.insert(&Global, GrowthPolicy::Exact, b"foo;baz;1", 3);
+        root.insert(&Global, GrowthPolicy::Exact, b"foo;baz;2", 4);
+        root.insert(&Global, GrowthPolicy::Exact, b"bar;1", 5);
+        root.insert(&Global, GrowthPolicy::Exact, b"bar;2", 6);
 
         let prefix = b"foo;ba";
         for i in 1..=prefix.len() {
@@ -1212,131 +2790,362 @@ mod tests {
         assert!(root.find_prefix(b"foo;bag").is_none());
         assert!(root.find_prefix(b"baz").is_none());
         assert!(root.find_prefix(b"bz").is_none());
+
+        root.drop_in(&Global);
     }
 
     #[test]
     fn test_take_children() {
         let rc = Rc::new(());
-        let mut root: Node<Rc<()>> = Node::new(&[]);
-        root.push_child(Node::new_with_value(b"a", rc.clone()));
-        root.push_child(Node::new_with_value(b"b", rc.clone()));
-        root.push_child(Node::new_with_value(b"c", rc.clone()));
-        root.push_child(Node::new_with_value(b"d", rc.clone()));
+        let mut root: Node<Rc<()>> = Node::new(&Global, &[]);
+        root.push_child(
+            &Global,
+            GrowthPolicy::Exact,
+            Node::new_with_value(&Global, b"a", rc.clone()),
+        );
+        root.push_child(
+            &Global,
+            GrowthPolicy::Exact,
+            Node::new_with_value(&Global, b"b", rc.clone()),
+        );
+        root.push_child(
+            &Global,
+            GrowthPolicy::Exact,
+            Node::new_with_value(&Global, b"c", rc.clone()),
+        );
+        root.push_child(
+            &Global,
+            GrowthPolicy::Exact,
+            Node::new_with_value(&Global, b"d", rc.clone()),
+        );
 
         assert_eq!(root.children().len(), 4);
         assert!(root.flags().contains(Flags::HAS_CHILDREN));
 
-        let mut children_it = root.take_children();
-        assert_eq!(children_it.next().unwrap().key(), b"a");
-        assert_eq!(children_it.next().unwrap().key(), b"b");
-        assert_eq!(children_it.next().unwrap().key(), b"c");
-        assert_eq!(children_it.next().unwrap().key(), b"d");
+        let mut children_it = root.take_children(&Global);
+        assert_eq!(take_key(children_it.next()), b"a");
+        assert_eq!(take_key(children_it.next()), b"b");
+        assert_eq!(take_key(children_it.next()), b"c");
+        assert_eq!(take_key(children_it.next()), b"d");
         drop(children_it);
 
         assert!(!root.flags().contains(Flags::HAS_CHILDREN));
         assert_eq!(Rc::strong_count(&rc), 1);
+
+        root.drop_in(&Global);
     }
 
     #[test]
     fn test_take_children_rev() {
         let rc = Rc::new(());
-        let mut root: Node<Rc<()>> = Node::new(&[]);
-        root.push_child(Node::new_with_value(b"a", rc.clone()));
-        root.push_child(Node::new_with_value(b"b", rc.clone()));
-        root.push_child(Node::new_with_value(b"c", rc.clone()));
-        root.push_child(Node::new_with_value(b"d", rc.clone()));
+        let mut root: Node<Rc<()>> = Node::new(&Global, &[]);
+        root.push_child(
+            &Global,
+            GrowthPolicy::Exact,
+            Node::new_with_value(&Global, b"a", rc.clone()),
+        );
+        root.push_child(
+            &Global,
+            GrowthPolicy::Exact,
+            Node::new_with_value(&Global, b"b", rc.clone()),
+        );
+        root.push_child(
+            &Global,
+            GrowthPolicy::Exact,
+            Node::new_with_value(&Global, b"c", rc.clone()),
+        );
+        root.push_child(
+            &Global,
+            GrowthPolicy::Exact,
+            Node::new_with_value(&Global, b"d", rc.clone()),
+        );
 
         assert_eq!(root.children().len(), 4);
         assert!(root.flags().contains(Flags::HAS_CHILDREN));
 
-        let mut children_it = root.take_children().rev();
-        assert_eq!(children_it.next().unwrap().key(), b"d");
-        assert_eq!(children_it.next().unwrap().key(), b"c");
-        assert_eq!(children_it.next().unwrap().key(), b"b");
-        assert_eq!(children_it.next().unwrap().key(), b"a");
+        let mut children_it = root.take_children(&Global).rev();
+        assert_eq!(take_key(children_it.next()), b"d");
+        assert_eq!(take_key(children_it.next()), b"c");
+        assert_eq!(take_key(children_it.next()), b"b");
+        assert_eq!(take_key(children_it.next()), b"a");
         drop(children_it);
 
         assert!(!root.flags().contains(Flags::HAS_CHILDREN));
         assert_eq!(Rc::strong_count(&rc), 1);
+
+        root.drop_in(&Global);
     }
 
     #[test]
     fn test_take_children_front_back() {
         let rc = Rc::new(());
-        let mut root: Node<Rc<()>> = Node::new(&[]);
-        root.push_child(Node::new_with_value(b"a", rc.clone()));
-        root.push_child(Node::new_with_value(b"b", rc.clone()));
-        root.push_child(Node::new_with_value(b"c", rc.clone()));
-        root.push_child(Node::new_with_value(b"d", rc.clone()));
+        let mut root: Node<Rc<()>> = Node::new(&Global, &[]);
+        root.push_child(
+            &Global,
+            GrowthPolicy::Exact,
+            Node::new_with_value(&Global, b"a", rc.clone()),
+        );
+        root.push_child(
+            &Global,
+            GrowthPolicy::Exact,
+            Node::new_with_value(&Global, b"b", rc.clone()),
+        );
+        root.push_child(
+            &Global,
+            GrowthPolicy::Exact,
+            Node::new_with_value(&Global, b"c", rc.clone()),
+        );
+        root.push_child(
+            &Global,
+            GrowthPolicy::Exact,
+            Node::new_with_value(&Global, b"d", rc.clone()),
+        );
 
         assert_eq!(root.children().len(), 4);
         assert!(root.flags().contains(Flags::HAS_CHILDREN));
 
-        let mut children_it = root.take_children();
-        assert_eq!(children_it.next().unwrap().key(), b"a");
-        assert_eq!(children_it.next_back().unwrap().key(), b"d");
-        assert_eq!(children_it.next().unwrap().key(), b"b");
-        assert_eq!(children_it.next_back().unwrap().key(), b"c");
+        let mut children_it = root.take_children(&Global);
+        assert_eq!(take_key(children_it.next()), b"a");
+        assert_eq!(take_key(children_it.next_back()), b"d");
+        assert_eq!(take_key(children_it.next()), b"b");
+        assert_eq!(take_key(children_it.next_back()), b"c");
         assert!(children_it.next().is_none());
         assert!(children_it.next_back().is_none());
         drop(children_it);
 
         assert!(!root.flags().contains(Flags::HAS_CHILDREN));
         assert_eq!(Rc::strong_count(&rc), 1);
+
+        root.drop_in(&Global);
     }
 
     #[test]
     fn test_take_children_unfinished() {
         let rc = Rc::new(());
-        let mut root: Node<Rc<()>> = Node::new(&[]);
-        root.push_child(Node::new_with_value(b"a", rc.clone()));
-        root.push_child(Node::new_with_value(b"b", rc.clone()));
-        root.push_child(Node::new_with_value(b"c", rc.clone()));
-        root.push_child(Node::new_with_value(b"d", rc.clone()));
+        let mut root: Node<Rc<()>> = Node::new(&Global, &[]);
+        root.push_child(
+            &Global,
+            GrowthPolicy::Exact,
+            Node::new_with_value(&Global, b"a", rc.clone()),
+        );
+        root.push_child(
+            &Global,
+            GrowthPolicy::Exact,
+            Node::new_with_value(&Global, b"b", rc.clone()),
+        );
+        root.push_child(
+            &Global,
+            GrowthPolicy::Exact,
+            Node::new_with_value(&Global, b"c", rc.clone()),
+        );
+        root.push_child(
+            &Global,
+            GrowthPolicy::Exact,
+            Node::new_with_value(&Global, b"d", rc.clone()),
+        );
 
         assert_eq!(Rc::strong_count(&rc), 5);
 
         assert_eq!(root.children().len(), 4);
         assert!(root.flags().contains(Flags::HAS_CHILDREN));
 
-        let mut children_it = root.take_children();
-        assert_eq!(children_it.next().unwrap().key(), b"a");
-        assert_eq!(children_it.next().unwrap().key(), b"b");
+        let mut children_it = root.take_children(&Global);
+        assert_eq!(take_key(children_it.next()), b"a");
+        assert_eq!(take_key(children_it.next()), b"b");
         drop(children_it);
 
         assert!(!root.flags().contains(Flags::HAS_CHILDREN));
         assert_eq!(Rc::strong_count(&rc), 1);
+
+        root.drop_in(&Global);
     }
 
     #[test]
     fn test_take_children_rev_unfinished() {
         let rc = Rc::new(());
-        let mut root: Node<Rc<()>> = Node::new(&[]);
-        root.push_child(Node::new_with_value(b"a", rc.clone()));
-        root.push_child(Node::new_with_value(b"b", rc.clone()));
-        root.push_child(Node::new_with_value(b"c", rc.clone()));
-        root.push_child(Node::new_with_value(b"d", rc.clone()));
+        let mut root: Node<Rc<()>> = Node::new(&Global, &[]);
+        root.push_child(
+            &Global,
+            GrowthPolicy::Exact,
+            Node::new_with_value(&Global, b"a", rc.clone()),
+        );
+        root.push_child(
+            &Global,
+            GrowthPolicy::Exact,
+            Node::new_with_value(&Global, b"b", rc.clone()),
+        );
+        root.push_child(
+            &Global,
+            GrowthPolicy::Exact,
+            Node::new_with_value(&Global, b"c", rc.clone()),
+        );
+        root.push_child(
+            &Global,
+            GrowthPolicy::Exact,
+            Node::new_with_value(&Global, b"d", rc.clone()),
+        );
 
         assert_eq!(root.children().len(), 4);
         assert!(root.flags().contains(Flags::HAS_CHILDREN));
 
-        let mut children_it = root.take_children().rev();
-        assert_eq!(children_it.next().unwrap().key(), b"d");
-        assert_eq!(children_it.next().unwrap().key(), b"c");
+        let mut children_it = root.take_children(&Global).rev();
+        assert_eq!(take_key(children_it.next()), b"d");
+        assert_eq!(take_key(children_it.next()), b"c");
         drop(children_it);
 
         assert!(!root.flags().contains(Flags::HAS_CHILDREN));
         assert_eq!(Rc::strong_count(&rc), 1);
+
+        root.drop_in(&Global);
     }
 
     #[test]
     fn test_take_children_with_no_children() {
-        let mut root: Node<u32> = Node::new(&[]);
+        let mut root: Node<u32> = Node::new(&Global, &[]);
 
         assert_eq!(root.children().len(), 0);
         assert!(!root.flags().contains(Flags::HAS_CHILDREN));
 
-        let mut children_it = root.take_children().rev();
+        let mut children_it = root.take_children(&Global).rev();
         assert!(children_it.next().is_none());
+        drop(children_it);
+
+        root.drop_in(&Global);
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    #[repr(align(64))]
+    struct OverAligned(u8);
+
+    fn check_offsets<V>() {
+        let all_flags = [
+            Flags::empty(),
+            Flags::VALUE_ALLOCATED,
+            Flags::HAS_CHILDREN,
+            Flags::VALUE_ALLOCATED | Flags::HAS_CHILDREN,
+        ];
+        for flags in all_flags {
+            for key_len in (0..300).chain([16383, 16384]) {
+                // flags, 1-3 bytes of key length and the key
+                let key_len_bytes = 1 + (key_len >= 128) as usize + (key_len >= 16384) as usize;
+                let mut layout = Layout::array::<u8>(1 + key_len_bytes + key_len).unwrap();
+                if flags.contains(Flags::VALUE_ALLOCATED) {
+                    let (l, offset) = layout.extend(Node::<V>::value_layout()).unwrap();
+                    assert_eq!(offset, Node::<V>::value_offset(key_len));
+                    layout = l;
+                }
+                if flags.contains(Flags::HAS_CHILDREN) {
+                    let (l, offset) = layout.extend(Layout::new::<[u8; 2]>()).unwrap();
+                    assert_eq!(offset, Node::<V>::children_len_offset(flags, key_len));
+                    for capacity in [1, 3, DENSE_THRESHOLD, DENSE_THRESHOLD + 1, 256] {
+                        let index_len = if capacity > DENSE_THRESHOLD { 256 } else { 0 };
+                        let (l, _) = l
+                            .extend(Layout::array::<u8>(capacity + index_len).unwrap())
+                            .unwrap();
+                        let (_, offset) = l
+                            .extend(Layout::array::<Node<V>>(capacity).unwrap())
+                            .unwrap();
+                        assert_eq!(offset, Node::<V>::children_offset(flags, key_len, capacity));
+                    }
+                }
+            }
+        }
+    }
+
+    fn check_alignment<V: Clone + PartialEq + std::fmt::Debug>(make_value: impl Fn(usize) -> V) {
+        // Binary strings of various lengths produce many splits and merges,
+        // i.e. key length changes of nodes with a value and children. Miri
+        // runs a smaller tree.
+        let count = if cfg!(miri) { 40 } else { 200 };
+        let keys: Vec<String> = (1..count).map(|i| format!("{:b}", i * 7)).collect();
+
+        let mut root = Node::new(&Global, &[]);
+        let mut expected = BTreeMap::new();
+        for (i, key) in keys.iter().enumerate() {
+            root.insert(&Global, GrowthPolicy::Exact, key.as_bytes(), make_value(i));
+            expected.insert(key.clone(), make_value(i));
+        }
+        for (i, key) in keys.iter().enumerate().step_by(3) {
+            assert_eq!(
+                root.remove(&Global, GrowthPolicy::Exact, key.as_bytes()),
+                Some(make_value(i))
+            );
+            expected.remove(key);
+        }
+
+        for node in NodeIter::new(&root) {
+            if let Some(value) = node.value() {
+                assert_eq!(value as *const V as usize % align_of::<V>(), 0);
+            }
+            assert_eq!(node.children().as_ptr() as usize % align_of::<Node<V>>(), 0);
+        }
+        for (key, value) in expected.iter() {
+            assert_eq!(root.get(key.as_bytes()), Some(value));
+        }
+
+        root.drop_in(&Global);
+    }
+
+    #[test]
+    fn test_varint() {
+        let mut buf = [0u8; 10];
+        for n in [0, 1, 127, 128, 255, 16383, 16384, 1 << 40, usize::MAX] {
+            unsafe {
+                write_varint(buf.as_mut_ptr(), n);
+                assert_eq!(read_varint(buf.as_ptr()), n);
+            }
+            let len = buf.iter().position(|&b| b < 0x80).unwrap() + 1;
+            assert_eq!(varint_len(n), len);
+        }
+    }
+
+    #[test]
+    fn test_layout_offsets() {
+        check_offsets::<()>();
+        check_offsets::<u8>();
+        check_offsets::<u16>();
+        check_offsets::<u64>();
+        check_offsets::<u128>();
+        check_offsets::<[u8; 3]>();
+        check_offsets::<OverAligned>();
+        check_offsets::<[u64; 25]>();
+    }
+
+    #[test]
+    fn test_value_alignment() {
+        check_alignment(|_| ());
+        check_alignment(|i| i as u8);
+        check_alignment(|i| [i as u16; 3]);
+        check_alignment(|i| i as u64);
+        check_alignment(|i| i as u128);
+        check_alignment(|i| OverAligned(i as u8));
+        check_alignment(|i| i.to_string());
+        check_alignment(|i| [i as u64; 25]);
+        check_alignment(|i| (i.to_string(), [i as u128; 8]));
+    }
+
+    #[test]
+    fn test_boxed_value() {
+        const { assert!(!Node::<[u8; MAX_EMBEDDED_VALUE_SIZE]>::BOXED_VALUE) };
+        const { assert!(Node::<[u8; MAX_EMBEDDED_VALUE_SIZE + 1]>::BOXED_VALUE) };
+
+        let mut node: Node<[u64; 25]> = Node::new(&Global, &[1, 2, 3]);
+        node.replace_value(&Global, [7; 25]);
+        let value_ptr = node.value().unwrap() as *const _;
+        assert!(node.allocated_size() < 64);
+
+        // Reallocating the node does not move the value.
+        for i in 0..100u8 {
+            node.insert(&Global, GrowthPolicy::Exact, &[i], [i as u64; 25]);
+        }
+        node.strip_key_prefix(&Global, 1);
+        assert_eq!(node.value().unwrap() as *const _, value_ptr);
+        assert_eq!(node.get(&[]), Some(&[7; 25]));
+        assert_eq!(node.get(&[42]), Some(&[42; 25]));
+
+        assert_eq!(node.take_value(&Global), Some([7; 25]));
+        assert_eq!(node.replace_value(&Global, [8; 25]), None);
+        assert_eq!(node.replace_value(&Global, [9; 25]), Some([8; 25]));
+        node.drop_in(&Global);
     }
 }
@@ -0,0 +1,739 @@
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::automaton::Automaton;
+use crate::error::RegexError;
+
+/// Maximum number of DFA states a pattern may compile to.
+const MAX_STATES: usize = 10_000;
+/// Maximum bound of a counted repetition.
+const MAX_REPEAT: u32 = 1_000;
+
+/// The dead state. No key that reaches it can match.
+const DEAD: u32 = 0;
+
+/// A regular expression compiled to a DFA over key bytes.
+///
+/// Supports literals, `.`, classes (`[a-z]`, `[^/]`, `\d`, `\w`, `\s` and
+/// their negations), groups (`(..)`, `(?:..)`), alternation, the repetitions
+/// `*`, `+`, `?`, `{n}`, `{n,}` and `{n,m}`, and the anchors `^` and `$` at
+/// the start and end of the top-level alternatives. An unanchored pattern
+/// matches keys that contain a match, as usual.
+///
+/// The pattern is matched byte by byte: `.` and classes match a single byte,
+/// so they only match whole characters for ASCII. Searching with a regex
+/// skips every subtree whose prefix leads to the dead state.
+#[derive(Debug, Clone)]
+pub struct Regex {
+    /// Equivalence class of each byte, bytes of a class are never told apart
+    /// by the pattern.
+    classes: Box<[u8; 256]>,
+    stride: usize,
+    /// Next state of each state and byte class.
+    transitions: Vec<u32>,
+    matches: Vec<bool>,
+    start: u32,
+}
+
+impl Regex {
+    pub fn new(pattern: &str) -> Result<Self, RegexError> {
+        let ast = Parser::new(pattern.as_bytes()).parse()?;
+        let mut nfa = Nfa::default();
+        let end = nfa.push(NfaState::Match);
+        let start = nfa.compile(&ast, end);
+        Ok(nfa.into_dfa(start)?.prune())
+    }
+
+    #[inline]
+    fn next(&self, state: u32, byte: u8) -> u32 {
+        self.transitions[state as usize * self.stride + self.classes[byte as usize] as usize]
+    }
+
+    /// Returns `true` if the whole of `key` matches.
+    pub fn matches<K: AsRef<[u8]>>(&self, key: K) -> bool {
+        let state = key.as_ref().iter().try_fold(self.start, |state, &byte| {
+            Some(self.next(state, byte)).filter(|&next| next != DEAD)
+        });
+        state.is_some_and(|state| self.matches[state as usize])
+    }
+
+    /// Redirects transitions into states that can't reach a match to the
+    /// dead state, so searches stop as early as possible.
+    fn prune(mut self) -> Self {
+        let states = self.matches.len();
+        let mut preds: Vec<Vec<u32>> = vec![vec![]; states];
+        for state in 0..states {
+            for &next in &self.transitions[state * self.stride..(state + 1) * self.stride] {
+                preds[next as usize].push(state as u32);
+            }
+        }
+
+        let mut live = self.matches.clone();
+        let mut stack: Vec<u32> = (0..states as u32).filter(|&s| live[s as usize]).collect();
+        while let Some(state) = stack.pop() {
+            for &pred in &preds[state as usize] {
+                if !live[pred as usize] {
+                    live[pred as usize] = true;
+                    stack.push(pred);
+                }
+            }
+        }
+
+        for next in self.transitions.iter_mut() {
+            if !live[*next as usize] {
+                *next = DEAD;
+            }
+        }
+        if !live[self.start as usize] {
+            self.start = DEAD;
+        }
+        self
+    }
+}
+
+impl Automaton for Regex {
+    type State = u32;
+
+    #[inline]
+    fn start(&self) -> u32 {
+        self.start
+    }
+
+    #[inline]
+    fn accept(&self, state: &u32, byte: u8) -> u32 {
+        self.next(*state, byte)
+    }
+
+    #[inline]
+    fn is_match(&self, state: &u32) -> bool {
+        self.matches[*state as usize]
+    }
+
+    #[inline]
+    fn can_match(&self, state: &u32) -> bool {
+        *state != DEAD
+    }
+}
+
+/// Parsed pattern. Byte sets are sorted, non-overlapping inclusive ranges.
+#[derive(Debug, Clone)]
+enum Ast {
+    Bytes(Vec<(u8, u8)>),
+    Concat(Vec<Ast>),
+    Alternate(Vec<Ast>),
+    Repeat {
+        ast: Box<Ast>,
+        min: u32,
+        max: Option<u32>,
+    },
+}
+
+impl Ast {
+    fn any() -> Ast {
+        Ast::Bytes(vec![(0, 255)])
+    }
+
+    fn any_repeated() -> Ast {
+        Ast::Repeat {
+            ast: Box::new(Ast::any()),
+            min: 0,
+            max: None,
+        }
+    }
+}
+
+struct Parser<'a> {
+    pattern: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(pattern: &'a [u8]) -> Self {
+        Parser { pattern, pos: 0 }
+    }
+
+    #[inline]
+    fn peek(&self) -> Option<u8> {
+        self.pattern.get(self.pos).copied()
+    }
+
+    #[inline]
+    fn bump(&mut self) -> Result<u8, RegexError> {
+        let byte = self.peek().ok_or(RegexError::UnexpectedEnd)?;
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    fn parse(mut self) -> Result<Ast, RegexError> {
+        let mut branches = vec![];
+        loop {
+            let anchored_start = self.peek() == Some(b'^');
+            if anchored_start {
+                self.pos += 1;
+            }
+            let mut branch = vec![];
+            if !anchored_start {
+                branch.push(Ast::any_repeated());
+            }
+            branch.push(self.parse_concat(true)?);
+            if self.peek() == Some(b'$') {
+                self.pos += 1;
+            } else {
+                branch.push(Ast::any_repeated());
+            }
+            branches.push(Ast::Concat(branch));
+
+            match self.peek() {
+                None => return Ok(Ast::Alternate(branches)),
+                Some(b'|') => self.pos += 1,
+                Some(_) => return Err(RegexError::UnexpectedByte(self.pos)),
+            }
+        }
+    }
+
+    /// Parses alternatives up to the end of a group.
+    fn parse_alternate(&mut self) -> Result<Ast, RegexError> {
+        let mut branches = vec![self.parse_concat(false)?];
+        while self.peek() == Some(b'|') {
+            self.pos += 1;
+            branches.push(self.parse_concat(false)?);
+        }
+        Ok(Ast::Alternate(branches))
+    }
+
+    /// Parses a sequence of repeated atoms. At the top level a `$` followed
+    /// by the end of the alternative ends the sequence.
+    fn parse_concat(&mut self, top_level: bool) -> Result<Ast, RegexError> {
+        let mut items = vec![];
+        while let Some(byte) = self.peek() {
+            match byte {
+                b'|' | b')' => break,
+                b'$' if top_level
+                    && matches!(self.pattern.get(self.pos + 1), None | Some(b'|')) =>
+                {
+                    break
+                }
+                _ => {}
+            }
+            let atom = self.parse_atom()?;
+            items.push(self.parse_repeat(atom)?);
+        }
+        Ok(Ast::Concat(items))
+    }
+
+    fn parse_atom(&mut self) -> Result<Ast, RegexError> {
+        let pos = self.pos;
+        match self.bump()? {
+            b'(' => {
+                if self.pattern[self.pos..].starts_with(b"?:") {
+                    self.pos += 2;
+                }
+                let ast = self.parse_alternate()?;
+                match self.bump()? {
+                    b')' => Ok(ast),
+                    _ => Err(RegexError::UnexpectedByte(self.pos - 1)),
+                }
+            }
+            b'[' => self.parse_class(),
+            b'.' => Ok(Ast::any()),
+            b'\\' => self.parse_escape().map(Ast::Bytes),
+            b'*' | b'+' | b'?' | b'{' => Err(RegexError::InvalidRepetition(pos)),
+            b'^' | b'$' | b']' | b'}' => Err(RegexError::UnexpectedByte(pos)),
+            byte => Ok(Ast::Bytes(vec![(byte, byte)])),
+        }
+    }
+
+    fn parse_repeat(&mut self, mut ast: Ast) -> Result<Ast, RegexError> {
+        loop {
+            let pos = self.pos;
+            let (min, max) = match self.peek() {
+                Some(b'*') => (0, None),
+                Some(b'+') => (1, None),
+                Some(b'?') => (0, Some(1)),
+                Some(b'{') => self.parse_counts()?,
+                _ => return Ok(ast),
+            };
+            if self.pos == pos {
+                self.pos += 1;
+            }
+            // Lazy repetitions match the same keys.
+            if self.peek() == Some(b'?') {
+                self.pos += 1;
+            }
+            ast = Ast::Repeat {
+                ast: Box::new(ast),
+                min,
+                max,
+            };
+        }
+    }
+
+    /// Parses the bounds of a `{n}`, `{n,}` or `{n,m}` repetition.
+    fn parse_counts(&mut self) -> Result<(u32, Option<u32>), RegexError> {
+        let pos = self.pos;
+        self.pos += 1;
+        let min = self.parse_count(pos)?;
+        let max = match self.bump()? {
+            b'}' => return Ok((min, Some(min))),
+            b',' if self.peek() == Some(b'}') => None,
+            b',' => Some(self.parse_count(pos)?),
+            _ => return Err(RegexError::InvalidRepetition(pos)),
+        };
+        if self.bump()? != b'}' || max.is_some_and(|max| max < min) {
+            return Err(RegexError::InvalidRepetition(pos));
+        }
+        Ok((min, max))
+    }
+
+    fn parse_count(&mut self, pos: usize) -> Result<u32, RegexError> {
+        let start = self.pos;
+        let mut count = 0u32;
+        while let Some(digit @ b'0'..=b'9') = self.peek() {
+            count = count * 10 + (digit - b'0') as u32;
+            if count > MAX_REPEAT {
+                return Err(RegexError::InvalidRepetition(pos));
+            }
+            self.pos += 1;
+        }
+        match self.pos > start {
+            true => Ok(count),
+            false if self.pos == self.pattern.len() => Err(RegexError::UnexpectedEnd),
+            false => Err(RegexError::InvalidRepetition(pos)),
+        }
+    }
+
+    /// Parses the escape after a `\`.
+    fn parse_escape(&mut self) -> Result<Vec<(u8, u8)>, RegexError> {
+        let pos = self.pos;
+        let ranges = match self.bump()? {
+            b'd' => vec![(b'0', b'9')],
+            b'w' => vec![(b'0', b'9'), (b'A', b'Z'), (b'_', b'_'), (b'a', b'z')],
+            b's' => vec![(b'\t', b'\r'), (b' ', b' ')],
+            b'D' => negate(vec![(b'0', b'9')]),
+            b'W' => negate(vec![(b'0', b'9'), (b'A', b'Z'), (b'_', b'_'), (b'a', b'z')]),
+            b'S' => negate(vec![(b'\t', b'\r'), (b' ', b' ')]),
+            b'n' => vec![(b'\n', b'\n')],
+            b'r' => vec![(b'\r', b'\r')],
+            b't' => vec![(b'\t', b'\t')],
+            b'0' => vec![(0, 0)],
+            b'x' => {
+                let hex = |byte: u8| (byte as char).to_digit(16);
+                let high = hex(self.bump()?).ok_or(RegexError::UnexpectedByte(self.pos - 1))?;
+                let low = hex(self.bump()?).ok_or(RegexError::UnexpectedByte(self.pos - 1))?;
+                let byte = (high * 16 + low) as u8;
+                vec![(byte, byte)]
+            }
+            byte if byte.is_ascii_punctuation() || byte == b' ' => vec![(byte, byte)],
+            _ => return Err(RegexError::UnexpectedByte(pos)),
+        };
+        Ok(ranges)
+    }
+
+    /// Parses a class after its `[`.
+    fn parse_class(&mut self) -> Result<Ast, RegexError> {
+        let negated = self.peek() == Some(b'^');
+        if negated {
+            self.pos += 1;
+        }
+        let mut ranges = vec![];
+        let mut first = true;
+        loop {
+            let pos = self.pos;
+            let start = match self.bump()? {
+                b']' if !first => break,
+                b'\\' => {
+                    let escaped = self.parse_escape()?;
+                    if escaped.len() > 1 || escaped[0].0 != escaped[0].1 {
+                        ranges.extend(escaped);
+                        first = false;
+                        continue;
+                    }
+                    escaped[0].0
+                }
+                byte => byte,
+            };
+            first = false;
+
+            let is_range = self.peek() == Some(b'-')
+                && !matches!(self.pattern.get(self.pos + 1), None | Some(b']'));
+            if !is_range {
+                ranges.push((start, start));
+                continue;
+            }
+            self.pos += 1;
+            let end = match self.bump()? {
+                b'\\' => match self.parse_escape()?.as_slice() {
+                    [(byte, end)] if byte == end => *byte,
+                    _ => return Err(RegexError::UnexpectedByte(pos)),
+                },
+                byte => byte,
+            };
+            if end < start {
+                return Err(RegexError::UnexpectedByte(pos));
+            }
+            ranges.push((start, end));
+        }
+
+        let ranges = normalize(ranges);
+        Ok(Ast::Bytes(match negated {
+            true => negate(ranges),
+            false => ranges,
+        }))
+    }
+}
+
+/// Sorts and merges overlapping or adjacent ranges.
+fn normalize(mut ranges: Vec<(u8, u8)>) -> Vec<(u8, u8)> {
+    ranges.sort_unstable();
+    let mut merged: Vec<(u8, u8)> = Vec::with_capacity(ranges.len());
+    for (start, end) in ranges {
+        match merged.last_mut() {
+            Some(last) if start as u16 <= last.1 as u16 + 1 => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+    merged
+}
+
+/// Returns the bytes not covered by normalized `ranges`.
+fn negate(ranges: Vec<(u8, u8)>) -> Vec<(u8, u8)> {
+    let mut negated = vec![];
+    let mut next = 0u16;
+    for (start, end) in normalize(ranges) {
+        if (start as u16) > next {
+            negated.push((next as u8, start - 1));
+        }
+        next = end as u16 + 1;
+    }
+    if next <= 255 {
+        negated.push((next as u8, 255));
+    }
+    negated
+}
+
+#[derive(Debug, Clone)]
+enum NfaState {
+    Bytes { ranges: Vec<(u8, u8)>, next: usize },
+    Split(usize, usize),
+    Goto(usize),
+    Match,
+}
+
+/// A Thompson NFA. States are compiled back to front so each fragment knows
+/// the state that follows it.
+#[derive(Default)]
+struct Nfa {
+    states: Vec<NfaState>,
+}
+
+impl Nfa {
+    fn push(&mut self, state: NfaState) -> usize {
+        self.states.push(state);
+        self.states.len() - 1
+    }
+
+    /// Compiles `ast` followed by `next` and returns the first state.
+    fn compile(&mut self, ast: &Ast, next: usize) -> usize {
+        match ast {
+            Ast::Bytes(ranges) => self.push(NfaState::Bytes {
+                ranges: ranges.clone(),
+                next,
+            }),
+            Ast::Concat(items) => items
+                .iter()
+                .rev()
+                .fold(next, |next, item| self.compile(item, next)),
+            Ast::Alternate(branches) => {
+                let starts: Vec<usize> = branches
+                    .iter()
+                    .map(|branch| self.compile(branch, next))
+                    .collect();
+                starts
+                    .into_iter()
+                    .rev()
+                    .reduce(|rest, start| self.push(NfaState::Split(start, rest)))
+                    .unwrap_or(next)
+            }
+            Ast::Repeat { ast, min, max } => {
+                let mut start = match max {
+                    Some(max) => (*min..*max).fold(next, |start, _| {
+                        let body = self.compile(ast, start);
+                        self.push(NfaState::Split(body, next))
+                    }),
+                    None => {
+                        let split = self.push(NfaState::Goto(next));
+                        let body = self.compile(ast, split);
+                        self.states[split] = NfaState::Split(body, next);
+                        split
+                    }
+                };
+                for _ in 0..*min {
+                    start = self.compile(ast, start);
+                }
+                start
+            }
+        }
+    }
+
+    /// Returns the sorted byte and match states reachable from `states`
+    /// without consuming a byte.
+    fn closure(&self, states: &[usize], visited: &mut [bool]) -> Vec<usize> {
+        visited.fill(false);
+        let mut stack = states.to_vec();
+        let mut closure = vec![];
+        while let Some(state) = stack.pop() {
+            if core::mem::replace(&mut visited[state], true) {
+                continue;
+            }
+            match self.states[state] {
+                NfaState::Bytes { .. } | NfaState::Match => closure.push(state),
+                NfaState::Split(a, b) => stack.extend([b, a]),
+                NfaState::Goto(next) => stack.push(next),
+            }
+        }
+        closure.sort_unstable();
+        closure
+    }
+
+    /// Builds a DFA with the subset construction. State 0 is the dead state.
+    fn into_dfa(self, start: usize) -> Result<Regex, RegexError> {
+        // Split the bytes into classes that every state treats the same.
+        let mut boundaries = [false; 256];
+        for state in &self.states {
+            if let NfaState::Bytes { ranges, .. } = state {
+                for &(start, end) in ranges {
+                    boundaries[start as usize] = true;
+                    if end < 255 {
+                        boundaries[end as usize + 1] = true;
+                    }
+                }
+            }
+        }
+        let mut classes = Box::new([0u8; 256]);
+        let mut representatives = vec![0u8];
+        for byte in 1..256 {
+            if boundaries[byte] {
+                representatives.push(byte as u8);
+            }
+            classes[byte] = (representatives.len() - 1) as u8;
+        }
+        let stride = representatives.len();
+
+        let mut visited = vec![false; self.states.len()];
+        let mut sets: Vec<Vec<usize>> = vec![vec![]];
+        let mut ids: BTreeMap<Vec<usize>, u32> = BTreeMap::new();
+        ids.insert(vec![], DEAD);
+        let start_set = self.closure(&[start], &mut visited);
+        let start = *ids.entry(start_set.clone()).or_insert(1);
+        if start != DEAD {
+            sets.push(start_set);
+        }
+
+        let mut transitions = vec![DEAD; stride];
+        let mut matches = vec![false];
+        let mut state = 1;
+        while state < sets.len() {
+            let set = core::mem::take(&mut sets[state]);
+            matches.push(
+                set.iter()
+                    .any(|&s| matches!(self.states[s], NfaState::Match)),
+            );
+            for &byte in &representatives {
+                let next: Vec<usize> = set
+                    .iter()
+                    .filter_map(|&s| match &self.states[s] {
+                        NfaState::Bytes { ranges, next }
+                            if ranges.iter().any(|r| (r.0..=r.1).contains(&byte)) =>
+                        {
+                            Some(*next)
+                        }
+                        _ => None,
+                    })
+                    .collect();
+                let next = self.closure(&next, &mut visited);
+                let id = match ids.get(&next) {
+                    Some(&id) => id,
+                    None => {
+                        if sets.len() == MAX_STATES {
+                            return Err(RegexError::TooManyStates);
+                        }
+                        let id = sets.len() as u32;
+                        ids.insert(next.clone(), id);
+                        sets.push(next);
+                        id
+                    }
+                };
+                transitions.push(id);
+            }
+            state += 1;
+        }
+
+        Ok(Regex {
+            classes,
+            stride,
+            transitions,
+            matches,
+            start,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::map::RadixMap;
+
+    const KEYS: &[&str] = &[
+        "",
+        "a",
+        "ab",
+        "abc",
+        "abcabc",
+        "b",
+        "ba",
+        "user/",
+        "user/1/settings",
+        "user/1/settings/x",
+        "user/12/profile",
+        "user/12/settings",
+        "user/x/settings",
+        "users/1/settings",
+        "x/user/3/settings",
+    ];
+
+    fn check<F: Fn(&str) -> bool>(pattern: &str, f: F) {
+        let regex = Regex::new(pattern).unwrap();
+        let map: RadixMap<usize> = KEYS.iter().enumerate().map(|(i, k)| (k, i)).collect();
+        let expected: Vec<(Box<[u8]>, usize)> = KEYS
+            .iter()
+            .enumerate()
+            .filter(|(_, key)| f(key))
+            .map(|(i, key)| (key.as_bytes().into(), i))
+            .collect();
+        let found: Vec<_> = map
+            .search(&regex)
+            .map(|(key, value)| (key, *value))
+            .collect();
+        assert_eq!(found, expected, "{}", pattern);
+        for key in KEYS {
+            assert_eq!(regex.matches(key), f(key), "{} {}", pattern, key);
+        }
+    }
+
+    fn is_id(s: &str) -> bool {
+        !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit())
+    }
+
+    #[test]
+    fn test_regex_search() {
+        check(r"^user/[0-9]+/settings$", |key| {
+            let parts: Vec<&str> = key.split('/').collect();
+            parts.len() == 3 && parts[0] == "user" && is_id(parts[1]) && parts[2] == "settings"
+        });
+        check(r"^user/\d+/", |key| {
+            let parts: Vec<&str> = key.split('/').collect();
+            parts.len() > 2 && parts[0] == "user" && is_id(parts[1])
+        });
+        check("settings$", |key| key.ends_with("settings"));
+        check("/12/", |key| key.contains("/12/"));
+        check("", |_| true);
+        check("^$", str::is_empty);
+        check("^(abc)*$", |key| ["", "abc", "abcabc"].contains(&key));
+        check("^a(b|bc)?$", |key| ["a", "ab", "abc"].contains(&key));
+        check("^(?:a|b)[^/c]?$", |key| {
+            ["a", "ab", "b", "ba"].contains(&key)
+        });
+        check("^.{2}$", |key| key.len() == 2);
+        check("^.{2,}$", |key| key.len() >= 2);
+        check("^.{1,3}$", |key| (1..=3).contains(&key.len()));
+        check(r"^\w+$", |key| !key.is_empty() && !key.contains('/'));
+        check("^a+?$|^user/$", |key| key == "a" || key == "user/");
+        check("^[x-z]|^b", |key| {
+            key.starts_with('x') || key.starts_with('b')
+        });
+        check("^never", |_| false);
+    }
+
+    #[test]
+    fn test_regex_pruning() {
+        let regex = Regex::new(r"^user/[0-9]+/settings$").unwrap();
+        let state = b"use"
+            .iter()
+            .fold(regex.start(), |s, &b| regex.accept(&s, b));
+        assert!(regex.can_match(&state));
+        let state = regex.accept(&state, b'x');
+        assert!(!regex.can_match(&state));
+
+        let state = b"user/1/settings"
+            .iter()
+            .fold(regex.start(), |s, &b| regex.accept(&s, b));
+        assert!(regex.is_match(&state));
+        assert!(!regex.can_match(&regex.accept(&state, b'/')));
+
+        // The empty class never matches, so keys starting with `a` are dead
+        // even though the NFA still has states left after the `a`.
+        let regex = Regex::new(r"^a[^\x00-\xff]|^b").unwrap();
+        assert!(regex.can_match(&regex.start()));
+        assert!(!regex.can_match(&regex.accept(&regex.start(), b'a')));
+        assert!(regex.can_match(&regex.accept(&regex.start(), b'b')));
+        let regex = Regex::new(r"^[^\x00-\xff]").unwrap();
+        assert!(!regex.can_match(&regex.start()));
+    }
+
+    #[test]
+    fn test_regex_errors() {
+        assert_eq!(Regex::new("(ab").unwrap_err(), RegexError::UnexpectedEnd);
+        assert_eq!(
+            Regex::new("ab)").unwrap_err(),
+            RegexError::UnexpectedByte(2)
+        );
+        assert_eq!(Regex::new("[ab").unwrap_err(), RegexError::UnexpectedEnd);
+        assert_eq!(
+            Regex::new("*a").unwrap_err(),
+            RegexError::InvalidRepetition(0)
+        );
+        assert_eq!(
+            Regex::new("a{2,1}").unwrap_err(),
+            RegexError::InvalidRepetition(1)
+        );
+        assert_eq!(
+            Regex::new("a{x}").unwrap_err(),
+            RegexError::InvalidRepetition(1)
+        );
+        assert_eq!(
+            Regex::new("a{1001}").unwrap_err(),
+            RegexError::InvalidRepetition(1)
+        );
+        assert_eq!(
+            Regex::new("a^b").unwrap_err(),
+            RegexError::UnexpectedByte(1)
+        );
+        assert_eq!(
+            Regex::new("a$b").unwrap_err(),
+            RegexError::UnexpectedByte(1)
+        );
+        assert_eq!(
+            Regex::new(r"\q").unwrap_err(),
+            RegexError::UnexpectedByte(1)
+        );
+        assert_eq!(Regex::new(r"\x4").unwrap_err(), RegexError::UnexpectedEnd);
+        assert_eq!(
+            Regex::new("[b-a]").unwrap_err(),
+            RegexError::UnexpectedByte(1)
+        );
+        assert_eq!(
+            Regex::new("^[ab]*a.{20}$").unwrap_err(),
+            RegexError::TooManyStates
+        );
+
+        let regex = Regex::new(r"^[\x41-\x43\]-]\.$").unwrap();
+        assert!(regex.matches("B."));
+        assert!(regex.matches("]."));
+        assert!(regex.matches("-."));
+        assert!(!regex.matches("Bx"));
+        assert!(!regex.matches("D."));
+    }
+}
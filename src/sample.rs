@@ -0,0 +1,345 @@
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use rand::seq::SliceRandom;
+use rand::Rng;
+
+use crate::allocator::NodeAllocator;
+use crate::map::RadixMap;
+use crate::node::Node;
+use crate::summary::{part_summary, Contains, Count, Summary};
+
+/// Caches the total weight of the values in each subtree, where the weight
+/// of a value is computed by a function. Used by
+/// [`RadixMap::sample_weighted()`].
+///
+/// Totals saturate at `u64::MAX`. Sampling is exact as long as the total
+/// weight of the map fits in a `u64`, beyond that the entries past the point
+/// of saturation in key order are chosen less often than their weight says.
+#[derive(Debug, Clone, Copy)]
+pub struct Weight<F>(F);
+
+impl<F> Weight<F> {
+    pub fn new(weight: F) -> Self {
+        Weight(weight)
+    }
+}
+
+impl<T, F: Fn(&T) -> u64> Summary<T> for Weight<F> {
+    type Output = u64;
+
+    #[inline]
+    fn summarize(&self, value: &T) -> u64 {
+        (self.0)(value)
+    }
+
+    #[inline]
+    fn combine(&self, a: u64, b: u64) -> u64 {
+        a.saturating_add(b)
+    }
+}
+
+// Uniform sampling needs the entry counts and weighted sampling the weights
+// of the subtrees, alone or as part of a tuple of summaries.
+impl<T, A: NodeAllocator, S: Summary<T>> RadixMap<T, A, S> {
+    /// Returns an entry chosen uniformly at random, or `None` if the map is
+    /// empty.
+    pub fn sample<R: Rng + ?Sized, I>(&self, rng: &mut R) -> Option<(Box<[u8]>, &T)>
+    where
+        S: Contains<T, Count, I>,
+    {
+        if self.is_empty() {
+            return None;
+        }
+        self.select(rng.gen_range(0..self.len()))
+    }
+
+    /// Returns an entry whose key starts with `prefix` chosen uniformly at
+    /// random, or `None` if there is none.
+    pub fn sample_prefix<K: AsRef<[u8]>, R: Rng + ?Sized, I>(
+        &self,
+        prefix: K,
+        rng: &mut R,
+    ) -> Option<(Box<[u8]>, &T)>
+    where
+        S: Contains<T, Count, I>,
+    {
+        let prefix = prefix.as_ref();
+        let count = self.count_prefix(prefix);
+        if count == 0 {
+            return None;
+        }
+        // The keys that start with the prefix directly follow the keys
+        // smaller than it.
+        self.select(self.rank(prefix) + rng.gen_range(0..count))
+    }
+
+    /// Returns `k` distinct entries chosen uniformly at random in random
+    /// order, or all entries if the map has fewer than `k`.
+    ///
+    /// The entries are looked up together in a single traversal that only
+    /// enters the subtrees containing chosen entries, so nodes shared by the
+    /// paths to several of them are visited once.
+    pub fn sample_k<R: Rng + ?Sized, I>(&self, k: usize, rng: &mut R) -> Vec<(Box<[u8]>, &T)>
+    where
+        S: Contains<T, Count, I>,
+    {
+        let mut indices = rand::seq::index::sample(rng, self.len(), k.min(self.len())).into_vec();
+        indices.sort_unstable();
+        let mut sample = select_sorted(self.root(), self.summary(), &indices);
+        sample.shuffle(rng);
+        sample
+    }
+
+    /// Returns an entry chosen at random with a probability proportional to
+    /// its weight, or `None` if the total weight is zero.
+    pub fn sample_weighted<R: Rng + ?Sized, F: Fn(&T) -> u64, I>(
+        &self,
+        rng: &mut R,
+    ) -> Option<(Box<[u8]>, &T)>
+    where
+        S: Contains<T, Weight<F>, I>,
+    {
+        self.sample_weighted_prefix([], rng)
+    }
+
+    /// Same as [`Self::sample_weighted()`] but only chooses among the entries
+    /// whose keys start with `prefix`.
+    pub fn sample_weighted_prefix<K: AsRef<[u8]>, R: Rng + ?Sized, F: Fn(&T) -> u64, I>(
+        &self,
+        prefix: K,
+        rng: &mut R,
+    ) -> Option<(Box<[u8]>, &T)>
+    where
+        S: Contains<T, Weight<F>, I>,
+    {
+        let prefix = prefix.as_ref();
+        let summary = self.summary();
+        let weight = summary.part();
+        let (offset, mut node) = match prefix.is_empty() {
+            true => (0, self.root()),
+            false => self.root().find_prefix(prefix)?,
+        };
+        let total = part_summary(node, summary).unwrap_or(0);
+        if total == 0 {
+            return None;
+        }
+
+        // Descends into the child whose range of cumulative weights contains
+        // the target, skipping the weight of the subtrees before it.
+        let mut key = prefix[..offset].to_vec();
+        let mut target = rng.gen_range(0..total);
+        'descend: loop {
+            key.extend_from_slice(node.key());
+            if let Some(value) = node.value() {
+                let value_weight = weight.summarize(value);
+                if target < value_weight {
+                    return Some((key.into(), value));
+                }
+                target -= value_weight;
+            }
+            for child in node.children() {
+                let child_weight = part_summary(child, summary).unwrap_or(0);
+                if target < child_weight {
+                    node = child;
+                    continue 'descend;
+                }
+                target -= child_weight;
+            }
+            unreachable!("subtree weights are out of date");
+        }
+    }
+}
+
+/// Returns the entries with the given indices in key order, which must be
+/// sorted and distinct. Every subtree is entered at most once, together with
+/// the indices of all chosen entries in it.
+fn select_sorted<'a, T, S: Contains<T, Count, I>, I>(
+    root: &'a Node<T, S::Output>,
+    summary: &S,
+    indices: &[usize],
+) -> Vec<(Box<[u8]>, &'a T)> {
+    let mut entries = Vec::with_capacity(indices.len());
+    let mut prefix = vec![];
+    // Subtrees to enter with the index of their first entry and the chosen
+    // indices that fall into them.
+    let mut stack = vec![(0, root, 0, indices)];
+    while let Some((prefix_len, node, mut first, mut indices)) = stack.pop() {
+        prefix.truncate(prefix_len);
+        prefix.extend_from_slice(node.key());
+        if let Some(value) = node.value() {
+            if indices.first() == Some(&first) {
+                entries.push((prefix.as_slice().into(), value));
+                indices = &indices[1..];
+            }
+            first += 1;
+        }
+
+        for child in node.children() {
+            if indices.is_empty() {
+                break;
+            }
+            let count = part_summary(child, summary).unwrap_or(0);
+            let split = indices.partition_point(|&index| index < first + count);
+            if split > 0 {
+                stack.push((prefix.len(), child, first, &indices[..split]));
+                indices = &indices[split..];
+            }
+            first += count;
+        }
+    }
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use std::collections::{BTreeMap, BTreeSet};
+
+    const KEYS: [&str; 8] = ["", "a", "ab", "abc", "abd", "b", "ba", "c"];
+
+    fn histogram(samples: impl Iterator<Item = Box<[u8]>>) -> BTreeMap<Box<[u8]>, usize> {
+        let mut histogram = BTreeMap::new();
+        for key in samples {
+            *histogram.entry(key).or_default() += 1;
+        }
+        histogram
+    }
+
+    #[test]
+    fn test_sample() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut map = RadixMap::with_summary(Count);
+        assert_eq!(map.sample(&mut rng), None);
+        map.extend(KEYS.iter().map(|key| (key, key.len())));
+
+        let counts = histogram((0..40_000).map(|_| map.sample(&mut rng).unwrap().0));
+        assert_eq!(counts.len(), KEYS.len());
+        for count in counts.values() {
+            assert!((4500..5500).contains(count), "{:?}", counts);
+        }
+
+        let counts = histogram((0..30_000).map(|_| map.sample_prefix("ab", &mut rng).unwrap().0));
+        assert_eq!(
+            counts.keys().map(|key| &key[..]).collect::<Vec<_>>(),
+            [&b"ab"[..], b"abc", b"abd"]
+        );
+        for count in counts.values() {
+            assert!((9500..10500).contains(count), "{:?}", counts);
+        }
+        assert_eq!(
+            map.sample_prefix("c", &mut rng),
+            Some((b"c"[..].into(), &1))
+        );
+        assert_eq!(map.sample_prefix("d", &mut rng), None);
+    }
+
+    #[test]
+    fn test_sample_k() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let map: RadixMap<usize, crate::Global, Count> =
+            (0..1000).map(|i| (format!("{:03}", i), i)).collect();
+
+        for k in [0, 1, 10, 999, 1000, 5000] {
+            let sample = map.sample_k(k, &mut rng);
+            assert_eq!(sample.len(), k.min(map.len()));
+            let keys: BTreeSet<_> = sample.iter().map(|(key, _)| key.clone()).collect();
+            assert_eq!(keys.len(), sample.len());
+            for (key, value) in sample {
+                assert_eq!(map.get(key), Some(value));
+            }
+        }
+    }
+
+    #[test]
+    fn test_sample_weighted() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut map = RadixMap::with_summary(Weight::new(|value: &u64| *value));
+        assert_eq!(map.sample_weighted(&mut rng), None);
+        for (i, key) in KEYS.iter().enumerate() {
+            map.insert(key, i as u64);
+        }
+
+        // The empty key has a weight of zero and is never chosen.
+        let counts = histogram((0..28_000).map(|_| map.sample_weighted(&mut rng).unwrap().0));
+        assert_eq!(counts.len(), KEYS.len() - 1);
+        for (i, key) in KEYS.iter().enumerate().skip(1) {
+            let expected = 1000 * i;
+            let count = counts[key.as_bytes()];
+            assert!(
+                count.abs_diff(expected) < 150 + expected / 10,
+                "{:?}",
+                counts
+            );
+        }
+
+        let counts =
+            histogram((0..11_000).map(|_| map.sample_weighted_prefix("b", &mut rng).unwrap().0));
+        assert!(counts[&b"b"[..]].abs_diff(5_000) < 300, "{:?}", counts);
+        assert!(counts[&b"ba"[..]].abs_diff(6_000) < 300, "{:?}", counts);
+
+        map.insert("b", 0);
+        map.insert("ba", 0);
+        assert_eq!(map.sample_weighted_prefix("b", &mut rng), None);
+        assert_eq!(map.sample_weighted_prefix("x", &mut rng), None);
+    }
+
+    #[test]
+    fn test_select_sorted() {
+        let map: RadixMap<usize, crate::Global, Count> =
+            KEYS.iter().enumerate().map(|(i, key)| (key, i)).collect();
+        for mask in 0..1u32 << KEYS.len() {
+            let indices: Vec<_> = (0..KEYS.len()).filter(|i| mask & (1 << i) != 0).collect();
+            let expected: Vec<_> = indices.iter().map(|&i| map.select(i).unwrap()).collect();
+            let mut selected = select_sorted(map.root(), map.summary(), &indices);
+            selected.sort();
+            assert_eq!(selected, expected);
+        }
+    }
+
+    #[test]
+    fn test_sample_k_uniform() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let map: RadixMap<usize, crate::Global, Count> =
+            KEYS.iter().enumerate().map(|(i, key)| (key, i)).collect();
+        let counts = histogram((0..10_000).flat_map(|_| {
+            map.sample_k(3, &mut rng)
+                .into_iter()
+                .map(|(key, _)| key)
+                .collect::<Vec<_>>()
+        }));
+        assert_eq!(counts.len(), KEYS.len());
+        for count in counts.values() {
+            assert!(count.abs_diff(3750) < 250, "{:?}", counts);
+        }
+    }
+
+    #[test]
+    fn test_weight_saturates() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut map = RadixMap::with_summary(Weight::new(|value: &u64| *value));
+        map.extend([("a", u64::MAX), ("ab", u64::MAX), ("b", 1)]);
+        assert_eq!(map.summary_prefix(""), Some(u64::MAX));
+        for _ in 0..100 {
+            assert_eq!(&*map.sample_weighted(&mut rng).unwrap().0, b"a");
+        }
+    }
+
+    #[test]
+    fn test_sample_combined_summaries() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut map = RadixMap::with_summary((Count, Weight::new(|value: &u64| *value)));
+        map.extend([("a", 0), ("b", 1), ("c", 0)]);
+        assert_eq!(map.sample_weighted(&mut rng), Some((b"b"[..].into(), &1)));
+        assert_eq!(
+            map.sample_prefix("c", &mut rng),
+            Some((b"c"[..].into(), &0))
+        );
+        let sample: BTreeSet<_> = map.sample_k(3, &mut rng).into_iter().collect();
+        assert_eq!(sample.len(), 3);
+    }
+}
@@ -1,18 +1,115 @@
+use alloc::boxed::Box;
+use alloc::vec;
+
+use crate::allocator::{Global, NodeAllocator};
+use crate::automaton::{Automaton, SearchKeys};
+use crate::error::RadixError;
+use crate::fuzzy::{Fuzzy, FuzzyKeys};
+use crate::growth::GrowthPolicy;
 use crate::iter::{Iter, MapK, MapV};
 use crate::map::RadixMap;
+use crate::stats::TreeStats;
 
-#[derive(Default, Debug)]
-pub struct RadixSet {
-    inner: RadixMap<()>,
+#[derive(Debug)]
+pub struct RadixSet<A: NodeAllocator = Global> {
+    inner: RadixMap<(), A>,
+}
+
+impl<A: NodeAllocator + Default> Default for RadixSet<A> {
+    fn default() -> Self {
+        RadixSet::new_in(A::default())
+    }
 }
 
 impl RadixSet {
     pub fn new() -> Self {
+        RadixSet::new_in(Global)
+    }
+
+    /// Creates an empty set whose children arrays grow according to `policy`.
+    pub fn with_growth_policy(policy: GrowthPolicy) -> Self {
+        RadixSet::with_growth_policy_in(policy, Global)
+    }
+
+    /// Creates an empty set that allocates at most `limit` bytes for its
+    /// nodes. Inserts that would exceed the limit fail with
+    /// [`RadixError::CapacityExceeded`].
+    pub fn with_memory_limit(limit: usize) -> Self {
+        RadixSet::with_memory_limit_in(limit, Global)
+    }
+
+    /// Creates a set from an iterator of keys sorted in ascending order.
+    ///
+    /// Unlike [`Self::insert()`] this allocates every node exactly once.
+    /// Duplicate keys are ignored.
+    ///
+    /// Returns [`RadixError::UnsortedKeys`] if the keys are not sorted.
+    pub fn from_sorted_iter<K: AsRef<[u8]>, I: IntoIterator<Item = K>>(
+        iter: I,
+    ) -> Result<Self, RadixError> {
+        RadixSet::from_sorted_iter_in(iter, Global)
+    }
+}
+
+impl<A: NodeAllocator> RadixSet<A> {
+    /// Creates an empty set whose nodes are allocated by `alloc`.
+    pub fn new_in(alloc: A) -> Self {
         RadixSet {
-            inner: RadixMap::new(),
+            inner: RadixMap::new_in(alloc),
         }
     }
 
+    /// Same as [`RadixSet::with_growth_policy()`] but the nodes are allocated
+    /// by `alloc`.
+    pub fn with_growth_policy_in(policy: GrowthPolicy, alloc: A) -> Self {
+        RadixSet {
+            inner: RadixMap::with_growth_policy_in(policy, alloc),
+        }
+    }
+
+    /// Same as [`RadixSet::with_memory_limit()`] but the nodes are allocated
+    /// by `alloc`.
+    pub fn with_memory_limit_in(limit: usize, alloc: A) -> Self {
+        RadixSet {
+            inner: RadixMap::with_memory_limit_in(limit, alloc),
+        }
+    }
+
+    /// Same as [`RadixSet::from_sorted_iter()`] but the nodes are allocated
+    /// by `alloc`.
+    pub fn from_sorted_iter_in<K: AsRef<[u8]>, I: IntoIterator<Item = K>>(
+        iter: I,
+        alloc: A,
+    ) -> Result<Self, RadixError> {
+        Ok(RadixSet {
+            inner: RadixMap::from_sorted_iter_in(iter.into_iter().map(|k| (k, ())), alloc)?,
+        })
+    }
+
+    /// Returns a reference to the set's allocator.
+    #[inline(always)]
+    pub fn allocator(&self) -> &A {
+        self.inner.allocator()
+    }
+
+    /// Returns the growth policy of the set's children arrays.
+    #[inline(always)]
+    pub fn growth_policy(&self) -> GrowthPolicy {
+        self.inner.growth_policy()
+    }
+
+    /// Returns the memory limit of the set, if any.
+    #[inline(always)]
+    pub fn memory_limit(&self) -> Option<usize> {
+        self.inner.memory_limit()
+    }
+
+    /// Returns the number of bytes the set has allocated for its nodes.
+    #[inline(always)]
+    pub fn memory_usage(&self) -> usize {
+        self.inner.memory_usage()
+    }
+
     /// Returns the number of elements in the set.
     #[inline(always)]
     pub fn len(&self) -> usize {
@@ -25,6 +122,18 @@ impl RadixSet {
         self.inner.is_empty()
     }
 
+    /// Clears the set, removing all values.
+    #[inline(always)]
+    pub fn clear(&mut self) {
+        self.inner.clear()
+    }
+
+    /// Releases the spare capacity of all children arrays.
+    #[inline(always)]
+    pub fn shrink_to_fit(&mut self) {
+        self.inner.shrink_to_fit()
+    }
+
     /// Adds a value to the set.
     ///
     /// If the set did not have an equal element present, true is returned.
@@ -35,6 +144,24 @@ impl RadixSet {
         self.inner.insert(key.as_ref(), ()).is_none()
     }
 
+    /// Adds a value to the set like [`Self::insert()`], but returns an error
+    /// and leaves the set unchanged if memory can't be allocated or the
+    /// memory limit would be exceeded.
+    #[inline(always)]
+    pub fn try_insert<K: AsRef<[u8]>>(&mut self, key: K) -> Result<bool, RadixError> {
+        Ok(self.inner.try_insert(key.as_ref(), ())?.is_none())
+    }
+
+    /// Adds the values of an iterator one by one like [`Self::try_insert()`].
+    /// Stops at the first value that can't be inserted and returns its
+    /// error. The values inserted before it stay in the set.
+    pub fn try_extend<K: AsRef<[u8]>, I: IntoIterator<Item = K>>(
+        &mut self,
+        iter: I,
+    ) -> Result<(), RadixError> {
+        self.inner.try_extend(iter.into_iter().map(|k| (k, ())))
+    }
+
     /// If the set contains an element equal to the value, removes it from
     /// the set and drops it. Returns whether such an element was present.
     #[inline(always)]
@@ -48,36 +175,99 @@ impl RadixSet {
         self.inner.contains_key(key)
     }
 
+    /// Returns the largest element smaller than or equal to `key`.
+    #[inline(always)]
+    pub fn floor<K: AsRef<[u8]>>(&self, key: K) -> Option<Box<[u8]>> {
+        self.inner.floor(key).map(|(key, _)| key)
+    }
+
+    /// Returns the smallest element greater than or equal to `key`.
+    #[inline(always)]
+    pub fn ceiling<K: AsRef<[u8]>>(&self, key: K) -> Option<Box<[u8]>> {
+        self.inner.ceiling(key).map(|(key, _)| key)
+    }
+
+    /// Returns the largest element strictly smaller than `key`.
+    #[inline(always)]
+    pub fn lower<K: AsRef<[u8]>>(&self, key: K) -> Option<Box<[u8]>> {
+        self.inner.lower(key).map(|(key, _)| key)
+    }
+
+    /// Returns the smallest element strictly greater than `key`.
+    #[inline(always)]
+    pub fn higher<K: AsRef<[u8]>>(&self, key: K) -> Option<Box<[u8]>> {
+        self.inner.higher(key).map(|(key, _)| key)
+    }
+
     /// Gets an iterator that visits the elements of this set in ascending order.
     #[inline(always)]
-    pub fn iter(&self) -> Iter<(), MapK<()>> {
+    pub fn iter(&self) -> Iter<'_, (), MapK<'_, ()>> {
         self.inner.keys()
     }
 
     /// Gets an iterator that visits the elements matching a given prefix in ascending order.
     #[inline(always)]
-    pub fn prefix_iter<K: AsRef<[u8]>>(&self, prefix: K) -> Iter<(), MapK<()>> {
+    pub fn prefix_iter<K: AsRef<[u8]>>(&self, prefix: K) -> Iter<'_, (), MapK<'_, ()>> {
         self.inner.prefix_keys(prefix)
     }
 
+    /// Gets an iterator over the elements within `max_distance` edits of
+    /// `query` in ascending order, along with their Levenshtein distance.
+    #[inline(always)]
+    pub fn fuzzy_search<K: AsRef<[u8]>>(&self, query: K, max_distance: usize) -> FuzzyKeys<'_> {
+        self.fuzzy_search_with(query, Fuzzy::new(max_distance))
+    }
+
+    /// Same as [`Self::fuzzy_search()`] with the distance options of `fuzzy`.
+    #[inline(always)]
+    pub fn fuzzy_search_with<K: AsRef<[u8]>>(&self, query: K, fuzzy: Fuzzy) -> FuzzyKeys<'_> {
+        FuzzyKeys::new(self.inner.fuzzy_search_with(query, fuzzy))
+    }
+
+    /// Gets an iterator over the elements matching `aut` in ascending order.
+    /// Pass a [`Regex`](crate::Regex) to search by regular expression.
+    #[inline(always)]
+    pub fn search<M: Automaton>(&self, aut: M) -> SearchKeys<'_, M> {
+        SearchKeys::new(self.inner.search(aut))
+    }
+
+    /// Returns memory and shape statistics of the set.
+    #[inline(always)]
+    pub fn stats(&self) -> TreeStats {
+        self.inner.stats()
+    }
+
+    /// Returns memory and shape statistics of the subtree holding all elements
+    /// starting with a given prefix.
+    #[inline(always)]
+    pub fn prefix_stats<K: AsRef<[u8]>>(&self, prefix: K) -> TreeStats {
+        self.inner.prefix_stats(prefix)
+    }
+
     /// Visits the elements representing the intersection, i.e., the elements that are both in
     /// `self` and `other`, in ascending order.
     #[inline(always)]
-    pub fn intersection<'a, 'b>(&'a self, other: &'b RadixSet) -> Intersection<'a, 'b> {
+    pub fn intersection<'a, 'b, B: NodeAllocator>(
+        &'a self,
+        other: &'b RadixSet<B>,
+    ) -> Intersection<'a, 'b> {
         Intersection::new(self, other)
     }
 
     /// Visits the elements representing the union, i.e., all the elements in `self` or `other`,
     /// without duplicates, in ascending order.
     #[inline(always)]
-    pub fn union<'a, 'b>(&'a self, other: &'b RadixSet) -> Union<'a, 'b> {
+    pub fn union<'a, 'b, B: NodeAllocator>(&'a self, other: &'b RadixSet<B>) -> Union<'a, 'b> {
         Union::new(self, other)
     }
 
     /// Visits the elements representing the difference, i.e., the elements that are in `self`
     /// but not in `other`, in ascending order.
     #[inline(always)]
-    pub fn difference<'a, 'b>(&'a self, other: &'b RadixSet) -> Difference<'a, 'b> {
+    pub fn difference<'a, 'b, B: NodeAllocator>(
+        &'a self,
+        other: &'b RadixSet<B>,
+    ) -> Difference<'a, 'b, B> {
         Difference::new(self, other)
     }
 }
@@ -88,7 +278,10 @@ pub struct Intersection<'a, 'b> {
 }
 
 impl<'a, 'b> Intersection<'a, 'b> {
-    fn new(left: &'a RadixSet, right: &'b RadixSet) -> Self {
+    fn new<A: NodeAllocator, B: NodeAllocator>(
+        left: &'a RadixSet<A>,
+        right: &'b RadixSet<B>,
+    ) -> Self {
         Intersection {
             left: Iter::new(Some(left.inner.root()), vec![]),
             right: Iter::new(Some(right.inner.root()), vec![]),
@@ -129,7 +322,10 @@ pub struct Union<'a, 'b> {
 }
 
 impl<'a, 'b> Union<'a, 'b> {
-    fn new(left: &'a RadixSet, right: &'b RadixSet) -> Self {
+    fn new<A: NodeAllocator, B: NodeAllocator>(
+        left: &'a RadixSet<A>,
+        right: &'b RadixSet<B>,
+    ) -> Self {
         Union {
             left: Iter::new(Some(left.inner.root()), vec![]),
             left_key: None,
@@ -193,13 +389,13 @@ impl<'a, 'b> Iterator for Union<'a, 'b> {
     }
 }
 
-pub struct Difference<'a, 'b> {
+pub struct Difference<'a, 'b, B: NodeAllocator = Global> {
     left: Iter<'a, (), MapV<'a, ()>>,
-    right: &'b RadixSet,
+    right: &'b RadixSet<B>,
 }
 
-impl<'a, 'b> Difference<'a, 'b> {
-    fn new(left: &'a RadixSet, right: &'b RadixSet) -> Self {
+impl<'a, 'b, B: NodeAllocator> Difference<'a, 'b, B> {
+    fn new<A: NodeAllocator>(left: &'a RadixSet<A>, right: &'b RadixSet<B>) -> Self {
         Difference {
             left: Iter::new(Some(left.inner.root()), vec![]),
             right,
@@ -207,7 +403,7 @@ impl<'a, 'b> Difference<'a, 'b> {
     }
 }
 
-impl<'a, 'b> Iterator for Difference<'a, 'b> {
+impl<'a, 'b, B: NodeAllocator> Iterator for Difference<'a, 'b, B> {
     type Item = Box<[u8]>;
 
     fn next(&mut self) -> Option<Self::Item> {
@@ -223,21 +419,21 @@ impl<'a, 'b> Iterator for Difference<'a, 'b> {
 
 impl<K: AsRef<[u8]>, const N: usize> From<[K; N]> for RadixSet {
     fn from(items: [K; N]) -> Self {
-        let mut set = RadixSet::new();
-        for item in items {
-            set.insert(item);
-        }
-        set
+        RadixSet::from_iter(items)
     }
 }
 
-impl<K: AsRef<[u8]>> FromIterator<K> for RadixSet {
+impl<K: AsRef<[u8]>, A: NodeAllocator + Default> FromIterator<K> for RadixSet<A> {
     fn from_iter<I: IntoIterator<Item = K>>(iter: I) -> Self {
-        let mut set = RadixSet::new();
-        for item in iter {
-            set.insert(item);
+        RadixSet {
+            inner: iter.into_iter().map(|k| (k, ())).collect(),
         }
-        set
+    }
+}
+
+impl<K: AsRef<[u8]>, A: NodeAllocator> Extend<K> for RadixSet<A> {
+    fn extend<I: IntoIterator<Item = K>>(&mut self, iter: I) {
+        self.inner.extend(iter.into_iter().map(|k| (k, ())));
     }
 }
 
@@ -249,12 +445,12 @@ mod tests {
     fn test_insert_and_get() {
         let mut set = RadixSet::new();
 
-        assert_eq!(set.insert("abc;0"), true);
-        assert_eq!(set.insert("abb;0"), true);
-        assert_eq!(set.insert("ab"), true);
-        assert_eq!(set.insert("c"), true);
-        assert_eq!(set.insert("cad"), true);
-        assert_eq!(set.insert("cad"), false);
+        assert!(set.insert("abc;0"));
+        assert!(set.insert("abb;0"));
+        assert!(set.insert("ab"));
+        assert!(set.insert("c"));
+        assert!(set.insert("cad"));
+        assert!(!set.insert("cad"));
 
         assert_eq!(set.len(), 5);
 
@@ -369,10 +565,10 @@ mod tests {
         // Test iter
         assert_eq!(set.iter().count(), 4);
         assert_eq!(set.prefix_iter(&key_a).count(), 2);
-        assert_eq!(set.prefix_iter(&[0]).count(), 2);
-        assert_eq!(set.prefix_iter(&[1]).count(), 1);
-        assert_eq!(set.prefix_iter(&[2]).count(), 1);
-        assert_eq!(set.prefix_iter(&[3]).count(), 0);
+        assert_eq!(set.prefix_iter([0]).count(), 2);
+        assert_eq!(set.prefix_iter([1]).count(), 1);
+        assert_eq!(set.prefix_iter([2]).count(), 1);
+        assert_eq!(set.prefix_iter([3]).count(), 0);
         assert_eq!(set.prefix_iter(&key_d).count(), 0);
     }
 
@@ -438,6 +634,46 @@ mod tests {
         assert_eq!(it.next(), None);
     }
 
+    #[test]
+    fn test_fuzzy_search() {
+        let set = populated_set();
+
+        let found: Vec<_> = set.fuzzy_search("cab", 1).collect();
+        assert_eq!(found, [(b"ab"[..].into(), 1), (b"cad"[..].into(), 1)]);
+
+        assert_eq!(set.fuzzy_search("acd", 1).next(), None);
+        let found: Vec<_> = set
+            .fuzzy_search_with("acd", Fuzzy::new(1).with_transpositions())
+            .collect();
+        assert_eq!(found, [(b"cad"[..].into(), 1)]);
+
+        let found: Vec<_> = set
+            .fuzzy_search_with("abd", Fuzzy::new(1).with_prefix())
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (b"ab"[..].into(), 1),
+                (b"abb;0"[..].into(), 1),
+                (b"abc;0"[..].into(), 1)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_neighbors() {
+        let set = populated_set();
+
+        assert_eq!(set.floor("abc"), Some(b"abb;0"[..].into()));
+        assert_eq!(set.floor("ab"), Some(b"ab"[..].into()));
+        assert_eq!(set.lower("ab"), None);
+        assert_eq!(set.ceiling("abc"), Some(b"abc;0"[..].into()));
+        assert_eq!(set.ceiling("c"), Some(b"c"[..].into()));
+        assert_eq!(set.higher("c"), Some(b"cad"[..].into()));
+        assert_eq!(set.higher("cad"), None);
+        assert_eq!(set.lower("z"), Some(b"cad"[..].into()));
+    }
+
     #[test]
     fn test_intersection_partial() {
         // Left then right
@@ -688,8 +924,8 @@ mod tests {
         let mut right = RadixSet::new();
 
         for i in 0..100000 {
-            left.insert(&(i as u64).to_be_bytes());
-            right.insert(&(i as u64).to_be_bytes());
+            left.insert((i as u64).to_be_bytes());
+            right.insert((i as u64).to_be_bytes());
         }
 
         let diff: Vec<Box<[u8]>> = left.difference(&right).collect();
@@ -709,6 +945,36 @@ mod tests {
         assert!(it.next().is_none());
     }
 
+    #[test]
+    fn test_from_sorted_iter() {
+        let set = RadixSet::from_sorted_iter(["bar", "baz", "baz", "foo"]).unwrap();
+
+        assert_eq!(set.len(), 3);
+
+        let mut it = set.iter();
+        assert_eq!(it.next(), Some("bar".as_bytes().into()));
+        assert_eq!(it.next(), Some("baz".as_bytes().into()));
+        assert_eq!(it.next(), Some("foo".as_bytes().into()));
+        assert!(it.next().is_none());
+
+        assert_eq!(
+            RadixSet::from_sorted_iter(["foo", "bar"]).unwrap_err(),
+            RadixError::UnsortedKeys
+        );
+    }
+
+    #[test]
+    fn test_extend() {
+        let mut set = RadixSet::new();
+        set.extend(["bar", "baz"]);
+        set.extend(["foo", "bar"]);
+
+        assert_eq!(set.len(), 3);
+        assert!(set.contains("bar"));
+        assert!(set.contains("baz"));
+        assert!(set.contains("foo"));
+    }
+
     #[test]
     fn test_from_iterator() {
         let set: RadixSet = vec!["foo", "bar", "baz", "foo"].into_iter().collect();
@@ -721,4 +987,25 @@ mod tests {
         assert_eq!(it.next(), Some("foo".as_bytes().into()));
         assert!(it.next().is_none());
     }
+
+    #[test]
+    fn test_memory_limit() {
+        let mut set = RadixSet::with_memory_limit(512);
+        assert_eq!(set.memory_limit(), Some(512));
+        assert_eq!(set.try_insert("foo"), Ok(true));
+        assert_eq!(set.try_insert("foo"), Ok(false));
+
+        let keys: Vec<_> = (0..1000).map(|i| format!("key-{}", i)).collect();
+        assert_eq!(set.try_extend(&keys), Err(RadixError::CapacityExceeded));
+        assert!(set.memory_usage() <= 512);
+        assert_eq!(set.memory_usage(), set.stats().allocated_bytes);
+
+        let len = set.len();
+        assert_eq!(
+            set.try_insert("another key"),
+            Err(RadixError::CapacityExceeded)
+        );
+        assert_eq!(set.len(), len);
+        assert!(!set.contains("another key"));
+    }
 }
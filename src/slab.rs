@@ -0,0 +1,349 @@
+use alloc::collections::BTreeMap;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::alloc::Layout;
+use core::cell::UnsafeCell;
+use core::ptr::{self, NonNull};
+
+use crate::allocator::{Global, NodeAllocator};
+
+/// Size of a single slab. Slabs are aligned to their size so that the slab
+/// owning a block can be found by masking the block address.
+const SLAB_SIZE: usize = 16 * 1024;
+/// Bytes reserved at the start of each slab for its header.
+const SLAB_HEADER_SIZE: usize = 16;
+/// Alignment of every block handed out from a slab.
+const BLOCK_ALIGN: usize = 16;
+/// Blocks larger than this are allocated individually.
+const MAX_BLOCK_SIZE: usize = 4096;
+/// Marks the end of a free list.
+const NIL: u32 = u32::MAX;
+
+/// A node allocator that carves nodes out of large slabs.
+///
+/// Blocks are grouped into size classes. Each class addresses its blocks by
+/// `u32` indices and keeps freed blocks in an intrusive free list, so memory
+/// released by removals is reused by later inserts of similarly sized nodes.
+/// Growing or shrinking a node within its size class does not move it.
+///
+/// Blocks larger than 4 KiB or with an alignment above 16 bytes are
+/// allocated from the global allocator.
+///
+/// All blocks can be released at once using [`Self::reset()`], which is what
+/// makes [`crate::RadixMap::clear()`] constant time for values without drop
+/// glue.
+///
+/// ```
+/// use radixt::{RadixMap, SlabAllocator};
+///
+/// let mut map = RadixMap::new_in(SlabAllocator::new());
+/// map.insert("foo", 1);
+/// map.insert("bar", 2);
+///
+/// assert_eq!(map.get("foo"), Some(&1));
+///
+/// map.clear();
+/// assert!(map.is_empty());
+/// ```
+#[derive(Default)]
+pub struct SlabAllocator {
+    inner: UnsafeCell<Inner>,
+}
+
+// The allocator owns all of its memory.
+unsafe impl Send for SlabAllocator {}
+
+#[derive(Default)]
+struct Inner {
+    classes: Vec<SizeClass>,
+    /// Blocks that do not fit into any size class.
+    large: BTreeMap<NonNull<u8>, Layout>,
+}
+
+struct SizeClass {
+    block_size: usize,
+    blocks_per_slab: u32,
+    slabs: Vec<NonNull<u8>>,
+    /// Index of the first block that was never handed out.
+    next_block: u32,
+    /// Index of the first block in the free list.
+    free_head: u32,
+}
+
+impl SlabAllocator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the number of bytes reserved from the global allocator.
+    pub fn reserved_bytes(&self) -> usize {
+        let inner = unsafe { &*self.inner.get() };
+        inner
+            .classes
+            .iter()
+            .map(|class| class.slabs.len() * SLAB_SIZE)
+            .sum::<usize>()
+            + inner.large.values().map(|l| l.size()).sum::<usize>()
+    }
+
+    #[inline(always)]
+    #[allow(clippy::mut_from_ref)]
+    fn inner(&self) -> &mut Inner {
+        // The allocator is not `Sync` and never calls back into user code,
+        // so there is at most one reference to the inner state at a time.
+        unsafe { &mut *self.inner.get() }
+    }
+}
+
+unsafe impl NodeAllocator for SlabAllocator {
+    fn allocate(&self, layout: Layout) -> Option<NonNull<u8>> {
+        let inner = self.inner();
+        match class_idx(layout) {
+            Some(idx) => inner.class(idx).allocate(),
+            None => {
+                let ptr = Global.allocate(layout)?;
+                inner.large.insert(ptr, layout);
+                Some(ptr)
+            }
+        }
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        let inner = self.inner();
+        match class_idx(layout) {
+            Some(idx) => inner.classes[idx].deallocate(ptr),
+            None => {
+                inner.large.remove(&ptr);
+                Global.deallocate(ptr, layout);
+            }
+        }
+    }
+
+    unsafe fn reallocate(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Option<NonNull<u8>> {
+        match (class_idx(old_layout), class_idx(new_layout)) {
+            // The block is large enough already.
+            (Some(old_idx), Some(new_idx)) if old_idx == new_idx => Some(ptr),
+            _ => {
+                let new_ptr = self.allocate(new_layout)?;
+                ptr::copy_nonoverlapping(
+                    ptr.as_ptr(),
+                    new_ptr.as_ptr(),
+                    old_layout.size().min(new_layout.size()),
+                );
+                self.deallocate(ptr, old_layout);
+                Some(new_ptr)
+            }
+        }
+    }
+
+    /// Releases all blocks at once. Slabs are kept for reuse.
+    fn reset(&mut self) -> bool {
+        let inner = self.inner.get_mut();
+        for (ptr, layout) in core::mem::take(&mut inner.large) {
+            unsafe { Global.deallocate(ptr, layout) };
+        }
+        for class in inner.classes.iter_mut() {
+            class.next_block = 0;
+            class.free_head = NIL;
+        }
+        true
+    }
+}
+
+impl Drop for SlabAllocator {
+    fn drop(&mut self) {
+        self.reset();
+        for class in self.inner.get_mut().classes.iter() {
+            for &slab in class.slabs.iter() {
+                unsafe { Global.deallocate(slab, slab_layout()) };
+            }
+        }
+    }
+}
+
+impl core::fmt::Debug for SlabAllocator {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("SlabAllocator")
+            .field("reserved_bytes", &self.reserved_bytes())
+            .finish()
+    }
+}
+
+impl Inner {
+    #[inline(always)]
+    fn class(&mut self, idx: usize) -> &mut SizeClass {
+        while self.classes.len() <= idx {
+            let block_size = class_size(self.classes.len());
+            self.classes.push(SizeClass {
+                block_size,
+                blocks_per_slab: ((SLAB_SIZE - SLAB_HEADER_SIZE) / block_size) as u32,
+                slabs: vec![],
+                next_block: 0,
+                free_head: NIL,
+            });
+        }
+        &mut self.classes[idx]
+    }
+}
+
+impl SizeClass {
+    fn allocate(&mut self) -> Option<NonNull<u8>> {
+        if self.free_head != NIL {
+            let ptr = self.block_ptr(self.free_head);
+            self.free_head = unsafe { ptr::read(ptr.as_ptr() as *const u32) };
+            return Some(ptr);
+        }
+
+        if self.next_block == NIL {
+            // The index space of this class is exhausted.
+            return None;
+        }
+        let slab_idx = (self.next_block / self.blocks_per_slab) as usize;
+        if slab_idx == self.slabs.len() {
+            let slab = Global.allocate(slab_layout())?;
+            unsafe { ptr::write(slab.as_ptr() as *mut u32, slab_idx as u32) };
+            self.slabs.push(slab);
+        }
+        let ptr = self.block_ptr(self.next_block);
+        self.next_block += 1;
+        Some(ptr)
+    }
+
+    unsafe fn deallocate(&mut self, ptr: NonNull<u8>) {
+        let slab_offset = ptr.as_ptr() as usize % SLAB_SIZE;
+        let slab = ptr.as_ptr().sub(slab_offset) as *const u32;
+        let offset = (slab_offset - SLAB_HEADER_SIZE) / self.block_size;
+        let idx = ptr::read(slab) * self.blocks_per_slab + offset as u32;
+
+        ptr::write(ptr.as_ptr() as *mut u32, self.free_head);
+        self.free_head = idx;
+    }
+
+    #[inline(always)]
+    fn block_ptr(&self, idx: u32) -> NonNull<u8> {
+        let slab = self.slabs[(idx / self.blocks_per_slab) as usize];
+        let offset = SLAB_HEADER_SIZE + (idx % self.blocks_per_slab) as usize * self.block_size;
+        unsafe { NonNull::new_unchecked(slab.as_ptr().add(offset)) }
+    }
+}
+
+#[inline(always)]
+fn slab_layout() -> Layout {
+    Layout::from_size_align(SLAB_SIZE, SLAB_SIZE).unwrap()
+}
+
+/// Size classes are spaced 16 bytes apart up to 256 bytes and 64 bytes apart
+/// above that.
+#[inline(always)]
+fn class_size(idx: usize) -> usize {
+    if idx < 16 {
+        (idx + 1) * 16
+    } else {
+        256 + (idx - 15) * 64
+    }
+}
+
+#[inline(always)]
+fn class_idx(layout: Layout) -> Option<usize> {
+    if layout.align() > BLOCK_ALIGN || layout.size() > MAX_BLOCK_SIZE {
+        return None;
+    }
+    let size = layout.size().max(1);
+    if size <= 256 {
+        Some((size - 1) / 16)
+    } else {
+        Some(15 + (size - 256).div_ceil(64))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_class_idx() {
+        for size in 1..=MAX_BLOCK_SIZE {
+            let idx = class_idx(Layout::from_size_align(size, 8).unwrap()).unwrap();
+            assert!(class_size(idx) >= size);
+            assert!(idx == 0 || class_size(idx - 1) < size);
+        }
+        assert_eq!(
+            class_idx(Layout::from_size_align(MAX_BLOCK_SIZE + 1, 8).unwrap()),
+            None
+        );
+        assert_eq!(class_idx(Layout::from_size_align(8, 32).unwrap()), None);
+    }
+
+    #[test]
+    fn test_allocate_and_reuse() {
+        let alloc = SlabAllocator::new();
+        let layout = Layout::from_size_align(24, 8).unwrap();
+
+        let blocks: Vec<_> = (0..2000).map(|_| alloc.allocate(layout).unwrap()).collect();
+        for (i, block) in blocks.iter().enumerate() {
+            assert_eq!(block.as_ptr() as usize % BLOCK_ALIGN, 0);
+            unsafe { ptr::write_bytes(block.as_ptr(), i as u8, layout.size()) };
+        }
+        for (i, block) in blocks.iter().enumerate() {
+            let data = unsafe { std::slice::from_raw_parts(block.as_ptr(), layout.size()) };
+            assert!(data.iter().all(|&b| b == i as u8));
+        }
+        let reserved = alloc.reserved_bytes();
+        assert_eq!(reserved, 4 * SLAB_SIZE);
+
+        for block in blocks.iter().step_by(2) {
+            unsafe { alloc.deallocate(*block, layout) };
+        }
+        for _ in 0..1000 {
+            alloc.allocate(layout).unwrap();
+        }
+        // Freed blocks were reused.
+        assert_eq!(alloc.reserved_bytes(), reserved);
+    }
+
+    #[test]
+    fn test_reallocate() {
+        let alloc = SlabAllocator::new();
+        let small = Layout::from_size_align(20, 8).unwrap();
+        let grown = Layout::from_size_align(30, 8).unwrap();
+        let large = Layout::from_size_align(MAX_BLOCK_SIZE * 2, 8).unwrap();
+
+        let ptr = alloc.allocate(small).unwrap();
+        unsafe { ptr::write_bytes(ptr.as_ptr(), 7, small.size()) };
+
+        // Growing within the size class keeps the block in place.
+        let ptr2 = unsafe { alloc.reallocate(ptr, small, grown) }.unwrap();
+        assert_eq!(ptr, ptr2);
+
+        let ptr3 = unsafe { alloc.reallocate(ptr2, grown, large) }.unwrap();
+        let data = unsafe { std::slice::from_raw_parts(ptr3.as_ptr(), small.size()) };
+        assert!(data.iter().all(|&b| b == 7));
+        assert_eq!(alloc.reserved_bytes(), SLAB_SIZE + large.size());
+
+        unsafe { alloc.deallocate(ptr3, large) };
+        assert_eq!(alloc.reserved_bytes(), SLAB_SIZE);
+    }
+
+    #[test]
+    fn test_reset() {
+        let mut alloc = SlabAllocator::new();
+        let layout = Layout::from_size_align(100, 8).unwrap();
+        let large = Layout::from_size_align(MAX_BLOCK_SIZE * 2, 8).unwrap();
+
+        let first = alloc.allocate(layout).unwrap();
+        for _ in 0..1000 {
+            alloc.allocate(layout).unwrap();
+        }
+        alloc.allocate(large).unwrap();
+        let reserved = alloc.reserved_bytes();
+
+        assert!(alloc.reset());
+        assert_eq!(alloc.reserved_bytes(), reserved - large.size());
+        assert_eq!(alloc.allocate(layout), Some(first));
+    }
+}
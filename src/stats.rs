@@ -0,0 +1,70 @@
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+use crate::node::Node;
+
+/// Memory and shape statistics of a tree or a subtree.
+///
+/// Histograms are indexed by the measured quantity, e.g. `fanout_histogram[2]`
+/// is the number of nodes with exactly two children. Edge lengths are
+/// unbounded, so their histogram is sparse and only holds lengths that occur.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TreeStats {
+    /// Total number of nodes.
+    pub node_count: usize,
+    /// Number of nodes holding a value, i.e. the number of entries.
+    pub valued_node_count: usize,
+    /// Number of nodes without a value that have at least one child.
+    pub unvalued_internal_node_count: usize,
+    /// Number of leaves stored in their parent's children array without an
+    /// allocation of their own.
+    pub inline_leaf_count: usize,
+    /// Total number of bytes allocated for nodes. Values larger than 64 bytes
+    /// are allocated separately and are not included.
+    pub allocated_bytes: usize,
+    /// Number of nodes at each depth. The (sub)tree root is at depth 0.
+    pub depth_histogram: Vec<usize>,
+    /// Number of nodes for each key (edge) length that occurs.
+    pub edge_len_histogram: BTreeMap<usize, usize>,
+    /// Number of nodes for each number of children.
+    pub fanout_histogram: Vec<usize>,
+}
+
+impl TreeStats {
+    pub(crate) fn new<T, U>(root: Option<&Node<T, U>>) -> Self {
+        let mut stats = TreeStats::default();
+
+        let mut stack: Vec<(usize, &Node<T, U>)> = root.map(|n| (0, n)).into_iter().collect();
+        while let Some((depth, node)) = stack.pop() {
+            let children = node.children();
+            let has_value = node.value().is_some();
+
+            stats.node_count += 1;
+            stats.valued_node_count += has_value as usize;
+            stats.unvalued_internal_node_count += (!has_value && !children.is_empty()) as usize;
+            stats.inline_leaf_count += node.is_inline() as usize;
+            stats.allocated_bytes += node.allocated_size();
+
+            increment(&mut stats.depth_histogram, depth);
+            *stats
+                .edge_len_histogram
+                .entry(node.key().len())
+                .or_default() += 1;
+            increment(&mut stats.fanout_histogram, children.len());
+
+            for child in children {
+                stack.push((depth + 1, child));
+            }
+        }
+
+        stats
+    }
+}
+
+#[inline(always)]
+fn increment(histogram: &mut Vec<usize>, idx: usize) {
+    if histogram.len() <= idx {
+        histogram.resize(idx + 1, 0);
+    }
+    histogram[idx] += 1;
+}
@@ -0,0 +1,827 @@
+use alloc::boxed::Box;
+use alloc::collections::BinaryHeap;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+use core::fmt;
+use core::marker::PhantomData;
+use core::mem;
+use core::ops::{Bound, Deref, DerefMut, RangeBounds};
+
+use crate::iter::{in_range_left, in_range_right};
+use crate::node::Node;
+
+/// An aggregate of the values of a map that is cached in every node, so that
+/// queries over a subtree don't have to visit its entries.
+///
+/// `combine` must be associative. Summaries are combined in key order, so it
+/// does not need to be commutative.
+pub trait Summary<T> {
+    type Output: Copy;
+
+    /// Returns the summary of a single value.
+    fn summarize(&self, value: &T) -> Self::Output;
+
+    /// Returns the summary of the values summarized by `a` followed by those
+    /// summarized by `b`.
+    fn combine(&self, a: Self::Output, b: Self::Output) -> Self::Output;
+}
+
+/// The summary of maps that don't cache anything.
+impl<T> Summary<T> for () {
+    type Output = ();
+
+    #[inline(always)]
+    fn summarize(&self, _value: &T) {}
+
+    #[inline(always)]
+    fn combine(&self, _a: (), _b: ()) {}
+}
+
+/// Caches the highest score of the values in each subtree, where the score
+/// of a value is computed by a function. Used by
+/// [`RadixMap::top_k()`](crate::RadixMap::top_k).
+#[derive(Debug, Clone, Copy)]
+pub struct MaxScore<F>(F);
+
+impl<F> MaxScore<F> {
+    pub fn new(score: F) -> Self {
+        MaxScore(score)
+    }
+
+    /// Returns the score of a value.
+    #[inline]
+    pub fn score<T, K>(&self, value: &T) -> K
+    where
+        F: Fn(&T) -> K,
+    {
+        (self.0)(value)
+    }
+}
+
+impl<T, K: Ord + Copy, F: Fn(&T) -> K> Summary<T> for MaxScore<F> {
+    type Output = K;
+
+    #[inline]
+    fn summarize(&self, value: &T) -> K {
+        (self.0)(value)
+    }
+
+    #[inline]
+    fn combine(&self, a: K, b: K) -> K {
+        a.max(b)
+    }
+}
+
+/// Caches the number of entries in each subtree, which answers order
+/// statistics queries like [`RadixMap::rank()`](crate::RadixMap::rank)
+/// without iterating.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Count;
+
+impl<T> Summary<T> for Count {
+    type Output = usize;
+
+    #[inline(always)]
+    fn summarize(&self, _value: &T) -> usize {
+        1
+    }
+
+    #[inline(always)]
+    fn combine(&self, a: usize, b: usize) -> usize {
+        a + b
+    }
+}
+
+/// Caches two summaries side by side, so that a map can answer the queries
+/// of both. For example a map with `(Count, MaxScore<F>)` supports
+/// [`RadixMap::rank()`](crate::RadixMap::rank) as well as
+/// [`RadixMap::top_k()`](crate::RadixMap::top_k). Nest tuples to combine more
+/// than two.
+impl<T, A: Summary<T>, B: Summary<T>> Summary<T> for (A, B) {
+    type Output = (A::Output, B::Output);
+
+    #[inline]
+    fn summarize(&self, value: &T) -> Self::Output {
+        (self.0.summarize(value), self.1.summarize(value))
+    }
+
+    #[inline]
+    fn combine(&self, a: Self::Output, b: Self::Output) -> Self::Output {
+        (self.0.combine(a.0, b.0), self.1.combine(a.1, b.1))
+    }
+}
+
+/// A summary that contains the summary `P`, either by being `P` or by being
+/// a tuple with a part that contains `P`. Queries that need a particular
+/// summary, like [`RadixMap::rank()`](crate::RadixMap::rank) which needs
+/// [`Count`], are available on every map whose summary contains it.
+///
+/// `I` is one of [`Here`], [`First`] and [`Second`] and locates `P` within
+/// the summary. It is inferred, and ambiguous if `P` occurs more than once.
+pub trait Contains<T, P: Summary<T>, I>: Summary<T> {
+    /// Returns the contained summary.
+    fn part(&self) -> &P;
+
+    /// Returns the contained summary's part of an output of this summary.
+    fn project(output: Self::Output) -> P::Output;
+}
+
+/// Locates a summary that is the summary itself, see [`Contains`].
+#[derive(Debug)]
+pub struct Here;
+
+/// Locates a summary in the first part of a tuple, see [`Contains`].
+#[derive(Debug)]
+pub struct First<I>(PhantomData<I>);
+
+/// Locates a summary in the second part of a tuple, see [`Contains`].
+#[derive(Debug)]
+pub struct Second<I>(PhantomData<I>);
+
+impl<T, P: Summary<T>> Contains<T, P, Here> for P {
+    #[inline(always)]
+    fn part(&self) -> &P {
+        self
+    }
+
+    #[inline(always)]
+    fn project(output: P::Output) -> P::Output {
+        output
+    }
+}
+
+impl<T, P: Summary<T>, I, A: Contains<T, P, I>, B: Summary<T>> Contains<T, P, First<I>> for (A, B) {
+    #[inline(always)]
+    fn part(&self) -> &P {
+        self.0.part()
+    }
+
+    #[inline(always)]
+    fn project(output: Self::Output) -> P::Output {
+        A::project(output.0)
+    }
+}
+
+impl<T, P: Summary<T>, I, A: Summary<T>, B: Contains<T, P, I>> Contains<T, P, Second<I>>
+    for (A, B)
+{
+    #[inline(always)]
+    fn part(&self) -> &P {
+        self.1.part()
+    }
+
+    #[inline(always)]
+    fn project(output: Self::Output) -> P::Output {
+        B::project(output.1)
+    }
+}
+
+/// Returns the contained summary `P` of a subtree.
+#[inline]
+pub(crate) fn part_summary<T, P: Summary<T>, I, S: Contains<T, P, I>>(
+    node: &Node<T, S::Output>,
+    summary: &S,
+) -> Option<P::Output> {
+    node.summary(summary).map(S::project)
+}
+
+#[inline]
+fn combine<T, S: Summary<T>>(
+    summary: &S,
+    a: Option<S::Output>,
+    b: Option<S::Output>,
+) -> Option<S::Output> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(summary.combine(a, b)),
+        (a, b) => a.or(b),
+    }
+}
+
+/// Returns the summary of the values whose keys are within `bounds`.
+///
+/// Subtrees are visited in key order. A subtree whose keys are all within
+/// the bounds contributes its cached summary and one whose keys are all
+/// outside is skipped, so only subtrees on the paths to the bounds are
+/// expanded.
+pub(crate) fn range_summary<T, S: Summary<T>, K: AsRef<[u8]>, B: RangeBounds<K>>(
+    root: &Node<T, S::Output>,
+    summary: &S,
+    bounds: &B,
+) -> Option<S::Output> {
+    let mut result = None;
+    let mut stack = vec![(0, root)];
+    let mut prefix = vec![];
+    while let Some((prefix_len, node)) = stack.pop() {
+        prefix.truncate(prefix_len);
+        prefix.extend_from_slice(node.key());
+
+        // The prefix is the smallest key of the subtree and every key below
+        // it is smaller than any larger key the prefix is not a prefix of.
+        let below =
+            |bound: &K| prefix.as_slice() < bound.as_ref() && !bound.as_ref().starts_with(&prefix);
+        let before_start = match bounds.start_bound() {
+            Bound::Included(start) | Bound::Excluded(start) => below(start),
+            Bound::Unbounded => false,
+        };
+        if before_start || !in_range_right(bounds.end_bound(), &prefix) {
+            continue;
+        }
+        let before_end = match bounds.end_bound() {
+            Bound::Included(end) | Bound::Excluded(end) => below(end),
+            Bound::Unbounded => true,
+        };
+        if before_end && in_range_left(bounds.start_bound(), &prefix) {
+            result = combine(summary, result, node.summary(summary));
+            continue;
+        }
+
+        if let Some(value) = node.value() {
+            if in_range_left(bounds.start_bound(), &prefix) {
+                result = combine(summary, result, Some(summary.summarize(value)));
+            }
+        }
+        for child in node.children().iter().rev() {
+            stack.push((prefix.len(), child));
+        }
+    }
+    result
+}
+
+/// A mutable reference to a value of a map, returned by
+/// [`RadixMap::get_mut_guarded()`](crate::RadixMap::get_mut_guarded).
+/// Recomputes the cached summaries on the path to the value's key when
+/// dropped.
+pub struct ValueMut<'a, T, S: Summary<T> = ()> {
+    value: *mut T,
+    root: *mut Node<T, S::Output>,
+    summary: &'a S,
+    /// Key of the value, empty if the map doesn't cache summaries.
+    key: Box<[u8]>,
+    _marker: PhantomData<&'a mut T>,
+}
+
+impl<'a, T, S: Summary<T>> ValueMut<'a, T, S> {
+    pub(crate) fn new(
+        root: &'a mut Node<T, S::Output>,
+        summary: &'a S,
+        value: *mut T,
+        key: &[u8],
+    ) -> Self {
+        let key = match mem::size_of::<S::Output>() {
+            0 => Box::default(),
+            _ => key.into(),
+        };
+        ValueMut {
+            value,
+            root,
+            summary,
+            key,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T, S: Summary<T>> Deref for ValueMut<'_, T, S> {
+    type Target = T;
+
+    #[inline(always)]
+    fn deref(&self) -> &T {
+        unsafe { &*self.value }
+    }
+}
+
+impl<T, S: Summary<T>> DerefMut for ValueMut<'_, T, S> {
+    #[inline(always)]
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.value }
+    }
+}
+
+impl<T: fmt::Debug, S: Summary<T>> fmt::Debug for ValueMut<'_, T, S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+impl<T, S: Summary<T>> Drop for ValueMut<'_, T, S> {
+    fn drop(&mut self) {
+        if mem::size_of::<S::Output>() != 0 {
+            // SAFETY: The guard borrows the map mutably, so the tree is
+            // unchanged since the value was looked up.
+            unsafe { (*self.root).update_summaries(self.summary, &self.key) };
+        }
+    }
+}
+
+/// A subtree or a value waiting in the queue of [`TopK`].
+enum Entry<'a, T, U> {
+    Node(&'a Node<T, U>),
+    Value(&'a T),
+}
+
+/// An entry of the queue of [`TopK`] with the highest score below it and
+/// its prefix, ordered by descending score and then by ascending prefix.
+struct Ranked<'a, T, U, K> {
+    score: K,
+    prefix: Box<[u8]>,
+    entry: Entry<'a, T, U>,
+}
+
+impl<T, U, K: Ord> PartialEq for Ranked<'_, T, U, K> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<T, U, K: Ord> Eq for Ranked<'_, T, U, K> {}
+
+impl<T, U, K: Ord> PartialOrd for Ranked<'_, T, U, K> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T, U, K: Ord> Ord for Ranked<'_, T, U, K> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.score
+            .cmp(&other.score)
+            .then_with(|| other.prefix.cmp(&self.prefix))
+    }
+}
+
+/// An iterator over the `k` entries with the highest scores below a prefix,
+/// ordered by descending score and then by key.
+///
+/// Subtrees are expanded best first by their cached maximum score. The
+/// prefix of a subtree sorts before all of its keys, so an entry is yielded
+/// as soon as it is the best one left and low-scoring subtrees are never
+/// visited.
+pub struct TopK<'a, T, S: Summary<T>, F, K> {
+    summary: &'a S,
+    score: &'a MaxScore<F>,
+    /// Returns the highest score of a subtree from its summary.
+    max_score: fn(S::Output) -> K,
+    remaining: usize,
+    queue: BinaryHeap<Ranked<'a, T, S::Output, K>>,
+}
+
+impl<'a, T, S: Summary<T>, F: Fn(&T) -> K, K: Ord + Copy> TopK<'a, T, S, F, K> {
+    /// Creates an iterator over the entries below `node`, whose key is
+    /// `prefix`.
+    pub(crate) fn new<I>(
+        node: Option<&'a Node<T, S::Output>>,
+        prefix: Vec<u8>,
+        summary: &'a S,
+        k: usize,
+    ) -> Self
+    where
+        S: Contains<T, MaxScore<F>, I>,
+    {
+        let mut queue = BinaryHeap::new();
+        if let Some(node) = node.filter(|_| k > 0) {
+            if let Some(max) = part_summary(node, summary) {
+                queue.push(Ranked {
+                    score: max,
+                    prefix: prefix.into(),
+                    entry: Entry::Node(node),
+                });
+            }
+        }
+        TopK {
+            summary,
+            score: summary.part(),
+            max_score: S::project,
+            remaining: k,
+            queue,
+        }
+    }
+}
+
+impl<'a, T, S: Summary<T>, F: Fn(&T) -> K, K: Ord + Copy> Iterator for TopK<'a, T, S, F, K> {
+    type Item = (Box<[u8]>, &'a T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        while let Some(ranked) = self.queue.pop() {
+            let node = match ranked.entry {
+                Entry::Value(value) => {
+                    self.remaining -= 1;
+                    return Some((ranked.prefix, value));
+                }
+                Entry::Node(node) => node,
+            };
+
+            if let Some(value) = node.value() {
+                self.queue.push(Ranked {
+                    score: self.score.score(value),
+                    prefix: ranked.prefix.clone(),
+                    entry: Entry::Value(value),
+                });
+            }
+            for child in node.children() {
+                let Some(max) = child.summary(self.summary).map(self.max_score) else {
+                    continue;
+                };
+                let mut prefix = Vec::with_capacity(ranked.prefix.len() + child.key().len());
+                prefix.extend_from_slice(&ranked.prefix);
+                prefix.extend_from_slice(child.key());
+                self.queue.push(Ranked {
+                    score: max,
+                    prefix: prefix.into(),
+                    entry: Entry::Node(child),
+                });
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use std::collections::BTreeMap;
+    use std::ops::Bound;
+
+    use crate::map::RadixMap;
+
+    /// Hashes the values in key order, so that combining summaries out of
+    /// order or skipping a value changes the result.
+    #[derive(Default)]
+    struct OrderedHash;
+
+    impl Summary<u32> for OrderedHash {
+        /// The hash and the power of the base it shifts a following hash by.
+        type Output = (u64, u64);
+
+        fn summarize(&self, value: &u32) -> (u64, u64) {
+            (*value as u64, 1_000_003)
+        }
+
+        fn combine(&self, a: (u64, u64), b: (u64, u64)) -> (u64, u64) {
+            (
+                a.0.wrapping_mul(b.1).wrapping_add(b.0),
+                a.1.wrapping_mul(b.1),
+            )
+        }
+    }
+
+    fn expected_summary<'a>(values: impl Iterator<Item = &'a u32>) -> Option<(u64, u64)> {
+        values
+            .map(|value| OrderedHash.summarize(value))
+            .reduce(|a, b| OrderedHash.combine(a, b))
+    }
+
+    fn random_keys(seed: u64, count: usize) -> Vec<Vec<u8>> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        (0..count)
+            .map(|_| {
+                let len = rng.gen_range(0..7);
+                (0..len).map(|_| b"abcd"[rng.gen_range(0..4)]).collect()
+            })
+            .collect()
+    }
+
+    /// Asserts that the cached summary of every node matches the scores of
+    /// the values below it and returns the highest one.
+    fn check_summaries<T, F: Fn(&T) -> u32>(
+        node: &Node<T, u32>,
+        score: &MaxScore<F>,
+    ) -> Option<u32> {
+        let expected = node
+            .children()
+            .iter()
+            .filter_map(|child| check_summaries(child, score))
+            .chain(node.value().map(|value| score.score(value)))
+            .max();
+        assert_eq!(node.summary(score), expected);
+        expected
+    }
+
+    fn expected_top_k(
+        entries: &BTreeMap<Vec<u8>, u32>,
+        prefix: &[u8],
+        k: usize,
+    ) -> Vec<(Box<[u8]>, u32)> {
+        let mut expected: Vec<_> = entries
+            .iter()
+            .filter(|(key, _)| key.starts_with(prefix))
+            .map(|(key, value)| (key.as_slice().into(), *value))
+            .collect();
+        expected.sort_by(|a: &(Box<[u8]>, u32), b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        expected.truncate(k);
+        expected
+    }
+
+    fn top_k<F: Fn(&u32) -> u32>(
+        map: &RadixMap<u32, crate::Global, MaxScore<F>>,
+        prefix: &[u8],
+        k: usize,
+    ) -> Vec<(Box<[u8]>, u32)> {
+        map.top_k(prefix, k)
+            .map(|(key, value)| (key, *value))
+            .collect()
+    }
+
+    #[test]
+    fn test_top_k() {
+        let mut map = RadixMap::with_score(|value: &u32| *value);
+        for (key, value) in [
+            ("apple", 5),
+            ("app", 9),
+            ("application", 7),
+            ("apply", 7),
+            ("banana", 10),
+            ("band", 1),
+            ("", 3),
+        ] {
+            map.insert(key, value);
+        }
+
+        assert_eq!(
+            top_k(&map, b"", 3),
+            [
+                (b"banana"[..].into(), 10),
+                (b"app"[..].into(), 9),
+                (b"application"[..].into(), 7)
+            ]
+        );
+        assert_eq!(
+            top_k(&map, b"appl", 10),
+            [
+                (b"application"[..].into(), 7),
+                (b"apply"[..].into(), 7),
+                (b"apple"[..].into(), 5)
+            ]
+        );
+        assert_eq!(top_k(&map, b"ban", 1), [(b"banana"[..].into(), 10)]);
+        assert_eq!(top_k(&map, b"ba", 0), []);
+        assert_eq!(top_k(&map, b"c", 5), []);
+
+        map.remove("banana");
+        assert_eq!(top_k(&map, b"ban", 1), [(b"band"[..].into(), 1)]);
+        check_summaries(map.root(), map.summary());
+    }
+
+    #[test]
+    fn test_top_k_matches_brute_force() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut entries = BTreeMap::new();
+        let mut map = RadixMap::with_score(|value: &u32| value % 1000);
+
+        for i in 0..4000 {
+            let len = rng.gen_range(0..7);
+            let key: Vec<u8> = (0..len).map(|_| b"abcd"[rng.gen_range(0..4)]).collect();
+            if i % 3 == 2 {
+                assert_eq!(map.remove(&key), entries.remove(&key));
+            } else {
+                let value = rng.gen();
+                assert_eq!(map.insert(&key, value), entries.insert(key, value));
+            }
+            if i % 500 == 0 {
+                check_summaries(map.root(), map.summary());
+            }
+        }
+        check_summaries(map.root(), map.summary());
+
+        let scored: BTreeMap<Vec<u8>, u32> =
+            entries.iter().map(|(k, v)| (k.clone(), v % 1000)).collect();
+        for prefix in [&b""[..], b"a", b"ab", b"abc", b"dd", b"abcdab"] {
+            for k in [1, 5, 50, 5000] {
+                let found: Vec<_> = top_k(&map, prefix, k)
+                    .into_iter()
+                    .map(|(key, value)| (key, value % 1000))
+                    .collect();
+                assert_eq!(found, expected_top_k(&scored, prefix, k));
+            }
+        }
+    }
+
+    #[test]
+    fn test_top_k_sorted_build() {
+        let mut map = RadixMap::with_score(|value: &u32| *value);
+        map.extend((0..1000u32).map(|i| (format!("{:04}", i), i * 7919 % 1000)));
+        check_summaries(map.root(), map.summary());
+
+        let entries: BTreeMap<Vec<u8>, u32> = map.iter().map(|(k, v)| (k.into(), *v)).collect();
+        assert_eq!(top_k(&map, b"01", 4), expected_top_k(&entries, b"01", 4));
+        assert_eq!(top_k(&map, b"", 4), expected_top_k(&entries, b"", 4));
+
+        // Summaries follow nodes that are moved by splits and merges.
+        map.insert("0", 5000);
+        map.insert("012", 4000);
+        assert_eq!(
+            top_k(&map, b"", 2),
+            [(b"0"[..].into(), 5000), (b"012"[..].into(), 4000)]
+        );
+        map.remove("0");
+        map.remove("012");
+        check_summaries(map.root(), map.summary());
+        assert_eq!(top_k(&map, b"", 4), expected_top_k(&entries, b"", 4));
+    }
+
+    #[test]
+    fn test_summary_prefix_and_range() {
+        let keys = random_keys(1, 3000);
+        let mut entries = BTreeMap::new();
+        let mut map: RadixMap<u32, crate::Global, OrderedHash> = RadixMap::default();
+        for (i, key) in keys.iter().enumerate() {
+            if i % 4 == 3 {
+                assert_eq!(map.remove(key), entries.remove(key));
+            } else {
+                assert_eq!(
+                    map.insert(key, i as u32),
+                    entries.insert(key.clone(), i as u32)
+                );
+            }
+        }
+
+        let bounds: Vec<&[u8]> = vec![
+            b"", b"a", b"ab", b"abc", b"abca", b"b", b"cd", b"dddddd", b"e",
+        ];
+        for prefix in &bounds {
+            assert_eq!(
+                map.summary_prefix(prefix),
+                expected_summary(
+                    entries
+                        .iter()
+                        .filter(|(key, _)| key.starts_with(prefix))
+                        .map(|(_, value)| value)
+                ),
+                "{:?}",
+                prefix
+            );
+        }
+        let bound = |i: usize, key: &'static [u8]| match i {
+            0 => Bound::Included(key),
+            1 => Bound::Excluded(key),
+            _ => Bound::Unbounded,
+        };
+        for &start in &bounds {
+            for &end in &bounds {
+                for i in 0..3 {
+                    for j in 0..3 {
+                        let range = (bound(i, start), bound(j, end));
+                        if start > end {
+                            continue;
+                        }
+                        assert_eq!(
+                            map.summary_range::<&[u8], _>(range),
+                            expected_summary(
+                                entries
+                                    .iter()
+                                    .filter(|(key, _)| range.contains(&key.as_slice()))
+                                    .map(|(_, value)| value)
+                            ),
+                            "{:?}",
+                            range
+                        );
+                    }
+                }
+            }
+        }
+
+        map.clear();
+        assert_eq!(map.summary_prefix(""), None);
+        assert_eq!(map.summary_range::<&[u8], _>(..), None);
+    }
+
+    #[test]
+    fn test_get_mut_updates_summaries() {
+        let mut map = RadixMap::with_summary(OrderedHash);
+        map.extend(random_keys(2, 500).into_iter().map(|key| (key, 1)));
+        let keys: Vec<Box<[u8]>> = map.keys().collect();
+        for (i, key) in keys.iter().enumerate() {
+            *map.get_mut_guarded(key).unwrap() = i as u32;
+            if let Some(mut value) = map.get_mut_guarded(key) {
+                *value += 1;
+            }
+        }
+        assert!(map.get_mut_guarded("x").is_none());
+
+        assert_eq!(
+            map.summary_prefix(""),
+            expected_summary((1..=keys.len() as u32).collect::<Vec<_>>().iter())
+        );
+        for prefix in [&b"a"[..], b"bc", b"ddd"] {
+            assert_eq!(
+                map.summary_prefix(prefix),
+                expected_summary(map.prefix_values(prefix)),
+            );
+        }
+    }
+
+    #[test]
+    fn test_order_statistics() {
+        let keys = random_keys(3, 3000);
+        let mut entries = BTreeMap::new();
+        let mut map = RadixMap::with_summary(Count);
+        for (i, key) in keys.iter().enumerate() {
+            if i % 3 == 2 {
+                assert_eq!(map.remove(key), entries.remove(key));
+            } else {
+                assert_eq!(map.insert(key, i), entries.insert(key.clone(), i));
+            }
+        }
+        let sorted: Vec<(&Vec<u8>, &usize)> = entries.iter().collect();
+
+        for (index, (key, value)) in sorted.iter().enumerate() {
+            assert_eq!(map.rank(key), index);
+            assert_eq!(map.select(index), Some((key.as_slice().into(), *value)));
+        }
+        assert_eq!(map.select(sorted.len()), None);
+        assert_eq!(map.rank("e"), sorted.len());
+        assert_eq!(map.rank("abcz"), entries.range(..b"abcz".to_vec()).count());
+
+        for index in [0, 1, sorted.len() / 2, sorted.len() - 1, sorted.len()] {
+            let found: Vec<_> = map.iter_from_index(index).map(|(key, _)| key).collect();
+            let expected: Vec<Box<[u8]>> = sorted[index..]
+                .iter()
+                .map(|(key, _)| key.as_slice().into())
+                .collect();
+            assert_eq!(found, expected);
+        }
+
+        for prefix in [&b""[..], b"a", b"abc", b"dd", b"e"] {
+            assert_eq!(
+                map.count_prefix(prefix),
+                entries.keys().filter(|key| key.starts_with(prefix)).count()
+            );
+        }
+        assert_eq!(
+            map.count_range::<&[u8], _, _>(&b"ab"[..]..=&b"c"[..]),
+            entries.range(b"ab".to_vec()..=b"c".to_vec()).count()
+        );
+        assert_eq!(map.count_range::<&[u8], _, _>(..), map.len());
+    }
+
+    #[test]
+    fn test_order_statistics_empty_key() {
+        let mut map: RadixMap<(), crate::Global, Count> = ["", "a", "ab", "b"]
+            .into_iter()
+            .map(|key| (key, ()))
+            .collect();
+        assert_eq!(map.select(0), Some((b""[..].into(), &())));
+        assert_eq!(map.rank(""), 0);
+        assert_eq!(map.rank("ab"), 2);
+        assert_eq!(map.iter_from_index(2).count(), 2);
+
+        // Counts follow the merge of "a" into "ab".
+        map.remove("a");
+        assert_eq!(map.rank("b"), 2);
+        assert_eq!(map.select(1), Some((b"ab"[..].into(), &())));
+        map.clear();
+        assert_eq!(map.select(0), None);
+        assert_eq!(map.iter_from_index(0).count(), 0);
+    }
+
+    #[test]
+    fn test_combined_summaries() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut entries = BTreeMap::new();
+        let mut map = RadixMap::with_summary((Count, MaxScore::new(|value: &u32| value % 1000)));
+        for (i, key) in random_keys(4, 3000).into_iter().enumerate() {
+            if i % 3 == 2 {
+                assert_eq!(map.remove(&key), entries.remove(&key));
+            } else {
+                let value = rng.gen();
+                assert_eq!(map.insert(&key, value), entries.insert(key, value));
+            }
+        }
+        *map.get_mut_guarded(b"ab").unwrap() = 999;
+        entries.insert(b"ab".to_vec(), 999);
+
+        let sorted: Vec<_> = entries.keys().collect();
+        for (index, key) in sorted.iter().enumerate().step_by(7) {
+            assert_eq!(map.rank(key), index);
+            assert_eq!(map.select(index).unwrap().0, key.as_slice().into());
+        }
+        let scored: BTreeMap<Vec<u8>, u32> =
+            entries.iter().map(|(k, v)| (k.clone(), v % 1000)).collect();
+        for prefix in [&b""[..], b"a", b"ab", b"dd"] {
+            let count = entries.keys().filter(|key| key.starts_with(prefix)).count();
+            assert_eq!(map.count_prefix(prefix), count);
+            let found: Vec<_> = map
+                .top_k(prefix, 10)
+                .map(|(key, value)| (key, value % 1000))
+                .collect();
+            assert_eq!(found, expected_top_k(&scored, prefix, 10));
+        }
+
+        // Both parts of a summary are combined in order.
+        let (count, max) = map.summary_prefix(b"ab").unwrap();
+        assert_eq!(count, map.count_prefix(b"ab"));
+        assert_eq!(max, 999);
+
+        // The parts can be nested and come in any order.
+        let mut map = RadixMap::with_summary((MaxScore::new(|value: &u32| *value), ((), Count)));
+        map.extend([("a", 3), ("b", 1), ("c", 2)]);
+        assert_eq!(map.rank("c"), 2);
+        assert_eq!(map.top_k("", 1).next(), Some((b"a"[..].into(), &3)));
+    }
+}